@@ -0,0 +1,77 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use rand::{self, Rng};
+
+const NONCE_SIZE: usize = 32;
+
+// Each side of a connection signs the other side's nonce with a different prefix, so that a digest
+// sent by one side can never be replayed as the digest of the other side.
+const CLIENT_ROLE: &'static [u8] = b"rabble-client";
+const SERVER_ROLE: &'static [u8] = b"rabble-server";
+
+/// The handshake state of a cluster connection.
+///
+/// The accepting server sends a `Hello` containing a random nonce. The connecting client proves
+/// that it knows the cluster cookie by replying with an `Auth` containing an HMAC of the server's
+/// nonce, along with a nonce of its own. The server verifies the digest and replies with an
+/// `AuthOk` containing an HMAC of the client's nonce, which the client verifies in turn. Members
+/// are only exchanged once both sides are authenticated.
+///
+/// The `Hello` also carries the range of protocol versions supported by the server. The client
+/// picks the highest version supported by both nodes and sends it in its `Auth`. Either side closes
+/// the connection if there is no such version.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AuthState {
+    /// A client waiting for the server's `Hello`
+    AwaitingHello,
+
+    /// A server that sent a `Hello` with the given nonce and is waiting for the client's `Auth`
+    AwaitingAuth(Vec<u8>),
+
+    /// A client that sent an `Auth` with the given nonce and is waiting for the server's `AuthOk`
+    AwaitingAuthOk(Vec<u8>),
+
+    Authenticated
+}
+
+/// Generate a random nonce for a handshake
+pub fn nonce() -> Vec<u8> {
+    let mut nonce = vec![0; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+/// The digest sent by a client in response to the server's nonce
+pub fn client_digest(cookie: &str, server_nonce: &[u8]) -> Vec<u8> {
+    digest(cookie, CLIENT_ROLE, server_nonce)
+}
+
+/// The digest sent by a server in response to the client's nonce
+pub fn server_digest(cookie: &str, client_nonce: &[u8]) -> Vec<u8> {
+    digest(cookie, SERVER_ROLE, client_nonce)
+}
+
+pub fn verify_client_digest(cookie: &str, server_nonce: &[u8], digest: &[u8]) -> bool {
+    verify(cookie, CLIENT_ROLE, server_nonce, digest)
+}
+
+pub fn verify_server_digest(cookie: &str, client_nonce: &[u8], digest: &[u8]) -> bool {
+    verify(cookie, SERVER_ROLE, client_nonce, digest)
+}
+
+fn mac(cookie: &str, role: &[u8], nonce: &[u8]) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_varkey(cookie.as_bytes()).unwrap();
+    mac.input(role);
+    mac.input(nonce);
+    mac
+}
+
+fn digest(cookie: &str, role: &[u8], nonce: &[u8]) -> Vec<u8> {
+    mac(cookie, role, nonce).result().code().to_vec()
+}
+
+fn verify(cookie: &str, role: &[u8], nonce: &[u8], digest: &[u8]) -> bool {
+    // Constant time comparison
+    mac(cookie, role, nonce).verify(digest).is_ok()
+}
@@ -0,0 +1,79 @@
+use std::collections::{HashMap, HashSet};
+use node_id::NodeId;
+use pid::Pid;
+
+/// The subscription tables of a node's pub/sub broker.
+///
+/// Brokers only track subscribers on their own node. Each broker tells its peers which topics
+/// have local subscribers, so that a publish is forwarded at most once to each interested node,
+/// where the peer's broker delivers it to its own subscribers.
+pub struct Broker {
+    // The local subscribers of each topic
+    subscribers: HashMap<String, HashSet<Pid>>,
+    // The topics with subscribers on each connected peer
+    peers: HashMap<NodeId, HashSet<String>>
+}
+
+impl Broker {
+    pub fn new() -> Broker {
+        Broker {
+            subscribers: HashMap::new(),
+            peers: HashMap::new()
+        }
+    }
+
+    pub fn subscribe(&mut self, topic: String, pid: Pid) {
+        self.subscribers.entry(topic).or_insert_with(HashSet::new).insert(pid);
+    }
+
+    pub fn unsubscribe(&mut self, topic: &str, pid: &Pid) {
+        let empty = match self.subscribers.get_mut(topic) {
+            Some(pids) => {
+                pids.remove(pid);
+                pids.is_empty()
+            },
+            None => false
+        };
+        if empty {
+            self.subscribers.remove(topic);
+        }
+    }
+
+    /// Remove a subscriber from all topics
+    pub fn remove(&mut self, pid: &Pid) {
+        for pids in self.subscribers.values_mut() {
+            pids.remove(pid);
+        }
+        self.subscribers.retain(|_, pids| !pids.is_empty());
+    }
+
+    pub fn is_subscribed(&self, pid: &Pid) -> bool {
+        self.subscribers.values().any(|pids| pids.contains(pid))
+    }
+
+    /// The topics with local subscribers
+    pub fn topics(&self) -> HashSet<String> {
+        self.subscribers.keys().cloned().collect()
+    }
+
+    pub fn subscribers(&self, topic: &str) -> Vec<Pid> {
+        self.subscribers.get(topic).map_or(Vec::new(), |pids| pids.iter().cloned().collect())
+    }
+
+    /// Replace the topics with subscribers on a peer
+    pub fn set_peer_topics(&mut self, node: NodeId, topics: HashSet<String>) {
+        self.peers.insert(node, topics);
+    }
+
+    pub fn remove_peer(&mut self, node: &NodeId) {
+        self.peers.remove(node);
+    }
+
+    /// The peers with subscribers for a topic
+    pub fn interested_peers(&self, topic: &str) -> Vec<NodeId> {
+        self.peers.iter()
+            .filter(|&(_, topics)| topics.contains(topic))
+            .map(|(node, _)| node.clone())
+            .collect()
+    }
+}
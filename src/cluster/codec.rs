@@ -0,0 +1,61 @@
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use msgpack::{Serializer, Deserializer};
+use bincode;
+use serde_cbor;
+use errors::*;
+use super::ExternalMsg;
+
+/// The wire format of messages sent between cluster servers.
+///
+/// Each message is encoded into a single frame, so codecs don't need to delimit messages. All nodes
+/// in a cluster must use the same codec.
+pub trait Codec<T>: Send {
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>>;
+    fn decode(&self, frame: &[u8]) -> Result<ExternalMsg<T>>;
+}
+
+/// Encode messages with MessagePack. This is the default codec.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MsgpackCodec;
+
+impl<'de, T: Serialize + Deserialize<'de>> Codec<T> for MsgpackCodec {
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        try!(msg.serialize(&mut Serializer::new(&mut encoded)));
+        Ok(encoded)
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<ExternalMsg<T>> {
+        let mut decoder = Deserializer::new(frame);
+        Ok(try!(Deserialize::deserialize(&mut decoder)))
+    }
+}
+
+/// Encode messages with bincode, a compact format that is fast to encode and decode
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>> {
+        bincode::serialize(msg).chain_err(|| "Failed to encode message with bincode")
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<ExternalMsg<T>> {
+        bincode::deserialize(frame).chain_err(|| "Failed to decode bincode frame")
+    }
+}
+
+/// Encode messages with CBOR
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for CborCodec {
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>> {
+        serde_cbor::to_vec(msg).chain_err(|| "Failed to encode message with CBOR")
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<ExternalMsg<T>> {
+        serde_cbor::from_slice(frame).chain_err(|| "Failed to decode CBOR frame")
+    }
+}
@@ -0,0 +1,18 @@
+use node_id::NodeId;
+
+/// A change in cluster membership or connectivity, pushed to processes and services that sent a
+/// `Msg::SubscribeClusterEvents` to the cluster server.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ClusterEvent {
+    /// A node was added to the cluster membership
+    MemberAdded(NodeId),
+
+    /// A node was removed from the cluster membership
+    MemberRemoved(NodeId),
+
+    /// A connection to a node was established
+    NodeConnected(NodeId),
+
+    /// An established connection to a node was lost
+    NodeDisconnected(NodeId)
+}
@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use time::SteadyTime;
+
+/// A phi accrual failure detector, as described in "The φ Accrual Failure Detector" by Hayashibara
+/// et al.
+///
+/// Rather than declaring a peer failed after a fixed timeout, the detector keeps a sliding window
+/// of heartbeat inter-arrival times and computes `phi`, a measure of suspicion that grows the
+/// longer a heartbeat is overdue relative to the observed distribution. A `phi` of 1 means there
+/// is roughly a 10% chance that a heartbeat that hasn't arrived yet still will, 2 means 1%, 3 means
+/// 0.1%, etc.
+pub struct FailureDetector {
+    intervals: VecDeque<f64>,
+    window_size: usize,
+    min_std_dev_ms: f64,
+    acceptable_pause_ms: f64,
+    last_heartbeat: SteadyTime
+}
+
+impl FailureDetector {
+    /// Create a detector for a peer that is expected to send heartbeats every
+    /// `expected_interval_ms`.
+    ///
+    /// The window is seeded with the expected interval so that phi is meaningful before enough
+    /// heartbeats have been received.
+    pub fn new(now: SteadyTime,
+               expected_interval_ms: f64,
+               window_size: usize,
+               min_std_dev_ms: f64,
+               acceptable_pause_ms: f64) -> FailureDetector
+    {
+        let mut intervals = VecDeque::with_capacity(window_size.max(2));
+        let std_dev = expected_interval_ms / 4.0;
+        intervals.push_back(expected_interval_ms - std_dev);
+        intervals.push_back(expected_interval_ms + std_dev);
+        FailureDetector {
+            intervals: intervals,
+            window_size: window_size.max(2),
+            min_std_dev_ms: min_std_dev_ms,
+            acceptable_pause_ms: acceptable_pause_ms,
+            last_heartbeat: now
+        }
+    }
+
+    pub fn heartbeat(&mut self, now: SteadyTime) {
+        let interval = (now - self.last_heartbeat).num_milliseconds() as f64;
+        if self.intervals.len() == self.window_size {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back(interval);
+        self.last_heartbeat = now;
+    }
+
+    /// The current suspicion level of the peer
+    pub fn phi(&self, now: SteadyTime) -> f64 {
+        let elapsed = (now - self.last_heartbeat).num_milliseconds() as f64;
+        let n = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<f64>() / n;
+        let variance = self.intervals.iter().map(|i| (i - mean) * (i - mean)).sum::<f64>() / n;
+        let std_dev = variance.sqrt().max(self.min_std_dev_ms);
+        phi(elapsed, mean + self.acceptable_pause_ms, std_dev)
+    }
+}
+
+/// Compute phi using a logistic approximation of the cumulative normal distribution
+fn phi(elapsed: f64, mean: f64, std_dev: f64) -> f64 {
+    let y = (elapsed - mean) / std_dev;
+    let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+    if elapsed > mean {
+        -(e / (1.0 + e)).log10()
+    } else {
+        -(1.0 - 1.0 / (1.0 + e)).log10()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, SteadyTime};
+    use super::FailureDetector;
+
+    const INTERVAL_MS: i64 = 1000;
+    const THRESHOLD: f64 = 8.0;
+
+    fn detector(now: SteadyTime, window_size: usize) -> FailureDetector {
+        FailureDetector::new(now, INTERVAL_MS as f64, window_size, 100.0, 0.0)
+    }
+
+    #[test]
+    fn steady_heartbeats_keep_phi_low() {
+        let mut now = SteadyTime::now();
+        let mut detector = detector(now, 100);
+        for _ in 0..50 {
+            now = now + Duration::milliseconds(INTERVAL_MS);
+            assert!(detector.phi(now) < 1.0);
+            detector.heartbeat(now);
+        }
+        assert!(detector.phi(now + Duration::milliseconds(INTERVAL_MS / 2)) < 1.0);
+    }
+
+    #[test]
+    fn missed_heartbeats_raise_phi_past_the_threshold() {
+        let mut now = SteadyTime::now();
+        let mut detector = detector(now, 100);
+        for _ in 0..50 {
+            now = now + Duration::milliseconds(INTERVAL_MS);
+            detector.heartbeat(now);
+        }
+        let one_missed = detector.phi(now + Duration::milliseconds(2 * INTERVAL_MS));
+        let two_missed = detector.phi(now + Duration::milliseconds(3 * INTERVAL_MS));
+        assert!(one_missed > THRESHOLD);
+        assert!(two_missed > one_missed);
+    }
+
+    #[test]
+    fn window_is_bounded() {
+        let mut now = SteadyTime::now();
+        let mut detector = detector(now, 10);
+        for _ in 0..50 {
+            now = now + Duration::milliseconds(INTERVAL_MS);
+            detector.heartbeat(now);
+        }
+        assert_eq!(detector.intervals.len(), 10);
+
+        // Once the window only contains slow heartbeats, a slow heartbeat is no longer suspicious
+        for _ in 0..10 {
+            now = now + Duration::milliseconds(3 * INTERVAL_MS);
+            detector.heartbeat(now);
+        }
+        assert_eq!(detector.intervals.len(), 10);
+        assert!(detector.intervals.iter().all(|&i| i as i64 == 3 * INTERVAL_MS));
+        assert!(detector.phi(now + Duration::milliseconds(3 * INTERVAL_MS)) < THRESHOLD);
+    }
+}
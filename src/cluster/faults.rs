@@ -0,0 +1,120 @@
+use std::collections::{HashMap, VecDeque};
+use time::{SteadyTime, Duration};
+use node_id::NodeId;
+
+/// A fault injected into the traffic between the local node and a peer.
+///
+/// Faults are only available with the `fault_injection` feature, and are intended for testing
+/// partitions and slow or misbehaving networks against real nodes. They apply to every frame
+/// exchanged with the peer, including the handshake, so a connection to a dropped peer is never
+/// established. Frames received on an accepted connection can only be matched to the peer once its
+/// `Auth` identifies it, so only `Drop` applies to that `Auth`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Drop all frames sent to or received from the peer, partitioning it from the local node
+    Drop,
+
+    /// Delay all frames sent to the peer by the given number of milliseconds. Frames are still
+    /// written in the order they were sent, even if the delay changes.
+    Delay(u64),
+
+    /// Corrupt all frames received from the peer so that they fail to decode
+    Corrupt
+}
+
+/// The faults injected by a cluster server, along with the frames it is holding back and the ids
+/// of the connections they are for
+pub struct Faults {
+    faults: HashMap<NodeId, Fault>,
+    delayed: VecDeque<(SteadyTime, NodeId, usize, Vec<u8>)>
+}
+
+impl Faults {
+    pub fn new() -> Faults {
+        Faults {
+            faults: HashMap::new(),
+            delayed: VecDeque::new()
+        }
+    }
+
+    /// Inject a fault into the traffic with a node, or remove it if `fault` is `None`.
+    ///
+    /// Return the delayed frames for the node that must be written right away, in order, because
+    /// the node is no longer delayed. They must be written before any new frames, so that frames
+    /// sent after the delay was removed don't overtake them. Delayed frames are discarded if the
+    /// new fault is `Drop`.
+    pub fn set(&mut self, node: NodeId, fault: Option<Fault>) -> Vec<(usize, Vec<u8>)> {
+        let released = match fault {
+            Some(Fault::Delay(_)) => Vec::new(),
+            _ => self.release(&node)
+        };
+        match fault {
+            Some(fault) => {
+                self.faults.insert(node, fault);
+            },
+            None => {
+                self.faults.remove(&node);
+            }
+        }
+        if fault == Some(Fault::Drop) {
+            return Vec::new();
+        }
+        released
+    }
+
+    /// Remove and return all delayed frames for a node, in the order they were sent
+    fn release(&mut self, node: &NodeId) -> Vec<(usize, Vec<u8>)> {
+        let (released, delayed): (VecDeque<_>, VecDeque<_>) =
+            self.delayed.drain(..).partition(|&(_, ref n, _, _)| n == node);
+        self.delayed = delayed;
+        released.into_iter().map(|(_, _, id, frame)| (id, frame)).collect()
+    }
+
+    /// Discard the frames delayed for a connection that was closed
+    pub fn discard(&mut self, id: usize) {
+        self.delayed.retain(|&(_, _, delayed_id, _)| delayed_id != id);
+    }
+
+    /// Return the frame to write now to the connection with the given id to a node, if any
+    pub fn outgoing(&mut self, id: usize, node: &NodeId, frame: Vec<u8>) -> Option<Vec<u8>> {
+        match self.faults.get(node) {
+            Some(&Fault::Drop) => None,
+            Some(&Fault::Delay(ms)) => {
+                // Never schedule a frame before one sent earlier, even if the delay was shortened
+                let mut due = SteadyTime::now() + Duration::milliseconds(ms as i64);
+                if let Some(&(last, ..)) = self.delayed.iter().rev().find(|&&(_, ref n, ..)| {
+                    n == node
+                }) {
+                    due = due.max(last);
+                }
+                self.delayed.push_back((due, node.clone(), id, frame));
+                None
+            },
+            _ => Some(frame)
+        }
+    }
+
+    /// Whether all frames received from a node are dropped
+    pub fn drops(&self, node: &NodeId) -> bool {
+        self.faults.get(node) == Some(&Fault::Drop)
+    }
+
+    /// Return the frame to decode after it was received from a node, if any
+    pub fn incoming(&self, node: &NodeId, frame: Vec<u8>) -> Option<Vec<u8>> {
+        match self.faults.get(node) {
+            Some(&Fault::Drop) => None,
+            Some(&Fault::Corrupt) => Some(frame.into_iter().map(|byte| !byte).collect()),
+            _ => Some(frame)
+        }
+    }
+
+    /// Remove and return all delayed frames that are due to be written, in the order they were
+    /// originally sent
+    pub fn due(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let now = SteadyTime::now();
+        let (due, delayed): (VecDeque<_>, VecDeque<_>) =
+            self.delayed.drain(..).partition(|&(time, ..)| time <= now);
+        self.delayed = delayed;
+        due.into_iter().map(|(_, _, id, frame)| (id, frame)).collect()
+    }
+}
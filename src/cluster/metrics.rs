@@ -9,5 +9,13 @@ metrics!(ClusterMetrics {
     received_remote_envelopes: u64,
     status_requests: u64,
     accepted_connections: u64,
-    connection_attempts: u64
+    connection_attempts: u64,
+    auth_failures: u64,
+    version_mismatches: u64,
+    blacklisted_peers: u64,
+    suspected_peers: u64,
+    undeliverable_envelopes: u64,
+    publishes: u64,
+    forwarded_publishes: u64,
+    forwarded_group_envelopes: u64
 });
@@ -1,4 +1,14 @@
 mod server;
+mod auth;
+mod broker;
+mod tls;
+mod transport;
+mod codec;
+mod failure_detector;
+mod event;
+mod ring;
+#[cfg(feature = "fault_injection")]
+mod faults;
 mod status;
 mod msg;
 mod metrics;
@@ -7,6 +17,21 @@ pub use self::server::ClusterServer;
 pub use self::status::ClusterStatus;
 pub use self::msg::{
     ClusterMsg,
-    ExternalMsg
+    ExternalMsg,
+    PROTOCOL_VERSION
 };
 pub use self::metrics::ClusterMetrics;
+pub use self::tls::TlsConfig;
+pub use self::codec::{Codec, MsgpackCodec, BincodeCodec, CborCodec};
+pub use self::transport::{
+    Transport,
+    Listener,
+    Connection,
+    TcpTransport,
+    UnixTransport,
+    MemoryTransport
+};
+pub use self::event::ClusterEvent;
+pub use self::ring::ClusterRing;
+#[cfg(feature = "fault_injection")]
+pub use self::faults::Fault;
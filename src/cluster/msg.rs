@@ -1,8 +1,15 @@
+use std::sync::mpsc::Sender;
+use std::collections::HashSet;
 use amy::Notification;
 use orset::{ORSet, Delta};
 use node_id::NodeId;
 use envelope::Envelope;
 use correlation_id::CorrelationId;
+use node::ShutdownReport;
+use pid::Pid;
+use registry::Registry;
+#[cfg(feature = "fault_injection")]
+use super::Fault;
 
 /// Messages sent to the Cluster Server
 pub enum ClusterMsg<T> {
@@ -10,16 +17,74 @@ pub enum ClusterMsg<T> {
     Join(NodeId),
     Leave(NodeId),
     Envelope(Envelope<T>),
+    // Sent by the executor to forward an envelope for an entity to the node owning the entity
+    Forwarded(Envelope<T>),
     GetStatus(CorrelationId),
-    Shutdown
+    RegisterName(String, Pid),
+    UnregisterName(String),
+    // Sent by the executor whenever the set of groups with local processes changes
+    LocalGroups(HashSet<String>),
+    #[cfg(feature = "fault_injection")]
+    SetFault(NodeId, Option<Fault>),
+    // Sent by the executor once all processes have stopped. The cluster server adds what it
+    // dropped to the report and sends it back before exiting.
+    Shutdown(ShutdownReport, Sender<ShutdownReport>)
 }
 
+/// The latest version of the protocol spoken between cluster servers
+///
+/// Version 2 added the global name registry with `Names` and `NamesDelta`.
+/// Version 3 added pub/sub with `Topics` and `Publish`.
+/// Version 4 added process groups with `Groups`.
+/// Version 5 added `Forwarded` to mark entity envelopes forwarded to the owner of the entity.
+pub const PROTOCOL_VERSION: u32 = 5;
+
 /// A message sent between nodes in Rabble.
 ///
+/// The handshake messages are used to negotiate the protocol version, so their encoding must not
+/// change between versions. The encoding of messages from older versions must not change either,
+/// so that nodes can still talk to peers that negotiated an older version. New messages are only
+/// ever added at the end.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExternalMsg<T> {
+   // Handshake messages. See `AuthState` for details.
+   Hello {from: NodeId, nonce: Vec<u8>, min_version: u32, max_version: u32},
+   Auth {from: NodeId, nonce: Vec<u8>, digest: Vec<u8>, version: u32},
+   AuthOk {digest: Vec<u8>},
+
    Members {from: NodeId, orset: ORSet<NodeId>},
    Ping,
    Envelope(Envelope<T>),
-   Delta(Delta<NodeId>)
+   Delta(Delta<NodeId>),
+   NamesDelta(Delta<(String, Pid)>),
+
+   // The topics with subscribers on the sending node, replacing any previously sent topics
+   Topics(Vec<String>),
+
+   // A `Msg::Publish` forwarded by the sending node's broker, to be delivered to local subscribers
+   Publish(Envelope<T>),
+
+   // The process groups with members on the sending node, replacing any previously sent groups
+   Groups(Vec<String>),
+
+   // The sending node's replica of the name registry, sent right after `Members`
+   Names(Registry),
+
+   // An envelope for an entity forwarded to this node because it owns the entity on the sending
+   // node's ring. It is delivered or dead lettered, but never forwarded again.
+   Forwarded(Envelope<T>)
+}
+
+impl<T> ExternalMsg<T> {
+    /// The protocol version that introduced this message. It must only be sent to peers that
+    /// negotiated this version or a later one.
+    pub fn version(&self) -> u32 {
+        match *self {
+            ExternalMsg::Names(_) | ExternalMsg::NamesDelta(_) => 2,
+            ExternalMsg::Topics(_) | ExternalMsg::Publish(_) => 3,
+            ExternalMsg::Groups(_) => 4,
+            ExternalMsg::Forwarded(_) => 5,
+            _ => 1
+        }
+    }
 }
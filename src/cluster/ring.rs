@@ -0,0 +1,62 @@
+use std::sync::{Arc, RwLock};
+use std::hash::Hash;
+use node_id::NodeId;
+use hash_ring::HashRing;
+
+/// A consistent hash ring of the cluster members, used to decide which nodes own a key
+///
+/// The cluster server adds and removes nodes as the membership changes, so every node with the
+/// same view of the membership maps a key to the same owners. Handles are cheap to clone and can
+/// be read from any thread. Processes that place data by key can send a `Msg::SubscribeRingChanges`
+/// to the cluster server to receive a `Msg::RingChanged` whenever ownership may have moved.
+#[derive(Debug, Clone)]
+pub struct ClusterRing {
+    ring: Arc<RwLock<HashRing<NodeId>>>,
+    replication_factor: usize
+}
+
+impl ClusterRing {
+    /// Create a ring containing only `node`, where each node has `vnodes` points on the ring and
+    /// each key is owned by `replication_factor` nodes
+    pub fn new(node: &NodeId, vnodes: usize, replication_factor: usize) -> ClusterRing {
+        let mut ring = HashRing::new(vnodes);
+        ring.add(node.clone());
+        ClusterRing {
+            ring: Arc::new(RwLock::new(ring)),
+            replication_factor: replication_factor.max(1)
+        }
+    }
+
+    pub fn replication_factor(&self) -> usize {
+        self.replication_factor
+    }
+
+    /// Return the nodes that own `key`, primary first. There are fewer owners than the
+    /// replication factor if the cluster has fewer members.
+    pub fn owners<K: Hash>(&self, key: &K) -> Vec<NodeId> {
+        self.ring.read().unwrap().owners(key, self.replication_factor)
+    }
+
+    /// Return the primary owner of `key`
+    pub fn owner<K: Hash>(&self, key: &K) -> Option<NodeId> {
+        self.ring.read().unwrap().owner(key).cloned()
+    }
+
+    /// Return the nodes on the ring, sorted
+    pub fn members(&self) -> Vec<NodeId> {
+        let mut members = self.ring.read().unwrap().members();
+        members.sort();
+        members
+    }
+
+    /// Update the ring with the given membership changes. This is called by the cluster server.
+    pub fn update(&self, added: &[NodeId], removed: &[NodeId]) {
+        let mut ring = self.ring.write().unwrap();
+        for node in added {
+            ring.add(node.clone());
+        }
+        for node in removed {
+            ring.remove(node);
+        }
+    }
+}
@@ -1,54 +1,66 @@
-use std::sync::mpsc::{self, Sender, Receiver};
-use std::collections::{HashMap, HashSet};
-use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender, SyncSender, Receiver};
+use std::mem;
+use std::thread;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
-use libc::EINPROGRESS;
-use net2::{TcpBuilder, TcpStreamExt};
 use serde::{Serialize, Deserialize};
-use msgpack::{Serializer, Deserializer};
 use slog;
-use amy::{Registrar, Notification, Event, FrameReader, FrameWriter};
+use amy::{Registrar, Notification, Event};
 use members::Members;
+use registry::Registry;
 use node_id::NodeId;
-use msg::Msg;
+use msg::{Msg, DownReason, UndeliverableReason, DeadLetterReason};
 use executor::ExecutorMsg;
 use timer_wheel::TimerWheel;
 use envelope::Envelope;
 use orset::{ORSet, Delta};
+use time::{SteadyTime, Duration};
 use pid::Pid;
 use correlation_id::CorrelationId;
+use config::RabbleConfig;
+use node::ShutdownReport;
 use errors::*;
 use metrics::Metrics;
-use super::{ClusterStatus, ClusterMsg, ExternalMsg, ClusterMetrics};
-
-// TODO: This is totally arbitrary right now and should probably be user configurable
-const MAX_FRAME_SIZE: u32 = 100*1024*1024; // 100 MB
-const TICK_TIME: usize = 1000; // milliseconds
-const REQUEST_TIMEOUT: usize = 5000; // milliseconds
-
-// This tick allows process specific timers to fire
-const EXECUTOR_TICK_TIME: usize = 100; // milliseconds
+use super::{ClusterStatus, ClusterMsg, ExternalMsg, ClusterMetrics, ClusterEvent};
+use super::PROTOCOL_VERSION;
+use super::auth::{self, AuthState};
+use super::transport::{Transport, Listener, Connection};
+use super::codec::Codec;
+use super::failure_detector::FailureDetector;
+use super::broker::Broker;
+use super::ring::ClusterRing;
+#[cfg(feature = "fault_injection")]
+use super::faults::Faults;
 
 struct Conn {
-    sock: TcpStream,
+    sock: Box<Connection>,
     node: Option<NodeId>,
+    // The node that authenticated on the other end of the connection. Unlike `node`, this is known
+    // on the accepting side before the connection is established.
+    peer: Option<NodeId>,
     is_client: bool,
+    auth: AuthState,
+    // The protocol version negotiated during the handshake
+    version: u32,
     members_sent: bool,
     timer_wheel_index: usize,
-    reader: FrameReader,
-    writer: FrameWriter
+    // Only established connections are monitored by a failure detector. Unestablished connections
+    // time out via the timer wheel.
+    detector: Option<FailureDetector>
 }
 
 impl Conn {
-    pub fn new(sock: TcpStream, node: Option<NodeId>, is_client: bool) -> Conn {
+    pub fn new(sock: Box<Connection>, node: Option<NodeId>, is_client: bool) -> Conn {
         Conn {
             sock: sock,
+            peer: node.clone(),
             node: node,
             is_client: is_client,
+            auth: AuthState::AwaitingHello,
+            version: 0,
             members_sent: false,
             timer_wheel_index: 0, // Initialize with a fake value
-            reader: FrameReader::new(MAX_FRAME_SIZE),
-            writer: FrameWriter::new(),
+            detector: None
         }
     }
 }
@@ -59,78 +71,200 @@ pub struct ClusterServer<T> {
     pid: Pid,
     node: NodeId,
     rx: Receiver<ClusterMsg<T>>,
-    executor_tx: Sender<ExecutorMsg<T>>,
+    executor_tx: SyncSender<ExecutorMsg<T>>,
+    config: RabbleConfig,
+    transport: Box<Transport>,
+    codec: Box<Codec<T>>,
     executor_timer_id: usize,
     timer_id: usize,
     timer_wheel: TimerWheel<usize>,
-    listener: TcpListener,
+    listener: Box<Listener>,
     listener_id: usize,
     members: Members,
+    // The members as of the last published membership event
+    published_members: HashSet<NodeId>,
+    subscribers: HashSet<Pid>,
+    ring: ClusterRing,
+    ring_subscribers: HashSet<Pid>,
+    names: Registry,
+    broker_pid: Pid,
+    broker: Broker,
+    // The topics with local subscribers last sent to peers
+    published_topics: HashSet<String>,
+    // The process groups with local members, as last sent by the executor
+    local_groups: HashSet<String>,
+    // The process groups with members on each established peer
+    peer_groups: HashMap<NodeId, HashSet<String>>,
+    // The resolution of the name registry last sent to the executor
+    published_names: HashMap<String, Pid>,
+    // Envelopes waiting for a connection to a member node to be established, along with whether
+    // they were forwarded to the node by the executor
+    pending: HashMap<NodeId, VecDeque<(SteadyTime, Envelope<T>, bool)>>,
     connections: HashMap<usize, Conn>,
     established: HashMap<NodeId, usize>,
+    // Peers that connections are refused to and from until the given time
+    blacklist: HashMap<NodeId, SteadyTime>,
+    // The most recent errors, bounded by `config.error_log_size`
+    error_log: VecDeque<String>,
     registrar: Registrar,
+    #[cfg(feature = "fault_injection")]
+    faults: Faults,
     logger: slog::Logger,
     metrics: ClusterMetrics
 }
 
 impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     pub fn new(node: NodeId,
+               config: RabbleConfig,
+               mut transport: Box<Transport>,
+               codec: Box<Codec<T>>,
                rx: Receiver<ClusterMsg<T>>,
-               executor_tx: Sender<ExecutorMsg<T>>,
+               executor_tx: SyncSender<ExecutorMsg<T>>,
                registrar: Registrar,
-               logger: slog::Logger) -> ClusterServer<T> {
+               ring: ClusterRing,
+               logger: slog::Logger) -> Result<ClusterServer<T>> {
         let pid = Pid {
             group: Some("rabble".to_string()),
             name: "cluster_server".to_string(),
             node: node.clone()
         };
-        let listener = TcpListener::bind(&node.addr[..]).unwrap();
-        listener.set_nonblocking(true).unwrap();
-        ClusterServer {
+        let listener = try!(transport.listen(&node.addr));
+        Ok(ClusterServer {
             pid: pid,
             node: node.clone(),
             rx: rx,
             executor_tx: executor_tx,
             executor_timer_id: 0,
             timer_id: 0,
-            timer_wheel: TimerWheel::new(REQUEST_TIMEOUT / TICK_TIME),
+            timer_wheel: TimerWheel::new(config.request_timeout_ticks()),
+            config: config,
+            transport: transport,
+            codec: codec,
             listener: listener,
             listener_id: 0,
-            members: Members::new(node),
+            members: Members::new(node.clone()),
+            names: Registry::new(&node),
+            broker_pid: Pid::broker(&node),
+            broker: Broker::new(),
+            published_topics: HashSet::new(),
+            local_groups: HashSet::new(),
+            peer_groups: HashMap::new(),
+            published_names: HashMap::new(),
+            published_members: vec![node].into_iter().collect(),
+            subscribers: HashSet::new(),
+            ring: ring,
+            ring_subscribers: HashSet::new(),
+            pending: HashMap::new(),
             connections: HashMap::new(),
             established: HashMap::new(),
+            blacklist: HashMap::new(),
+            error_log: VecDeque::new(),
             registrar: registrar,
+            #[cfg(feature = "fault_injection")]
+            faults: Faults::new(),
             logger: logger.new(o!("component" => "cluster_server")),
             metrics: ClusterMetrics::new()
-        }
+        })
     }
 
     pub fn run(mut self) {
         info!(self.logger, "Starting");
-        self.timer_id = self.registrar.set_interval(TICK_TIME).unwrap();
-        self.executor_timer_id = self.registrar.set_interval(EXECUTOR_TICK_TIME).unwrap();
-        self.listener_id = self.registrar.register(&self.listener, Event::Read).unwrap();
+        if self.config.cookie.is_empty() {
+            warn!(self.logger, "No cookie configured. Any node can join the cluster.");
+        }
+        self.timer_id = self.registrar.set_interval(self.config.tick_time_ms).unwrap();
+        self.executor_timer_id =
+            self.registrar.set_interval(self.config.executor_tick_time_ms).unwrap();
+        self.listener_id = self.listener.register(&self.registrar).unwrap();
         while let Ok(msg) = self.rx.recv() {
             if let Err(e) = self.handle_cluster_msg(msg) {
-                self.metrics.errors += 1;
-                for id in e.kind().get_ids() {
-                    self.close(id)
-                }
-                match *e.kind() {
-                    ErrorKind::EncodeError(..) | ErrorKind::DecodeError(..) |
-                    ErrorKind::RegistrarError(..) | ErrorKind::SendError(..) => {
-                        error!(self.logger, e.to_string());
-                        break;
-                    }
+                if !self.handle_error(&e) {
+                    break;
+                }
+            }
+        }
+    }
 
-                    ErrorKind::Shutdown(..) => {
-                        info!(self.logger, e.to_string());
-                        break;
-                    },
+    /// Close the connections involved in an error, so that a failure of a single peer doesn't
+    /// affect any others.
+    ///
+    /// Returns false if the cluster server must stop, which is only the case when shutting down or
+    /// when the executor is gone.
+    fn handle_error(&mut self, e: &Error) -> bool {
+        match *e.kind() {
+            ErrorKind::BroadcastError(ref errors) |
+            ErrorKind::PollNotificationErrors(ref errors) => {
+                let mut keep_running = true;
+                for e in errors {
+                    keep_running &= self.handle_error(e);
+                }
+                return keep_running;
+            },
+            ErrorKind::Shutdown(..) => {
+                info!(self.logger, e.to_string());
+                return false;
+            },
+            ErrorKind::SendError(..) => {
+                error!(self.logger, e.to_string());
+                return false;
+            },
+            _ => ()
+        }
+
+        self.metrics.errors += 1;
+        warn!(self.logger, e.to_string());
+        self.log_error(e);
 
-                    _ => warn!(self.logger, e.to_string())
+        // Peers that send garbage or whose connections can't be written or polled are likely to
+        // keep failing, so refuse to talk to them for a while. A peer that fails before it has
+        // authenticated is unknown, so its connection is only closed. It isn't blacklisted by
+        // address, since that would also refuse other nodes on the same host.
+        let blacklist = match *e.kind() {
+            ErrorKind::EncodeError(Some(_), _) | ErrorKind::DecodeError(..) |
+            ErrorKind::RegistrarError(Some(_), _) => true,
+            _ => false
+        };
+        for id in e.kind().get_ids() {
+            if blacklist {
+                if let Some(node) = self.connections.get(&id).and_then(|conn| conn.peer.clone()) {
+                    self.blacklist(node);
                 }
             }
+            self.close(id);
+        }
+        true
+    }
+
+    fn log_error(&mut self, e: &Error) {
+        if self.config.error_log_size == 0 {
+            return;
+        }
+        if self.error_log.len() == self.config.error_log_size {
+            self.error_log.pop_front();
+        }
+        self.error_log.push_back(e.to_string());
+    }
+
+    fn blacklist(&mut self, node: NodeId) {
+        warn!(self.logger, "Blacklisting peer";
+              "peer" => node.to_string(), "timeout_ms" => self.config.blacklist_timeout_ms);
+        self.metrics.blacklisted_peers += 1;
+        let timeout = Duration::milliseconds(self.config.blacklist_timeout_ms as i64);
+        self.blacklist.insert(node, SteadyTime::now() + timeout);
+    }
+
+    fn is_blacklisted(&self, node: &NodeId) -> bool {
+        self.blacklist.contains_key(node)
+    }
+
+    fn expire_blacklist(&mut self) {
+        let now = SteadyTime::now();
+        let expired: Vec<NodeId> =
+            self.blacklist.iter().filter(|&(_, until)| *until <= now).map(|(n, _)| n.clone())
+                                 .collect();
+        for node in expired {
+            info!(self.logger, "Peer no longer blacklisted"; "peer" => node.to_string());
+            self.blacklist.remove(&node);
         }
     }
 
@@ -150,18 +284,53 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
             },
             ClusterMsg::Envelope(envelope) => {
                 self.metrics.received_local_envelopes += 1;
-                // Only metric requests are directly sent to the cluster server
                 if envelope.to == self.pid {
-                    self.send_metrics(envelope);
+                    self.handle_envelope(envelope);
                     return Ok(());
                 }
-                self.send_remote(envelope)
+                if envelope.to == self.broker_pid {
+                    return self.handle_broker_envelope(envelope);
+                }
+                if envelope.to.is_cluster_group_members() {
+                    return self.send_to_group(envelope);
+                }
+                self.send_remote(envelope, false)
+            },
+            ClusterMsg::Forwarded(envelope) => {
+                self.metrics.received_local_envelopes += 1;
+                self.send_remote(envelope, true)
+            },
+            ClusterMsg::LocalGroups(groups) => {
+                debug!(self.logger, "Broadcasting groups"; "groups" => format!("{:?}", groups));
+                let msg = ExternalMsg::Groups::<T>(groups.iter().cloned().collect());
+                self.local_groups = groups;
+                self.broadcast(&msg)
+            },
+            ClusterMsg::RegisterName(name, pid) => {
+                let deltas = self.names.register(name, pid);
+                self.names_changed(deltas)
+            },
+            ClusterMsg::UnregisterName(name) => {
+                let deltas = self.names.unregister(&name);
+                self.names_changed(deltas)
             },
             ClusterMsg::GetStatus(correlation_id) => {
                 self.metrics.status_requests += 1;
                 self.get_status(correlation_id)
             },
-            ClusterMsg::Shutdown => Err(ErrorKind::Shutdown(self.pid.clone()).into())
+            #[cfg(feature = "fault_injection")]
+            ClusterMsg::SetFault(node, fault) => {
+                info!(self.logger, "Set fault";
+                      "peer" => node.to_string(), "fault" => format!("{:?}", fault));
+                for (id, frame) in self.faults.set(node, fault) {
+                    try!(self.write(id, Some(frame)));
+                }
+                Ok(())
+            },
+            ClusterMsg::Shutdown(report, reply) => {
+                self.shutdown(report, reply);
+                Err(ErrorKind::Shutdown(self.pid.clone()).into())
+            }
         }
     }
 
@@ -169,7 +338,10 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         let status = ClusterStatus {
             members: self.members.all(),
             established: self.established.keys().cloned().collect(),
-            num_connections: self.connections.len()
+            num_connections: self.connections.len(),
+            suspicion: self.suspicion(),
+            blacklisted: self.blacklist.keys().cloned().collect(),
+            errors: self.error_log.iter().cloned().collect()
         };
         let envelope = Envelope {
             to: correlation_id.pid.clone(),
@@ -187,21 +359,158 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(())
     }
 
-    fn send_remote(&mut self, envelope: Envelope<T>) -> Result<()> {
-        if let Some(id) = self.established.get(&envelope.to.node).cloned() {
+    /// Send an envelope to a remote node. Envelopes for entities forwarded by the executor are sent
+    /// as `ExternalMsg::Forwarded`, so that the peer doesn't forward them again.
+    ///
+    /// Envelopes for member nodes that aren't connected yet are queued until the connection is
+    /// established. Envelopes that can't be queued are returned to their sender as undeliverable.
+    fn send_remote(&mut self, envelope: Envelope<T>, forwarded: bool) -> Result<()> {
+        if let Some(&id) = self.established.get(&envelope.to.node) {
             trace!(self.logger, "send remote"; "to" => envelope.to.to_string());
-            let mut encoded = Vec::new();
             let node = envelope.to.node.clone();
-            try!(ExternalMsg::Envelope(envelope).serialize(&mut Serializer::new(&mut encoded))
-                .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node))));
-            try!(self.write(id, Some(encoded)));
+            // Peers that negotiated a version without `Forwarded` route the envelope as if it was
+            // sent to them directly
+            let version = self.connections.get(&id).map_or(0, |conn| conn.version);
+            let msg = if forwarded && version >= 5 {
+                ExternalMsg::Forwarded(envelope)
+            } else {
+                ExternalMsg::Envelope(envelope)
+            };
+            return self.send_to_node(&node, &msg);
+        }
+        if !self.members.all().contains(&envelope.to.node) {
+            self.undeliverable(envelope, UndeliverableReason::NotAMember);
+            return Ok(());
+        }
+        let max = self.config.pending_queue_size;
+        let full = {
+            let queue = self.pending.entry(envelope.to.node.clone()).or_insert_with(VecDeque::new);
+            queue.len() >= max
+        };
+        if full {
+            self.undeliverable(envelope, UndeliverableReason::QueueFull);
+        } else {
+            trace!(self.logger, "queue remote"; "to" => envelope.to.to_string());
+            let queue = self.pending.get_mut(&envelope.to.node).unwrap();
+            queue.push_back((SteadyTime::now(), envelope, forwarded));
+        }
+        Ok(())
+    }
+
+    /// Send a message over the established connection to a node, if there is one and the node
+    /// supports the message
+    fn send_to_node(&mut self, node: &NodeId, msg: &ExternalMsg<T>) -> Result<()> {
+        let id = match self.established.get(node) {
+            Some(&id) => id,
+            None => return Ok(())
+        };
+        if self.connections.get(&id).map_or(true, |conn| conn.version < msg.version()) {
+            return Ok(());
+        }
+        let encoded = try!(self.codec.encode(msg)
+            .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node.clone()))));
+        #[cfg(feature = "fault_injection")]
+        let encoded = match self.faults.outgoing(id, node, encoded) {
+            Some(encoded) => encoded,
+            None => return Ok(())
+        };
+        self.write(id, Some(encoded))
+    }
+
+    /// Forward an envelope for a cluster wide group to every peer with members in the group. Each
+    /// peer receives a single copy addressed to the group members on that node.
+    ///
+    /// The executor has already delivered the envelope to the local members.
+    fn send_to_group(&mut self, envelope: Envelope<T>) -> Result<()> {
+        let group = envelope.to.name.clone();
+        let peers: Vec<NodeId> = self.peer_groups.iter()
+            .filter(|&(_, groups)| groups.contains(&group))
+            .map(|(node, _)| node.clone())
+            .collect();
+        let mut errors = Vec::new();
+        for node in peers {
+            let mut envelope = envelope.clone();
+            envelope.to = Pid::group_members(&group, &node);
+            self.metrics.forwarded_group_envelopes += 1;
+            if let Err(e) = self.send_to_node(&node, &ExternalMsg::Envelope(envelope)) {
+                errors.push(e);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ErrorKind::BroadcastError(errors).into());
+        }
+        Ok(())
+    }
+
+    /// Send all envelopes queued for a node that just became connected
+    fn flush_pending(&mut self, node: &NodeId) -> Result<()> {
+        if !self.established.contains_key(node) {
+            return Ok(());
+        }
+        if let Some(queue) = self.pending.remove(node) {
+            debug!(self.logger, "Flushing pending envelopes";
+                   "peer" => node.to_string(), "count" => queue.len());
+            for (_, envelope, forwarded) in queue {
+                try!(self.send_remote(envelope, forwarded));
+            }
         }
         Ok(())
     }
 
+    /// Return envelopes that have been queued for too long to their senders
+    fn expire_pending(&mut self) {
+        let timeout = Duration::milliseconds(self.config.pending_timeout_ms as i64);
+        let deadline = SteadyTime::now() - timeout;
+        let mut expired = Vec::new();
+        for queue in self.pending.values_mut() {
+            while queue.front().map_or(false, |&(queued_at, ..)| queued_at < deadline) {
+                expired.push(queue.pop_front().unwrap().1);
+            }
+        }
+        self.pending.retain(|_, queue| !queue.is_empty());
+        for envelope in expired {
+            self.undeliverable(envelope, UndeliverableReason::Expired);
+        }
+    }
+
+    /// Notify the sender of an envelope that it could not be delivered
+    ///
+    /// A monitor or link request for a pid on a node that can't be reached is answered with a
+    /// `Down` or `Exit` instead, so that the executor of the watcher forgets the watch. Requests to
+    /// remove a watch are dropped.
+    fn undeliverable(&mut self, envelope: Envelope<T>, reason: UndeliverableReason) {
+        self.metrics.undeliverable_envelopes += 1;
+        debug!(self.logger, "Undeliverable envelope";
+               "to" => envelope.to.to_string(), "reason" => format!("{:?}", reason));
+        let to_executor = envelope.to == Pid::executor(&envelope.to.node);
+        let msg = match envelope.msg {
+            // Never bounce a bounce
+            Msg::Undeliverable(_) => return,
+            Msg::Monitor(ref pid) if to_executor => {
+                Msg::Down {pid: pid.clone(), reason: DownReason::NodeDown}
+            },
+            Msg::Link(ref pid) if to_executor => {
+                Msg::Exit {pid: pid.clone(), reason: DownReason::NodeDown}
+            },
+            // The watch is gone along with the connection
+            Msg::Demonitor(_) | Msg::Unlink(_) if to_executor => return,
+            _ => Msg::Undeliverable(reason)
+        };
+        self.send_local(Envelope::new(envelope.from.clone(),
+                                      envelope.to.clone(),
+                                      msg,
+                                      envelope.correlation_id.clone()));
+        if reason == UndeliverableReason::NotAMember {
+            let msg = ExecutorMsg::DeadLetter(envelope, DeadLetterReason::UnknownNode);
+            let _ = self.executor_tx.send(msg);
+        }
+    }
+
     fn handle_poll_notifications(&mut self, notifications: Vec<Notification>) -> Result<()> {
         trace!(self.logger, "handle_poll_notification"; "num_notifications" => notifications.len());
         let mut errors = Vec::new();
+        #[cfg(feature = "fault_injection")]
+        errors.extend(self.write_delayed());
         for n in notifications {
             let result = match n.id {
                 id if id == self.listener_id => self.accept_connection(),
@@ -220,6 +529,28 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(())
     }
 
+    /// Write the frames held back by `Fault::Delay` that are now due
+    #[cfg(feature = "fault_injection")]
+    fn write_delayed(&mut self) -> Vec<Error> {
+        let mut errors = Vec::new();
+        for (id, frame) in self.faults.due() {
+            if let Err(e) = self.write(id, Some(frame)) {
+                errors.push(e);
+            }
+        }
+        errors
+    }
+
+    /// Return the frame to write to a connection now, if any, given the fault injected for its
+    /// peer. Frames are written as is until the peer is known.
+    #[cfg(feature = "fault_injection")]
+    fn outgoing_frame(&mut self, id: usize, frame: Vec<u8>) -> Option<Vec<u8>> {
+        match self.connections.get(&id).and_then(|conn| conn.peer.clone()) {
+            Some(node) => self.faults.outgoing(id, &node, frame),
+            None => Some(frame)
+        }
+    }
+
     fn do_socket_io(&mut self, notification: Notification) -> Result<()> {
         match notification.event {
             Event::Read => self.read(notification.id),
@@ -231,41 +562,203 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         }
     }
 
-    /// Returns `Some(true)` if there is such a connection and the members were already sent.
-    /// Returns `Some(false)` if there is such a connection and the members were NOT sent.
-    /// Returns None if there is no such connection.
-    fn members_sent(&self, id: usize) -> Option<bool> {
-        if let Some(conn) = self.connections.get(&id) {
-            return Some(conn.members_sent);
+    fn read(&mut self, id: usize) -> Result<()> {
+        trace!(self.logger, "read"; "id" => id);
+        let messages = try!(self.decode_messages(id));
+        for msg in messages {
+            if self.is_authenticated(id) {
+                try!(self.handle_decoded_message(id, msg));
+            } else {
+                try!(self.handle_handshake_message(id, msg));
+            }
         }
-        None
+        Ok(())
     }
 
-    fn read(&mut self, id: usize) -> Result<()> {
-        trace!(self.logger, "read"; "id" => id);
-        match self.members_sent(id) {
-            Some(false) => try!(self.send_members(id)),
-            None => (),
-            Some(true) => {
-                let messages = try!(self.decode_messages(id));
-                for msg in messages {
-                    try!(self.handle_decoded_message(id, msg));
+    fn is_authenticated(&self, id: usize) -> bool {
+        self.connections.get(&id).map_or(false, |conn| conn.auth == AuthState::Authenticated)
+    }
+
+    /// Authenticate the peer of an unauthenticated connection.
+    ///
+    /// Any message that isn't the expected next step of the handshake, or that carries an invalid
+    /// digest, results in an `AuthError` that closes the connection.
+    fn handle_handshake_message(&mut self, id: usize, msg: ExternalMsg<T>) -> Result<()> {
+        let state = match self.connections.get(&id) {
+            Some(conn) => conn.auth.clone(),
+            None => return Ok(())
+        };
+        match (state, msg) {
+            (AuthState::AwaitingHello,
+             ExternalMsg::Hello {from, nonce, min_version, max_version}) => {
+                debug!(self.logger, "Got Hello"; "id" => id, "from" => from.to_string());
+                try!(self.check_peer_identity(id, &from));
+                if self.refuse_blacklisted(id, &from) {
+                    return Ok(());
                 }
+                let version = try!(self.negotiate_version(id, &from, min_version, max_version));
+                self.set_version(id, version);
+                let client_nonce = auth::nonce();
+                let msg = ExternalMsg::Auth {
+                    from: self.node.clone(),
+                    nonce: client_nonce.clone(),
+                    digest: auth::client_digest(&self.config.cookie, &nonce),
+                    version: version
+                };
+                try!(self.send_handshake_message(id, msg));
+                self.set_auth_state(id, AuthState::AwaitingAuthOk(client_nonce));
+            },
+            (AuthState::AwaitingAuth(server_nonce),
+             ExternalMsg::Auth {from, nonce, digest, version}) => {
+                // The peer of an accepted connection is unknown until its Auth is decoded, so the
+                // Auth is dropped here rather than before decoding it
+                #[cfg(feature = "fault_injection")]
+                let dropped = self.faults.drops(&from);
+                #[cfg(not(feature = "fault_injection"))]
+                let dropped = false;
+                if dropped {
+                    return Ok(());
+                }
+                try!(self.check_peer_identity(id, &from));
+                if self.refuse_blacklisted(id, &from) {
+                    return Ok(());
+                }
+                if !auth::verify_client_digest(&self.config.cookie, &server_nonce, &digest) {
+                    return Err(self.auth_failed(id, Some(from)));
+                }
+                try!(self.negotiate_version(id, &from, version, version));
+                self.set_version(id, version);
+                if let Some(conn) = self.connections.get_mut(&id) {
+                    conn.peer = Some(from.clone());
+                }
+                info!(self.logger, "Authenticated peer"; "id" => id, "peer" => from.to_string());
+                let msg = ExternalMsg::AuthOk {
+                    digest: auth::server_digest(&self.config.cookie, &nonce)
+                };
+                try!(self.send_handshake_message(id, msg));
+                self.set_auth_state(id, AuthState::Authenticated);
+                try!(self.send_members(id));
+            },
+            (AuthState::AwaitingAuthOk(client_nonce), ExternalMsg::AuthOk {digest}) => {
+                if !auth::verify_server_digest(&self.config.cookie, &client_nonce, &digest) {
+                    return Err(self.auth_failed(id, None));
+                }
+                info!(self.logger, "Authenticated peer"; "id" => id);
+                self.set_auth_state(id, AuthState::Authenticated);
+                try!(self.send_members(id));
+            },
+            (state, msg) => {
+                warn!(self.logger, "Unexpected message during handshake";
+                      "id" => id, "state" => format!("{:?}", state), "msg" => format!("{:?}", msg));
+                return Err(self.auth_failed(id, None));
             }
         }
         Ok(())
     }
 
+    /// Close a handshaking connection if the peer is blacklisted. Returns true if the connection
+    /// was closed.
+    fn refuse_blacklisted(&mut self, id: usize, from: &NodeId) -> bool {
+        if !self.is_blacklisted(from) {
+            return false;
+        }
+        debug!(self.logger, "Refusing connection from blacklisted peer";
+               "id" => id, "peer" => from.to_string());
+        self.close(id);
+        true
+    }
+
+    /// The range of protocol versions this node accepts
+    fn protocol_versions(&self) -> (u32, u32) {
+        (self.config.min_protocol_version, self.config.max_protocol_version.min(PROTOCOL_VERSION))
+    }
+
+    /// Choose the highest protocol version supported by both this node and a peer supporting the
+    /// given range of versions
+    fn negotiate_version(&mut self, id: usize, from: &NodeId, min: u32, max: u32) -> Result<u32> {
+        let (local_min, local_max) = self.protocol_versions();
+        let version = max.min(local_max);
+        if version < min.max(local_min) {
+            self.metrics.version_mismatches += 1;
+            warn!(self.logger, "Refusing connection with incompatible protocol version";
+                  "id" => id, "peer" => from.to_string(),
+                  "local_versions" => format!("{}-{}", local_min, local_max),
+                  "peer_versions" => format!("{}-{}", min, max));
+            return Err(ErrorKind::IncompatibleVersion(id, Some(from.clone()), min, max).into());
+        }
+        debug!(self.logger, "Negotiated protocol version";
+               "id" => id, "peer" => from.to_string(), "version" => version);
+        Ok(version)
+    }
+
+    /// Ensure that a TLS peer presented a certificate for the node it claims to be
+    fn check_peer_identity(&mut self, id: usize, from: &NodeId) -> Result<()> {
+        let valid = self.connections.get(&id).map_or(false, |conn| conn.sock.peer_is(&from.name));
+        if !valid {
+            warn!(self.logger, "Peer certificate does not match node name";
+                  "id" => id, "peer" => from.to_string());
+            return Err(self.auth_failed(id, Some(from.clone())));
+        }
+        Ok(())
+    }
+
+    fn set_version(&mut self, id: usize, version: u32) {
+        if let Some(conn) = self.connections.get_mut(&id) {
+            conn.version = version;
+        }
+    }
+
+    fn set_auth_state(&mut self, id: usize, state: AuthState) {
+        if let Some(conn) = self.connections.get_mut(&id) {
+            conn.auth = state;
+        }
+    }
+
+    fn auth_failed(&mut self, id: usize, from: Option<NodeId>) -> Error {
+        self.metrics.auth_failures += 1;
+        let node = from.or_else(|| self.connections.get(&id).and_then(|conn| conn.node.clone()));
+        ErrorKind::AuthError(id, node).into()
+    }
+
+    fn send_handshake_message(&mut self, id: usize, msg: ExternalMsg<T>) -> Result<()> {
+        let encoded = try!(self.codec.encode(&msg)
+                           .chain_err(|| ErrorKind::EncodeError(Some(id), None)));
+        #[cfg(feature = "fault_injection")]
+        let encoded = match self.outgoing_frame(id, encoded) {
+            Some(encoded) => encoded,
+            None => return Ok(())
+        };
+        self.write(id, Some(encoded))
+    }
+
     fn handle_decoded_message(&mut self, id: usize, msg: ExternalMsg<T>) -> Result<()> {
         match msg {
+            ExternalMsg::Hello {..} | ExternalMsg::Auth {..} | ExternalMsg::AuthOk {..} => {
+                warn!(self.logger, "Unexpected handshake message after authentication";
+                      "id" => id);
+                return Err(self.auth_failed(id, None));
+            },
             ExternalMsg::Members{from, orset} => {
                 info!(self.logger, "Got Members"; "id" => id, "from" => from.to_string());
-                self.establish_connection(id, from, orset);
+                try!(self.check_peer_identity(id, &from));
+                let peer = self.connections.get(&id).and_then(|conn| conn.peer.clone());
+                if peer.as_ref() != Some(&from) {
+                    warn!(self.logger, "Members sent from a node other than the authenticated peer";
+                          "id" => id, "from" => from.to_string(),
+                          "peer" => format!("{:?}", peer));
+                    return Err(self.auth_failed(id, peer));
+                }
+                self.establish_connection(id, from.clone(), orset);
+                let topics = ExternalMsg::Topics(self.published_topics.iter().cloned().collect());
+                try!(self.send_to_node(&from, &topics));
+                let groups = ExternalMsg::Groups(self.local_groups.iter().cloned().collect());
+                try!(self.send_to_node(&from, &groups));
+                try!(self.flush_pending(&from));
                 self.check_connections();
             },
             ExternalMsg::Ping => {
                 trace!(self.logger, "Got Ping"; "id" => id);
-                self.reset_timer(id);
+                self.heartbeat(id);
             }
             ExternalMsg::Envelope(envelope) => {
                 self.metrics.received_remote_envelopes += 1;
@@ -279,12 +772,57 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                                                     Some(envelope.to)).into());
                 }
             },
+            ExternalMsg::Forwarded(envelope) => {
+                self.metrics.received_remote_envelopes += 1;
+                debug!(self.logger, "Got forwarded entity message";
+                       "from" => envelope.from.to_string(),
+                       "to" => envelope.to.to_string());
+                if let Err(mpsc::SendError(ExecutorMsg::Forwarded(envelope)))
+                    = self.executor_tx.send(ExecutorMsg::Forwarded(envelope))
+                {
+                    return Err(ErrorKind::SendError("ExecutorMsg::Forwarded".to_string(),
+                                                    Some(envelope.to)).into());
+                }
+            },
             ExternalMsg::Delta(delta) => {
                 debug!(self.logger, "Got Delta mutator";
                        "id" => id, "delta" => format!("{:?}", delta));
                 if self.members.join_delta(delta.clone()) {
+                    self.publish_membership_changes();
                     try!(self.broadcast_delta(delta));
                 }
+            },
+            ExternalMsg::Topics(topics) => {
+                debug!(self.logger, "Got Topics"; "id" => id, "topics" => format!("{:?}", topics));
+                if let Some(node) = self.connections.get(&id).and_then(|conn| conn.node.clone()) {
+                    self.broker.set_peer_topics(node, topics.into_iter().collect());
+                }
+            },
+            ExternalMsg::Groups(groups) => {
+                debug!(self.logger, "Got Groups"; "id" => id, "groups" => format!("{:?}", groups));
+                if let Some(node) = self.connections.get(&id).and_then(|conn| conn.node.clone()) {
+                    self.peer_groups.insert(node, groups.into_iter().collect());
+                }
+            },
+            ExternalMsg::Publish(envelope) => {
+                self.metrics.received_remote_envelopes += 1;
+                let Envelope {from, msg, correlation_id, ..} = envelope;
+                if let Msg::Publish(topic, payload) = msg {
+                    self.deliver_publish(from, topic, payload, correlation_id);
+                }
+            },
+            ExternalMsg::Names(names) => {
+                debug!(self.logger, "Got Names"; "id" => id);
+                self.names.join(names);
+                try!(self.publish_names());
+            },
+            ExternalMsg::NamesDelta(delta) => {
+                debug!(self.logger, "Got names Delta mutator";
+                       "id" => id, "delta" => format!("{:?}", delta));
+                if self.names.join_delta(delta.clone()) {
+                    try!(self.publish_names());
+                    try!(self.broadcast_names_delta(delta));
+                }
             }
         }
         Ok(())
@@ -295,26 +833,52 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         let registrar = &self.registrar;
         if let Some(mut conn) = self.connections.get_mut(&id) {
             if msg.is_none() {
-                if conn.writer.is_writable() {
+                if conn.sock.is_writable() {
                     // The socket has just became writable. We need to re-register it as only
                     // readable, or it the event will keep firing indefinitely even if there is
                     // no data to write.
-                    try!(registrar.reregister(id, &conn.sock, Event::Read)
+                    try!(conn.sock.reregister(id, registrar, Event::Read)
                          .chain_err(|| ErrorKind::RegistrarError(Some(id), conn.node.clone())));
                 }
 
                 // We just got an Event::Write from the poller
-                conn.writer.writable();
+                conn.sock.writable();
             }
             try!(conn_write(id, &mut conn, msg, &registrar));
         }
         Ok(())
     }
 
-    fn reset_timer(&mut self, id: usize) {
+    fn heartbeat(&mut self, id: usize) {
         if let Some(conn) = self.connections.get_mut(&id) {
-            self.timer_wheel.remove(&id, conn.timer_wheel_index);
-            conn.timer_wheel_index = self.timer_wheel.insert(id)
+            if let Some(ref mut detector) = conn.detector {
+                detector.heartbeat(SteadyTime::now());
+            }
+        }
+    }
+
+    /// The current phi of each established peer
+    fn suspicion(&self) -> HashMap<NodeId, f64> {
+        let now = SteadyTime::now();
+        self.established.iter().filter_map(|(node, id)| {
+            self.connections.get(id)
+                .and_then(|conn| conn.detector.as_ref())
+                .map(|detector| (node.clone(), detector.phi(now)))
+        }).collect()
+    }
+
+    /// Close established connections to peers whose suspicion level exceeds the threshold
+    fn close_suspected(&mut self) {
+        let threshold = self.config.phi_threshold as f64;
+        let suspected: Vec<(NodeId, f64)> =
+            self.suspicion().into_iter().filter(|&(_, phi)| phi > threshold).collect();
+        for (node, phi) in suspected {
+            warn!(self.logger, "Peer suspected of failure";
+                  "peer" => node.to_string(), "phi" => phi);
+            self.metrics.suspected_peers += 1;
+            if let Some(id) = self.established.get(&node).cloned() {
+                self.close(id);
+            }
         }
     }
 
@@ -322,23 +886,36 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     /// established connection between these two nodes, determine which one should be closed.
     fn establish_connection(&mut self, id: usize, from: NodeId, orset: ORSet<NodeId>) {
         self.members.join(orset);
+        self.publish_membership_changes();
+        let already_established = self.established.contains_key(&from);
         if let Some(close_id) = self.choose_connection_to_close(id, &from) {
             debug!(self.logger,
                    "Two connections between nodes. Closing the connection where \
                     the peer that sorts lower was the connecting client";
                     "peer" => from.to_string(), "id" => close_id);
-            self.close(close_id);
             if close_id == id {
+                self.close(close_id);
                 return;
             }
+            // The established connection is being replaced, so the peer isn't actually down
+            self.established.remove(&from);
+            self.close(close_id);
         }
         debug!(self.logger, "Trying to establish connection"; "peer" => from.to_string(), "id" => id);
         if let Some(conn) = self.connections.get_mut(&id) {
             info!(self.logger, "Establish connection"; "peer" => from.to_string(), "id" => id);
             conn.node = Some(from.clone());
             self.timer_wheel.remove(&id, conn.timer_wheel_index);
-            conn.timer_wheel_index = self.timer_wheel.insert(id);
-            self.established.insert(from, id);
+            let config = &self.config;
+            conn.detector = Some(FailureDetector::new(SteadyTime::now(),
+                                                      config.tick_time_ms as f64,
+                                                      config.failure_detector_window,
+                                                      config.min_heartbeat_std_dev_ms as f64,
+                                                      config.acceptable_heartbeat_pause_ms as f64));
+            self.established.insert(from.clone(), id);
+            if !already_established {
+                self.publish(ClusterEvent::NodeConnected(from));
+            }
         }
     }
 
@@ -365,12 +942,19 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         let mut output = Vec::new();
         if let Some(conn) = self.connections.get_mut(&id) {
             let node = conn.node.clone();
-            try!(conn.reader.read(&mut conn.sock)
-                 .chain_err(|| ErrorKind::ReadError(id, node.clone())));
+            let frames = try!(conn.sock.read_frames()
+                              .chain_err(|| ErrorKind::ReadError(id, node.clone())));
 
-            for frame in conn.reader.iter_mut() {
-                let mut decoder = Deserializer::new(&frame[..]);
-                let msg = try!(Deserialize::deserialize(&mut decoder)
+            for frame in frames {
+                #[cfg(feature = "fault_injection")]
+                let frame = match conn.peer {
+                    Some(ref node) => match self.faults.incoming(node, frame) {
+                        Some(frame) => frame,
+                        None => continue
+                    },
+                    None => frame
+                };
+                let msg = try!(self.codec.decode(&frame[..])
                                .chain_err(|| ErrorKind::DecodeError(id, node.clone())));
                 output.push(msg);
             }
@@ -380,6 +964,7 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
 
     fn join(&mut self, node: NodeId) -> Result<()> {
         let delta = self.members.add(node.clone());
+        self.publish_membership_changes();
         try!(self.broadcast_delta(delta));
         self.metrics.connection_attempts += 1;
         self.connect(node)
@@ -387,6 +972,7 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
 
     fn leave(&mut self, node: NodeId) -> Result<()> {
         if let Some(delta) = self.members.leave(node.clone()) {
+            self.publish_membership_changes();
             try!(self.broadcast_delta(delta));
         }
         Ok(())
@@ -394,31 +980,45 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
 
     fn connect(&mut self, node: NodeId) -> Result<()> {
         debug!(self.logger, "connect"; "to" => node.to_string());
-        let sock = try!(TcpBuilder::new_v4().chain_err(|| "Failed to create a IPv4 socket"));
-        let sock = try!(sock.to_tcp_stream().chain_err(|| "Failed to create TcpStream"));
-        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
-        if let Err(e) = sock.connect(&node.addr[..]) {
-            if e.raw_os_error().is_some() && *e.raw_os_error().as_ref().unwrap() != EINPROGRESS {
-                return Err(e).chain_err(|| ErrorKind::ConnectError(node));
-            }
-        }
-        try!(self.init_connection(sock, Some(node)));
-        Ok(())
+        let sock = try!(self.transport.connect(&node));
+        let id = try!(self.init_connection(sock, Some(node)));
+        // A TLS client starts the handshake, and the server waits for it before sending its
+        // Hello. Flush the handshake as soon as the socket connects.
+        self.write(id, None)
     }
 
+    /// Accept all pending connections and send each a `Hello`.
+    ///
+    /// The listener is edge triggered, so a connection that fails is handled on its own and the
+    /// remaining connections are still accepted.
     fn accept_connection(&mut self) -> Result<()> {
-        while let Ok((sock, _)) = self.listener.accept() {
+        while let Some(sock) = try!(self.listener.accept()) {
             self.metrics.accepted_connections += 1;
             debug!(self.logger, "accepted connection");
-            try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
-            let id = try!(self.init_connection(sock, None));
-            try!(self.send_members(id));
+            if let Err(e) = self.send_hello(sock) {
+                self.handle_error(&e);
+            }
         }
         Ok(())
     }
 
-    fn init_connection(&mut self, sock: TcpStream, node: Option<NodeId>) -> Result<usize> {
-        let id = try!(self.registrar.register(&sock, Event::Read)
+    fn send_hello(&mut self, sock: Box<Connection>) -> Result<()> {
+        let id = try!(self.init_connection(sock, None));
+        let nonce = auth::nonce();
+        let (min_version, max_version) = self.protocol_versions();
+        let hello = ExternalMsg::Hello {
+            from: self.node.clone(),
+            nonce: nonce.clone(),
+            min_version: min_version,
+            max_version: max_version
+        };
+        try!(self.send_handshake_message(id, hello));
+        self.set_auth_state(id, AuthState::AwaitingAuth(nonce));
+        Ok(())
+    }
+
+    fn init_connection(&mut self, sock: Box<Connection>, node: Option<NodeId>) -> Result<usize> {
+        let id = try!(sock.register(&self.registrar, Event::Read)
                       .chain_err(|| ErrorKind::RegistrarError(None, None)));
         debug!(self.logger, "init_connection()";
                "id" => id, "is_client" => node.is_some(), "peer" => format!("{:?}", node));
@@ -429,21 +1029,82 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(id)
     }
 
+    /// Send the cluster members, followed by the name registry if the peer supports it
     fn send_members(&mut self, id: usize) -> Result<()> {
-        let encoded = try!(self.encode_members(id));
+        let members = ExternalMsg::Members::<T> {
+            from: self.node.clone(),
+            orset: self.members.get_orset()
+        };
+        let names = ExternalMsg::Names::<T>(self.names.clone());
+        let mut frames = Vec::new();
+        for msg in vec![members, names] {
+            if self.connections.get(&id).map_or(false, |conn| conn.version >= msg.version()) {
+                let frame = try!(self.codec.encode(&msg)
+                                 .chain_err(|| ErrorKind::EncodeError(Some(id), None)));
+                #[cfg(feature = "fault_injection")]
+                let frame = match self.outgoing_frame(id, frame) {
+                    Some(frame) => frame,
+                    None => continue
+                };
+                frames.push(frame);
+            }
+        }
         let registrar = &self.registrar;
         if let Some(mut conn) = self.connections.get_mut(&id) {
             info!(self.logger, "Send members"; "id" => id);
-            try!(conn_write(id, &mut conn, Some(encoded), &registrar));
+            for frame in frames {
+                try!(conn_write(id, &mut conn, Some(frame), &registrar));
+            }
             conn.members_sent = true;
         }
         Ok(())
     }
 
+    /// Flush all writes queued for peers and report the envelopes that never left the node
+    fn shutdown(&mut self, mut report: ShutdownReport, reply: Sender<ShutdownReport>) {
+        report.dropped_remote_envelopes = self.pending.values().map(|queue| queue.len()).sum();
+        report.unflushed_connections = self.flush();
+        let _ = reply.send(report);
+    }
+
+    /// Write out everything queued on all connections, waiting up to `request_timeout_ms` for
+    /// sockets to become writable. Returns the number of connections that couldn't be flushed.
+    fn flush(&mut self) -> usize {
+        let deadline =
+            SteadyTime::now() + Duration::milliseconds(self.config.request_timeout_ms as i64);
+        let mut failed = 0;
+        let mut unflushed: Vec<usize> = self.connections.keys().cloned().collect();
+        loop {
+            let mut remaining = Vec::new();
+            for id in unflushed {
+                if let Some(conn) = self.connections.get_mut(&id) {
+                    conn.sock.writable();
+                    match conn.sock.write_frame(None) {
+                        Ok(true) => (),
+                        Ok(false) => remaining.push(id),
+                        Err(_) => failed += 1
+                    }
+                }
+            }
+            if remaining.is_empty() || SteadyTime::now() >= deadline {
+                if failed + remaining.len() > 0 {
+                    warn!(self.logger, "Failed to flush connections";
+                          "count" => failed + remaining.len());
+                }
+                return failed + remaining.len();
+            }
+            unflushed = remaining;
+            thread::sleep(::std::time::Duration::from_millis(1));
+        }
+    }
+
     fn tick(&mut self) -> Result<()> {
         trace!(self.logger, "tick");
         let expired = self.timer_wheel.expire();
         self.deregister(expired);
+        self.close_suspected();
+        self.expire_pending();
+        self.expire_blacklist();
         try!(self.broadcast_pings());
         self.check_connections();
         Ok(())
@@ -456,15 +1117,6 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(())
     }
 
-    fn encode_members(&self, id: usize) -> Result<Vec<u8>> {
-        let orset = self.members.get_orset();
-        let mut encoded = Vec::new();
-        let msg = ExternalMsg::Members::<T> {from: self.node.clone(), orset: orset};
-        try!(msg.serialize(&mut Serializer::new(&mut encoded))
-             .chain_err(|| ErrorKind::EncodeError(Some(id), None)));
-        Ok(encoded)
-    }
-
     fn deregister(&mut self, expired: HashSet<usize>) {
         for id in expired.iter() {
             warn!(self.logger, "Connection timeout"; "id" => *id);
@@ -475,7 +1127,9 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     /// Close an existing connection and remove all related state.
     fn close(&mut self, id: usize) {
         if let Some(conn) = self.connections.remove(&id) {
-            let _ = self.registrar.deregister(conn.sock);
+            let _ = conn.sock.deregister(&self.registrar);
+            #[cfg(feature = "fault_injection")]
+            self.faults.discard(id);
             self.timer_wheel.remove(&id, conn.timer_wheel_index);
             if let Some(node) = conn.node {
                 // Remove established connection if it matches this id
@@ -483,6 +1137,7 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                     if established_id == id {
                         info!(self.logger, "Closing established connection";
                               "id" => id,"peer" => node.to_string());
+                        self.node_down(node);
                         return;
                     }
                     // The established node didn't correspond to this id, so put it back
@@ -495,31 +1150,68 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
 
     fn broadcast_delta(&mut self, delta: Delta<NodeId>) -> Result<()> {
         debug!(self.logger, "Broadcasting delta"; "delta" => format!("{:?}", delta));
-        let mut encoded = Vec::new();
-        let msg = ExternalMsg::Delta::<T>(delta);
-        try!(msg.serialize(&mut Serializer::new(&mut encoded))
-             .chain_err(|| ErrorKind::EncodeError(None, None)));
-        self.broadcast(encoded)
+        self.broadcast(&ExternalMsg::Delta(delta))
+    }
+
+    fn broadcast_names_delta(&mut self, delta: Delta<(String, Pid)>) -> Result<()> {
+        debug!(self.logger, "Broadcasting names delta"; "delta" => format!("{:?}", delta));
+        self.broadcast(&ExternalMsg::NamesDelta(delta))
+    }
+
+    /// The local replica of the name registry was changed by a registration on this node
+    fn names_changed(&mut self, deltas: Vec<Delta<(String, Pid)>>) -> Result<()> {
+        try!(self.publish_names());
+        for delta in deltas {
+            try!(self.broadcast_names_delta(delta));
+        }
+        Ok(())
+    }
+
+    /// Send the resolution of the name registry to the executor if it changed
+    fn publish_names(&mut self) -> Result<()> {
+        let names = self.names.resolve();
+        if names == self.published_names {
+            return Ok(());
+        }
+        self.published_names = names.clone();
+        if let Err(_) = self.executor_tx.send(ExecutorMsg::GlobalNames(names)) {
+            return Err(ErrorKind::SendError("ExecutorMsg::GlobalNames".to_string(), None).into());
+        }
+        Ok(())
     }
 
     fn broadcast_pings(&mut self) -> Result<()> {
-        let mut encoded = Vec::new();
-        let msg = ExternalMsg::Ping::<T>;
-        try!(msg.serialize(&mut Serializer::new(&mut encoded))
-             .chain_err(|| ErrorKind::EncodeError(None, None)));
-        self.broadcast(encoded)
+        self.broadcast(&ExternalMsg::Ping)
     }
 
-    // Write encoded values to all connections and return the id of any connections with errors
-    fn broadcast(&mut self, encoded: Vec<u8>) -> Result<()> {
+    // Write a message to all connections that support it and return the errors of any connections
+    // that failed
+    fn broadcast(&mut self, msg: &ExternalMsg<T>) -> Result<()> {
+        let encoded = try!(self.codec.encode(msg)
+                           .chain_err(|| ErrorKind::EncodeError(None, None)));
+        let version = msg.version();
         let mut errors = Vec::new();
         let registrar = &self.registrar;
+        #[cfg(feature = "fault_injection")]
+        let faults = &mut self.faults;
         for (id, mut conn) in self.connections.iter_mut() {
             if !conn.members_sent {
                 // This connection isn't connected yet
                 continue;
             }
-            if let Err(e) = conn_write(*id, &mut conn, Some(encoded.clone()), &registrar) {
+            if conn.version < version {
+                continue;
+            }
+            let frame = encoded.clone();
+            #[cfg(feature = "fault_injection")]
+            let frame = match conn.peer {
+                Some(ref node) => match faults.outgoing(*id, node, frame) {
+                    Some(frame) => frame,
+                    None => continue
+                },
+                None => frame
+            };
+            if let Err(e) = conn_write(*id, &mut conn, Some(frame), &registrar) {
                 errors.push(e)
             }
         }
@@ -543,7 +1235,9 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
             self.connections.iter().filter_map(|(_, conn)| conn.node.clone()).collect();
 
         let to_connect: Vec<NodeId> = all.difference(&known_peer_conns)
-                                       .filter(|&node| *node != self.node).cloned().collect();
+                                       .filter(|&node| *node != self.node)
+                                       .filter(|&node| !self.is_blacklisted(node))
+                                       .cloned().collect();
 
         let to_disconnect: Vec<NodeId> = known_peer_conns.difference(&all).cloned().collect();
 
@@ -562,10 +1256,13 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     }
 
     fn disconnect_all(&mut self) {
-        self.established = HashMap::new();
+        let established = mem::replace(&mut self.established, HashMap::new());
+        for node in established.into_iter().map(|(node, _)| node) {
+            self.node_down(node);
+        }
         for (id, conn) in self.connections.drain() {
             self.timer_wheel.remove(&id, conn.timer_wheel_index);
-            if let Err(e) = self.registrar.deregister(conn.sock) {
+            if let Err(e) = conn.sock.deregister(&self.registrar) {
                 error!(self.logger, "Failed to deregister socket";
                        "id" => id, "peer" => format!("{:?}", conn.node),
                        "error" => e.to_string());
@@ -576,9 +1273,10 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     fn disconnect_established(&mut self, to_disconnect: Vec<NodeId>) {
         for node in to_disconnect {
             if let Some(id) = self.established.remove(&node) {
+                self.node_down(node);
                 let conn = self.connections.remove(&id).unwrap();
                 self.timer_wheel.remove(&id, conn.timer_wheel_index);
-                if let Err(e) = self.registrar.deregister(conn.sock) {
+                if let Err(e) = conn.sock.deregister(&self.registrar) {
                     error!(self.logger, "Failed to deregister socket";
                            "id" => id, "peer" => conn.node.unwrap().to_string(),
                            "error" => e.to_string());
@@ -587,23 +1285,188 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         }
     }
 
-    fn send_metrics(&mut self, envelope: Envelope<T>) {
-        if let Msg::GetMetrics = envelope.msg {
-            let new_envelope = Envelope {
-                to: envelope.from,
-                from: self.pid.clone(),
-                msg: Msg::Metrics(self.metrics.data()),
-                correlation_id: envelope.correlation_id
-            };
-            // Route the response through the executor since it knows how to contact all Pids
-            if let Err(mpsc::SendError(ExecutorMsg::Envelope(new_envelope))) =
-                self.executor_tx.send(ExecutorMsg::Envelope(new_envelope))
-            {
-                error!(self.logger, "Failed to send to executor";
-                    "envelope" => format!("{:?}", new_envelope));
+    /// Inform the executor that an established connection to a node was lost so that it can
+    /// notify any monitors or links of processes on that node.
+    fn node_down(&mut self, node: NodeId) {
+        self.broker.remove_peer(&node);
+        self.peer_groups.remove(&node);
+        if let Err(_) = self.executor_tx.send(ExecutorMsg::NodeDown(node.clone())) {
+            error!(self.logger, "Failed to send NodeDown to executor");
+        }
+        self.publish(ClusterEvent::NodeDisconnected(node));
+    }
+
+    fn is_subscribed(&self, pid: &Pid) -> bool {
+        self.subscribers.contains(pid) || self.ring_subscribers.contains(pid)
+    }
+
+    /// Monitor a pid that just subscribed to cluster events or ring changes, so that it can be
+    /// removed when it terminates, and stop monitoring it once it has unsubscribed from both.
+    fn watch_subscriber(&self, pid: Pid, was_subscribed: bool) {
+        let msg = match (was_subscribed, self.is_subscribed(&pid)) {
+            (false, true) => Msg::Monitor(pid),
+            (true, false) => Msg::Demonitor(pid),
+            _ => return
+        };
+        self.send_local(Envelope::new(Pid::executor(&self.node), self.pid.clone(), msg, None));
+    }
+
+    /// Handle an envelope addressed to the cluster server itself
+    fn handle_envelope(&mut self, envelope: Envelope<T>) {
+        let Envelope {from, msg, correlation_id, ..} = envelope;
+        match msg {
+            Msg::GetMetrics => {
+                let msg = Msg::Metrics(self.metrics.data());
+                self.send_local(Envelope::new(from, self.pid.clone(), msg, correlation_id));
+            },
+            Msg::SubscribeClusterEvents => {
+                let watched = self.is_subscribed(&from);
+                self.subscribers.insert(from.clone());
+                self.watch_subscriber(from, watched);
+            },
+            Msg::UnsubscribeClusterEvents => {
+                let watched = self.is_subscribed(&from);
+                self.subscribers.remove(&from);
+                self.watch_subscriber(from, watched);
+            },
+            Msg::SubscribeRingChanges => {
+                let watched = self.is_subscribed(&from);
+                self.ring_subscribers.insert(from.clone());
+                self.watch_subscriber(from, watched);
+            },
+            Msg::UnsubscribeRingChanges => {
+                let watched = self.is_subscribed(&from);
+                self.ring_subscribers.remove(&from);
+                self.watch_subscriber(from, watched);
+            },
+            Msg::Down {pid, ..} => {
+                self.subscribers.remove(&pid);
+                self.ring_subscribers.remove(&pid);
+            },
+            msg => error!(self.logger, "Received Unknown Msg";
+                          "from" => from.to_string(), "msg" => format!("{:?}", msg))
+        }
+    }
+
+    fn handle_broker_envelope(&mut self, envelope: Envelope<T>) -> Result<()> {
+        let Envelope {from, msg, correlation_id, ..} = envelope;
+        match msg {
+            Msg::Subscribe(topic) => {
+                if from.node != self.node {
+                    warn!(self.logger, "Only local pids can subscribe to the broker";
+                          "from" => from.to_string());
+                    return Ok(());
+                }
+                // Monitor the subscriber so that it can be removed when it terminates
+                if !self.broker.is_subscribed(&from) {
+                    let (to, msg) = (Pid::executor(&self.node), Msg::Monitor(from.clone()));
+                    self.send_local(Envelope::new(to, self.broker_pid.clone(), msg, None));
+                }
+                self.broker.subscribe(topic, from);
+            },
+            Msg::Unsubscribe(topic) => {
+                self.broker.unsubscribe(&topic, &from);
+                if !self.broker.is_subscribed(&from) {
+                    let (to, msg) = (Pid::executor(&self.node), Msg::Demonitor(from));
+                    self.send_local(Envelope::new(to, self.broker_pid.clone(), msg, None));
+                }
+            },
+            Msg::Down {pid, ..} => self.broker.remove(&pid),
+            Msg::Publish(topic, payload) => {
+                self.metrics.publishes += 1;
+                let mut errors = Vec::new();
+                for node in self.broker.interested_peers(&topic) {
+                    let envelope = Envelope {
+                        to: Pid::broker(&node),
+                        from: from.clone(),
+                        msg: Msg::Publish(topic.clone(), payload.clone()),
+                        correlation_id: correlation_id.clone()
+                    };
+                    self.metrics.forwarded_publishes += 1;
+                    if let Err(e) = self.send_to_node(&node, &ExternalMsg::Publish(envelope)) {
+                        errors.push(e);
+                    }
+                }
+                self.deliver_publish(from, topic, payload, correlation_id);
+                if !errors.is_empty() {
+                    return Err(ErrorKind::BroadcastError(errors).into());
+                }
+            },
+            msg => error!(self.logger, "Received Unknown Msg";
+                          "from" => from.to_string(), "msg" => format!("{:?}", msg))
+        }
+        self.publish_topics()
+    }
+
+    /// Deliver a published message to the local subscribers of its topic
+    fn deliver_publish(&mut self,
+                       from: Pid,
+                       topic: String,
+                       payload: T,
+                       correlation_id: Option<CorrelationId>)
+    {
+        for subscriber in self.broker.subscribers(&topic) {
+            let msg = Msg::Publish(topic.clone(), payload.clone());
+            self.send_local(Envelope::new(subscriber, from.clone(), msg, correlation_id.clone()));
+        }
+    }
+
+    /// Tell peers about any change to the topics with local subscribers
+    fn publish_topics(&mut self) -> Result<()> {
+        let topics = self.broker.topics();
+        if topics == self.published_topics {
+            return Ok(());
+        }
+        debug!(self.logger, "Broadcasting topics"; "topics" => format!("{:?}", topics));
+        let msg = ExternalMsg::Topics::<T>(topics.iter().cloned().collect());
+        self.published_topics = topics;
+        self.broadcast(&msg)
+    }
+
+    /// Send a `MemberAdded` or `MemberRemoved` event for each change in membership since the last
+    /// call, and update the ring to match
+    fn publish_membership_changes(&mut self) {
+        let members = self.members.all();
+        let added: Vec<NodeId> = members.difference(&self.published_members).cloned().collect();
+        let removed: Vec<NodeId> = self.published_members.difference(&members).cloned().collect();
+        if !added.is_empty() || !removed.is_empty() {
+            self.ring.update(&added, &removed);
+            if let Err(_) = self.executor_tx.send(ExecutorMsg::RingChanged) {
+                error!(self.logger, "Failed to send RingChanged to executor");
             }
-        } else {
-            error!(self.logger, "Received Unknown Msg";
+            let msg = Msg::RingChanged(self.ring.members());
+            for subscriber in &self.ring_subscribers {
+                let to = subscriber.clone();
+                self.send_local(Envelope::new(to, self.pid.clone(), msg.clone(), None));
+            }
+        }
+        for node in added {
+            self.publish(ClusterEvent::MemberAdded(node));
+        }
+        for node in removed {
+            self.publish(ClusterEvent::MemberRemoved(node.clone()));
+            if let Some(queue) = self.pending.remove(&node) {
+                for (_, envelope, _) in queue {
+                    self.undeliverable(envelope, UndeliverableReason::NotAMember);
+                }
+            }
+        }
+        self.published_members = members;
+    }
+
+    fn publish(&self, event: ClusterEvent) {
+        for subscriber in &self.subscribers {
+            let msg = Msg::ClusterEvent(event.clone());
+            self.send_local(Envelope::new(subscriber.clone(), self.pid.clone(), msg, None));
+        }
+    }
+
+    /// Route an envelope through the executor since it knows how to contact all Pids
+    fn send_local(&self, envelope: Envelope<T>) {
+        if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope))) =
+            self.executor_tx.send(ExecutorMsg::Envelope(envelope))
+        {
+            error!(self.logger, "Failed to send to executor";
                    "envelope" => format!("{:?}", envelope));
         }
     }
@@ -614,11 +1477,11 @@ fn conn_write(id: usize,
               msg: Option<Vec<u8>>,
               registrar: &Registrar) -> Result<()>
 {
-        let writable = try!(conn.writer.write(&mut conn.sock, msg).chain_err(|| {
+        let writable = try!(conn.sock.write_frame(msg).chain_err(|| {
             ErrorKind::WriteError(id, conn.node.clone())
         }));
         if !writable {
-            return registrar.reregister(id, &conn.sock, Event::Both)
+            return conn.sock.reregister(id, registrar, Event::Both)
                 .chain_err(|| ErrorKind::RegistrarError(Some(id), conn.node.clone()));
         }
         Ok(())
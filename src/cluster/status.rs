@@ -1,9 +1,24 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use node_id::NodeId;
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+/// A snapshot of the state of the cluster server
+///
+/// `ClusterStatus` only implements `PartialEq`, not `Eq`, since suspicion levels are floating
+/// point numbers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ClusterStatus {
     pub members: HashSet<NodeId>,
     pub established: HashSet<NodeId>,
-    pub num_connections: usize
+    pub num_connections: usize,
+
+    /// The phi accrual suspicion level of each established peer. Peers are disconnected when
+    /// their suspicion exceeds the configured `phi_threshold`.
+    pub suspicion: HashMap<NodeId, f64>,
+
+    /// Peers whose connections recently failed with an encode, decode or registration error.
+    /// Connections to and from these peers are refused until their blacklist timeout expires.
+    pub blacklisted: HashSet<NodeId>,
+
+    /// The most recent errors encountered by the cluster server, oldest first
+    pub errors: Vec<String>
 }
@@ -0,0 +1,204 @@
+use std::io::{self, Read, Write, BufReader};
+use std::fs::File;
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use rustls::{Session, ClientConfig, ServerConfig, ClientSession, ServerSession,
+             RootCertStore, AllowAnyAuthenticatedClient, Certificate, PrivateKey};
+use rustls::internal::pemfile;
+use webpki::{DNSNameRef, EndEntityCert};
+use untrusted;
+use errors::*;
+
+/// Paths to the PEM encoded files used to secure connections between nodes.
+///
+/// Both sides of every connection present a certificate signed by the CA. Each node's certificate
+/// must be valid for the DNS name given by the `name` of its `NodeId`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub ca_path: String,
+    pub cert_path: String,
+    pub key_path: String
+}
+
+/// The rustls configs shared by all connections of a cluster server
+#[derive(Clone)]
+pub struct TlsContext {
+    client: Arc<ClientConfig>,
+    server: Arc<ServerConfig>
+}
+
+impl TlsContext {
+    pub fn new(config: &TlsConfig) -> Result<TlsContext> {
+        let roots = load_roots(&config.ca_path)?;
+        let certs = load_certs(&config.cert_path)?;
+        let key = load_key(&config.key_path)?;
+
+        let mut client = ClientConfig::new();
+        client.root_store = roots.clone();
+        client.set_single_client_cert(certs.clone(), key.clone());
+
+        let mut server = ServerConfig::new(AllowAnyAuthenticatedClient::new(roots));
+        server.set_single_cert(certs, key);
+
+        Ok(TlsContext {
+            client: Arc::new(client),
+            server: Arc::new(server)
+        })
+    }
+
+    /// Wrap a socket connecting to the node with the given name
+    pub fn client(&self, sock: TcpStream, peer_name: &str) -> Result<TlsStream> {
+        let name = DNSNameRef::try_from_ascii_str(peer_name).map_err(|_| {
+            ErrorKind::ConfigError(format!("Node name {} is not a valid DNS name", peer_name))
+        })?;
+        let session = ClientSession::new(&self.client, name);
+        Ok(TlsStream::new(sock, Box::new(session)))
+    }
+
+    /// Wrap an accepted socket
+    pub fn server(&self, sock: TcpStream) -> TlsStream {
+        TlsStream::new(sock, Box::new(ServerSession::new(&self.server)))
+    }
+}
+
+/// A non-blocking TLS session over a TCP socket.
+///
+/// Plaintext written to the stream is buffered in the session until the socket becomes writable,
+/// so callers must flush the stream on writable events while `wants_write()` returns true.
+pub struct TlsStream {
+    pub sock: TcpStream,
+    session: Box<Session>
+}
+
+impl TlsStream {
+    fn new(sock: TcpStream, session: Box<Session>) -> TlsStream {
+        TlsStream {
+            sock: sock,
+            session: session
+        }
+    }
+
+    pub fn wants_write(&self) -> bool {
+        self.session.wants_write()
+    }
+
+    /// Return true if the peer presented a certificate valid for the given DNS name
+    pub fn peer_is(&self, name: &str) -> bool {
+        let name = match DNSNameRef::try_from_ascii_str(name) {
+            Ok(name) => name,
+            Err(_) => return false
+        };
+        let certs = match self.session.get_peer_certificates() {
+            Some(certs) => certs,
+            None => return false
+        };
+        match certs.first() {
+            Some(cert) => {
+                match EndEntityCert::from(untrusted::Input::from(&cert.0)) {
+                    Ok(cert) => cert.verify_is_valid_for_dns_name(name).is_ok(),
+                    Err(_) => false
+                }
+            },
+            None => false
+        }
+    }
+
+    /// Write as many buffered TLS records to the socket as possible
+    fn flush_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            match self.session.write_tls(&mut self.sock) {
+                Ok(_) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for TlsStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut eof = false;
+        loop {
+            match self.session.read_tls(&mut self.sock) {
+                Ok(0) => {
+                    eof = true;
+                    break;
+                },
+                Ok(_) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e)
+            }
+        }
+        self.session.process_new_packets().map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+        })?;
+
+        // Handshake messages and alerts may have been generated in response to the records read
+        self.flush_tls()?;
+
+        match self.session.read(buf) {
+            Ok(0) if !eof => Err(io::ErrorKind::WouldBlock.into()),
+            result => result
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.session.write(buf)?;
+        self.flush_tls()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_tls()
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    let file = File::open(path).chain_err(|| {
+        ErrorKind::ConfigError(format!("Failed to open {}", path))
+    })?;
+    Ok(BufReader::new(file))
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match roots.add_pem_file(&mut open(path)?) {
+        Ok((valid, _)) if valid > 0 => Ok(roots),
+        _ => Err(ErrorKind::ConfigError(format!("No valid CA certificates in {}", path)).into())
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    match pemfile::certs(&mut open(path)?) {
+        Ok(ref certs) if certs.is_empty() => {
+            Err(ErrorKind::ConfigError(format!("No certificates in {}", path)).into())
+        },
+        Ok(certs) => Ok(certs),
+        Err(_) => Err(ErrorKind::ConfigError(format!("Invalid certificates in {}", path)).into())
+    }
+}
+
+/// Load a PKCS8 or RSA private key
+fn load_key(path: &str) -> Result<PrivateKey> {
+    if let Ok(mut keys) = pemfile::pkcs8_private_keys(&mut open(path)?) {
+        if !keys.is_empty() {
+            return Ok(keys.remove(0));
+        }
+    }
+    if let Ok(mut keys) = pemfile::rsa_private_keys(&mut open(path)?) {
+        if !keys.is_empty() {
+            return Ok(keys.remove(0));
+        }
+    }
+    Err(ErrorKind::ConfigError(format!("No private key in {}", path)).into())
+}
@@ -0,0 +1,378 @@
+use std::io::{self, Read, Write};
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use libc::EINPROGRESS;
+use net2::{TcpBuilder, TcpStreamExt};
+use amy::{Registrar, Event, FrameReader, FrameWriter};
+use node_id::NodeId;
+use config::RabbleConfig;
+use errors::*;
+use super::tls::{TlsContext, TlsStream};
+
+/// The means by which cluster servers connect to each other.
+///
+/// A transport listens at the `addr` of the local `NodeId` and connects to the `addr` of remote
+/// `NodeId`s. All sockets must be non-blocking and registered with the cluster server's poller.
+pub trait Transport: Send {
+    /// Start listening for connections from other nodes
+    fn listen(&mut self, addr: &str) -> Result<Box<Listener>>;
+
+    /// Start connecting to another node. The connection may not be usable until the poller
+    /// reports it writable.
+    fn connect(&mut self, node: &NodeId) -> Result<Box<Connection>>;
+}
+
+/// A listening socket of a transport
+pub trait Listener: Send {
+    /// Register the listener with the poller for read events, and return its id
+    fn register(&self, registrar: &Registrar) -> io::Result<usize>;
+
+    /// Accept a connection if one is waiting
+    fn accept(&mut self) -> Result<Option<Box<Connection>>>;
+}
+
+/// A connection between two nodes that carries length prefixed frames
+pub trait Connection: Send {
+    fn register(&self, registrar: &Registrar, event: Event) -> io::Result<usize>;
+    fn reregister(&self, id: usize, registrar: &Registrar, event: Event) -> io::Result<()>;
+    fn deregister(self: Box<Self>, registrar: &Registrar) -> io::Result<()>;
+
+    /// Return all complete frames that can be read without blocking
+    fn read_frames(&mut self) -> io::Result<Vec<Vec<u8>>>;
+
+    /// Queue a frame, if any, and write as much queued data as possible without blocking.
+    ///
+    /// Return false if data remains queued, in which case the connection must be registered for
+    /// write events, and written again once it becomes writable.
+    fn write_frame(&mut self, frame: Option<Vec<u8>>) -> io::Result<bool>;
+
+    /// Return true if the last write didn't block
+    fn is_writable(&self) -> bool;
+
+    /// The poller reported that the connection is writable
+    fn writable(&mut self);
+
+    /// Return true if the peer is allowed to identify itself as the node with the given name.
+    ///
+    /// Transports that can't authenticate peers accept any name.
+    fn peer_is(&self, _name: &str) -> bool {
+        true
+    }
+}
+
+/// A non-blocking byte stream that frames can be read from and written to
+trait RawStream: Read + Write + AsRawFd + Send + 'static {
+    /// Return true if there is buffered data that must be flushed when the socket is writable
+    fn wants_write(&self) -> bool {
+        false
+    }
+
+    fn peer_is(&self, _name: &str) -> bool {
+        true
+    }
+}
+
+impl RawStream for TcpStream {}
+impl RawStream for UnixStream {}
+
+impl RawStream for TlsStream {
+    fn wants_write(&self) -> bool {
+        TlsStream::wants_write(self)
+    }
+
+    fn peer_is(&self, name: &str) -> bool {
+        TlsStream::peer_is(self, name)
+    }
+}
+
+/// A connection that frames messages over a byte stream
+struct FramedConnection<S> {
+    sock: S,
+    reader: FrameReader,
+    writer: FrameWriter
+}
+
+impl<S: RawStream> FramedConnection<S> {
+    fn new(sock: S, max_frame_size: u32) -> Box<Connection> {
+        Box::new(FramedConnection {
+            sock: sock,
+            reader: FrameReader::new(max_frame_size),
+            writer: FrameWriter::new()
+        })
+    }
+}
+
+impl<S: RawStream> Connection for FramedConnection<S> {
+    fn register(&self, registrar: &Registrar, event: Event) -> io::Result<usize> {
+        registrar.register(&self.sock, event)
+    }
+
+    fn reregister(&self, id: usize, registrar: &Registrar, event: Event) -> io::Result<()> {
+        registrar.reregister(id, &self.sock, event)
+    }
+
+    fn deregister(self: Box<Self>, registrar: &Registrar) -> io::Result<()> {
+        registrar.deregister(self.sock)
+    }
+
+    fn read_frames(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        try!(self.reader.read(&mut self.sock));
+        Ok(self.reader.iter_mut().collect())
+    }
+
+    fn write_frame(&mut self, frame: Option<Vec<u8>>) -> io::Result<bool> {
+        let writable = try!(self.writer.write(&mut self.sock, frame));
+        // TLS records may remain buffered after the frame writer has written everything
+        try!(self.sock.flush());
+        Ok(writable && !self.sock.wants_write())
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writer.is_writable()
+    }
+
+    fn writable(&mut self) {
+        self.writer.writable()
+    }
+
+    fn peer_is(&self, name: &str) -> bool {
+        self.sock.peer_is(name)
+    }
+}
+
+/// Connect nodes over TCP, secured with TLS if the config contains a `TlsConfig`
+pub struct TcpTransport {
+    tls: Option<TlsContext>,
+    max_frame_size: u32
+}
+
+impl TcpTransport {
+    pub fn new(config: &RabbleConfig) -> Result<TcpTransport> {
+        let tls = match config.tls {
+            Some(ref tls) => Some(try!(TlsContext::new(tls))),
+            None => None
+        };
+        Ok(TcpTransport {
+            tls: tls,
+            max_frame_size: config.max_frame_size
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn listen(&mut self, addr: &str) -> Result<Box<Listener>> {
+        let listener = try!(TcpListener::bind(addr)
+                            .chain_err(|| format!("Failed to listen on {}", addr)));
+        try!(listener.set_nonblocking(true).chain_err(|| "Failed to make listener nonblocking"));
+        Ok(Box::new(TcpTransportListener {
+            listener: listener,
+            tls: self.tls.clone(),
+            max_frame_size: self.max_frame_size
+        }))
+    }
+
+    fn connect(&mut self, node: &NodeId) -> Result<Box<Connection>> {
+        let sock = try!(TcpBuilder::new_v4().chain_err(|| "Failed to create a IPv4 socket"));
+        let sock = try!(sock.to_tcp_stream().chain_err(|| "Failed to create TcpStream"));
+        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        if let Err(e) = sock.connect(&node.addr[..]) {
+            if e.raw_os_error().is_some() && *e.raw_os_error().as_ref().unwrap() != EINPROGRESS {
+                return Err(e).chain_err(|| ErrorKind::ConnectError(node.clone()));
+            }
+        }
+        Ok(match self.tls {
+            Some(ref tls) => FramedConnection::new(try!(tls.client(sock, &node.name)),
+                                                   self.max_frame_size),
+            None => FramedConnection::new(sock, self.max_frame_size)
+        })
+    }
+}
+
+struct TcpTransportListener {
+    listener: TcpListener,
+    tls: Option<TlsContext>,
+    max_frame_size: u32
+}
+
+impl Listener for TcpTransportListener {
+    fn register(&self, registrar: &Registrar) -> io::Result<usize> {
+        registrar.register(&self.listener, Event::Read)
+    }
+
+    fn accept(&mut self) -> Result<Option<Box<Connection>>> {
+        let sock = match self.listener.accept() {
+            Ok((sock, _)) => sock,
+            Err(_) => return Ok(None)
+        };
+        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        Ok(Some(match self.tls {
+            Some(ref tls) => FramedConnection::new(tls.server(sock), self.max_frame_size),
+            None => FramedConnection::new(sock, self.max_frame_size)
+        }))
+    }
+}
+
+/// Connect nodes running on the same host over Unix domain sockets.
+///
+/// The `addr` of each `NodeId` is the path of its socket. Any file at that path is removed when the
+/// node starts listening.
+pub struct UnixTransport {
+    max_frame_size: u32
+}
+
+impl UnixTransport {
+    pub fn new(config: &RabbleConfig) -> UnixTransport {
+        UnixTransport {
+            max_frame_size: config.max_frame_size
+        }
+    }
+}
+
+impl Transport for UnixTransport {
+    fn listen(&mut self, addr: &str) -> Result<Box<Listener>> {
+        if Path::new(addr).exists() {
+            try!(fs::remove_file(addr).chain_err(|| format!("Failed to remove {}", addr)));
+        }
+        let listener = try!(UnixListener::bind(addr)
+                            .chain_err(|| format!("Failed to listen on {}", addr)));
+        try!(listener.set_nonblocking(true).chain_err(|| "Failed to make listener nonblocking"));
+        Ok(Box::new(UnixTransportListener {
+            listener: listener,
+            max_frame_size: self.max_frame_size
+        }))
+    }
+
+    fn connect(&mut self, node: &NodeId) -> Result<Box<Connection>> {
+        // Connecting to a local socket doesn't block waiting for the peer to accept
+        let sock = try!(UnixStream::connect(&node.addr)
+                        .chain_err(|| ErrorKind::ConnectError(node.clone())));
+        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        Ok(FramedConnection::new(sock, self.max_frame_size))
+    }
+}
+
+struct UnixTransportListener {
+    listener: UnixListener,
+    max_frame_size: u32
+}
+
+impl Listener for UnixTransportListener {
+    fn register(&self, registrar: &Registrar) -> io::Result<usize> {
+        registrar.register(&self.listener, Event::Read)
+    }
+
+    fn accept(&mut self) -> Result<Option<Box<Connection>>> {
+        let sock = match self.listener.accept() {
+            Ok((sock, _)) => sock,
+            Err(_) => return Ok(None)
+        };
+        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        Ok(Some(FramedConnection::new(sock, self.max_frame_size)))
+    }
+}
+
+/// Connect nodes running in the same process without binding any ports or socket files.
+///
+/// Nodes listening on clones of a `MemoryTransport` can connect to each other using any string as
+/// their `addr`. Each connection is an anonymous Unix socket pair, so it can be registered with the
+/// poller like any other connection. This is handy for tests that start many nodes.
+#[derive(Clone)]
+pub struct MemoryTransport {
+    listeners: Arc<Mutex<HashMap<String, MemoryListenerHandle>>>,
+    max_frame_size: u32
+}
+
+// The means of handing a new connection to a listener and waking up its poller
+struct MemoryListenerHandle {
+    tx: Sender<UnixStream>,
+    wakeup: UnixStream
+}
+
+impl MemoryTransport {
+    pub fn new(config: &RabbleConfig) -> MemoryTransport {
+        MemoryTransport {
+            listeners: Arc::new(Mutex::new(HashMap::new())),
+            max_frame_size: config.max_frame_size
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn listen(&mut self, addr: &str) -> Result<Box<Listener>> {
+        let (wakeup_tx, wakeup_rx) = try!(UnixStream::pair()
+                                          .chain_err(|| "Failed to create a socket pair"));
+        try!(wakeup_tx.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        try!(wakeup_rx.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        let (tx, rx) = channel();
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(addr) {
+            return Err(format!("Failed to listen on {}: address in use", addr).into());
+        }
+        listeners.insert(addr.to_string(), MemoryListenerHandle {tx: tx, wakeup: wakeup_tx});
+        Ok(Box::new(MemoryTransportListener {
+            addr: addr.to_string(),
+            listeners: self.listeners.clone(),
+            rx: rx,
+            wakeup: wakeup_rx,
+            max_frame_size: self.max_frame_size
+        }))
+    }
+
+    fn connect(&mut self, node: &NodeId) -> Result<Box<Connection>> {
+        let listeners = self.listeners.lock().unwrap();
+        let listener = match listeners.get(&node.addr) {
+            Some(listener) => listener,
+            None => return Err(ErrorKind::ConnectError(node.clone()).into())
+        };
+        let (sock, peer) = try!(UnixStream::pair().chain_err(|| "Failed to create a socket pair"));
+        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        try!(peer.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        try!(listener.tx.send(peer).chain_err(|| ErrorKind::ConnectError(node.clone())));
+        // The listener only needs to wake up once to accept all waiting connections, so a full
+        // wakeup socket is fine
+        if let Err(e) = (&listener.wakeup).write(&[0]) {
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(e).chain_err(|| ErrorKind::ConnectError(node.clone()));
+            }
+        }
+        Ok(FramedConnection::new(sock, self.max_frame_size))
+    }
+}
+
+struct MemoryTransportListener {
+    addr: String,
+    listeners: Arc<Mutex<HashMap<String, MemoryListenerHandle>>>,
+    rx: Receiver<UnixStream>,
+    wakeup: UnixStream,
+    max_frame_size: u32
+}
+
+impl Listener for MemoryTransportListener {
+    fn register(&self, registrar: &Registrar) -> io::Result<usize> {
+        registrar.register(&self.wakeup, Event::Read)
+    }
+
+    fn accept(&mut self) -> Result<Option<Box<Connection>>> {
+        let mut buf = [0; 64];
+        while let Ok(n) = self.wakeup.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+        Ok(self.rx.try_recv().ok().map(|sock| FramedConnection::new(sock, self.max_frame_size)))
+    }
+}
+
+impl Drop for MemoryTransportListener {
+    fn drop(&mut self) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.remove(&self.addr);
+        }
+    }
+}
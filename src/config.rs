@@ -0,0 +1,398 @@
+use std::fmt::{self, Debug, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use ferris::Resolution;
+use serde_json;
+use toml;
+use executor::MailboxConfig;
+use cluster::{TlsConfig, PROTOCOL_VERSION};
+use errors::*;
+
+/// The resolution of a slot in the executor's hierarchical timer wheel
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TimerResolution {
+    Ms,
+    TenMs,
+    HundredMs,
+    Sec,
+    Min,
+    Hour
+}
+
+impl From<TimerResolution> for Resolution {
+    fn from(resolution: TimerResolution) -> Resolution {
+        match resolution {
+            TimerResolution::Ms => Resolution::Ms,
+            TimerResolution::TenMs => Resolution::TenMs,
+            TimerResolution::HundredMs => Resolution::HundredMs,
+            TimerResolution::Sec => Resolution::Sec,
+            TimerResolution::Min => Resolution::Min,
+            TimerResolution::Hour => Resolution::Hour
+        }
+    }
+}
+
+/// Tuning parameters for a single rabble node
+///
+/// Use `RabbleConfig::default()`, a `RabbleConfigBuilder`, or `RabbleConfig::from_file` to create a
+/// config and pass it to `rabble::rouse_with`. Fields missing from a config file take their
+/// default values.
+///
+/// The cookie is redacted when a config is printed with `Debug` and is never serialized, so a
+/// config can be logged or written out without leaking it.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RabbleConfig {
+    /// How long the poller thread waits for notifications before waking up
+    pub poll_timeout_ms: usize,
+
+    /// The interval at which the cluster server sends heartbeats and checks for failed peers
+    pub tick_time_ms: usize,
+
+    /// The time after which a peer connection that has not been established is closed. This
+    /// should be a multiple of `tick_time_ms`.
+    pub request_timeout_ms: usize,
+
+    /// The maximum number of envelopes queued for a member node that is not connected yet
+    pub pending_queue_size: usize,
+
+    /// The time after which envelopes queued for a member node that is not connected are
+    /// returned to their senders as undeliverable
+    pub pending_timeout_ms: usize,
+
+    /// The phi accrual suspicion level above which an established peer is considered failed and
+    /// disconnected. Higher values detect failures more slowly but make false positives less
+    /// likely.
+    pub phi_threshold: u32,
+
+    /// The number of heartbeat inter-arrival times used to estimate the heartbeat distribution
+    pub failure_detector_window: usize,
+
+    /// The minimum standard deviation of the heartbeat distribution. This prevents a perfectly
+    /// regular heartbeat from making the detector overly sensitive to small delays.
+    pub min_heartbeat_std_dev_ms: usize,
+
+    /// A pause in heartbeats that is tolerated before suspicion starts rising, such as a GC
+    /// pause or a temporary network hiccup
+    pub acceptable_heartbeat_pause_ms: usize,
+
+    /// The interval at which the executor fires expired process timers
+    pub executor_tick_time_ms: usize,
+
+    /// The largest frame that can be received from a peer, in bytes
+    pub max_frame_size: u32,
+
+    /// The slot resolutions of the executor's timer wheel, from finest to coarsest
+    pub timer_resolutions: Vec<TimerResolution>,
+
+    /// The number of threads running processes
+    pub executor_workers: usize,
+
+    /// The mailbox configuration of processes not spawned with an explicit one
+    pub default_mailbox: MailboxConfig,
+
+    /// The maximum number of messages queued for the executor
+    pub executor_channel_bound: usize,
+
+    /// The maximum number of messages queued for the cluster server
+    pub cluster_channel_bound: usize,
+
+    /// The maximum number of messages queued for each executor worker. Envelopes for processes
+    /// on a worker with a full channel are sent to the dead letter service.
+    pub worker_channel_bound: usize,
+
+    /// The shared secret that nodes use to authenticate each other when connecting. All nodes in
+    /// a cluster must use the same cookie.
+    #[serde(skip_serializing)]
+    pub cookie: String,
+
+    /// Secure connections between nodes with mutually authenticated TLS. Connections are plain
+    /// TCP if this is `None`.
+    pub tls: Option<TlsConfig>,
+
+    /// The range of cluster protocol versions this node accepts from peers. Nodes connect using
+    /// the highest version both support, so during a rolling upgrade the range should include the
+    /// versions of both old and new nodes. The maximum is limited to the latest version supported
+    /// by this release.
+    pub min_protocol_version: u32,
+    pub max_protocol_version: u32,
+
+    /// How long a peer is refused connections after it sent a frame that couldn't be decoded, or
+    /// its connection couldn't be encoded to or registered with the poller
+    pub blacklist_timeout_ms: usize,
+
+    /// The number of recent cluster server errors reported in the `ClusterStatus`
+    pub error_log_size: usize,
+
+    /// The number of points each node has on the `ClusterRing`. More points spread keys more
+    /// evenly between nodes.
+    pub ring_vnodes: usize,
+
+    /// The number of nodes that own each key on the `ClusterRing`
+    pub replication_factor: usize
+}
+
+impl Debug for RabbleConfig {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("RabbleConfig")
+            .field("poll_timeout_ms", &self.poll_timeout_ms)
+            .field("tick_time_ms", &self.tick_time_ms)
+            .field("request_timeout_ms", &self.request_timeout_ms)
+            .field("pending_queue_size", &self.pending_queue_size)
+            .field("pending_timeout_ms", &self.pending_timeout_ms)
+            .field("phi_threshold", &self.phi_threshold)
+            .field("failure_detector_window", &self.failure_detector_window)
+            .field("min_heartbeat_std_dev_ms", &self.min_heartbeat_std_dev_ms)
+            .field("acceptable_heartbeat_pause_ms", &self.acceptable_heartbeat_pause_ms)
+            .field("executor_tick_time_ms", &self.executor_tick_time_ms)
+            .field("max_frame_size", &self.max_frame_size)
+            .field("timer_resolutions", &self.timer_resolutions)
+            .field("executor_workers", &self.executor_workers)
+            .field("default_mailbox", &self.default_mailbox)
+            .field("executor_channel_bound", &self.executor_channel_bound)
+            .field("cluster_channel_bound", &self.cluster_channel_bound)
+            .field("worker_channel_bound", &self.worker_channel_bound)
+            .field("cookie", &"<redacted>")
+            .field("tls", &self.tls)
+            .field("min_protocol_version", &self.min_protocol_version)
+            .field("max_protocol_version", &self.max_protocol_version)
+            .field("blacklist_timeout_ms", &self.blacklist_timeout_ms)
+            .field("error_log_size", &self.error_log_size)
+            .field("ring_vnodes", &self.ring_vnodes)
+            .field("replication_factor", &self.replication_factor)
+            .finish()
+    }
+}
+
+impl Default for RabbleConfig {
+    fn default() -> RabbleConfig {
+        RabbleConfig {
+            poll_timeout_ms: 5000,
+            tick_time_ms: 1000,
+            request_timeout_ms: 5000,
+            pending_queue_size: 1000,
+            pending_timeout_ms: 5000,
+            phi_threshold: 8,
+            failure_detector_window: 100,
+            min_heartbeat_std_dev_ms: 100,
+            acceptable_heartbeat_pause_ms: 3000,
+            executor_tick_time_ms: 100,
+            max_frame_size: 100*1024*1024, // 100 MB
+            timer_resolutions: vec![TimerResolution::TenMs,
+                                    TimerResolution::Sec,
+                                    TimerResolution::Min],
+            executor_workers: 1,
+            default_mailbox: MailboxConfig::default(),
+            executor_channel_bound: 10_000,
+            cluster_channel_bound: 10_000,
+            worker_channel_bound: 10_000,
+            cookie: String::new(),
+            tls: None,
+            min_protocol_version: PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+            blacklist_timeout_ms: 30000,
+            error_log_size: 100,
+            ring_vnodes: 128,
+            replication_factor: 3
+        }
+    }
+}
+
+impl RabbleConfig {
+    /// Load a config from a TOML or JSON file, as determined by the file's extension
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<RabbleConfig> {
+        let path = path.as_ref();
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        let config: RabbleConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)?,
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => return Err(ErrorKind::ConfigError(format!("Unknown config file type: {}",
+                                                           path.display())).into())
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that the config can be used to start a node
+    pub fn validate(&self) -> Result<()> {
+        let nonzero = [
+            ("tick_time_ms", self.tick_time_ms),
+            ("executor_tick_time_ms", self.executor_tick_time_ms),
+            ("executor_workers", self.executor_workers),
+            ("executor_channel_bound", self.executor_channel_bound),
+            ("cluster_channel_bound", self.cluster_channel_bound),
+            ("worker_channel_bound", self.worker_channel_bound),
+            ("failure_detector_window", self.failure_detector_window),
+            ("ring_vnodes", self.ring_vnodes),
+            ("replication_factor", self.replication_factor)
+        ];
+        for &(name, value) in nonzero.iter() {
+            if value == 0 {
+                return Err(ErrorKind::ConfigError(format!("{} must be greater than 0",
+                                                          name)).into());
+            }
+        }
+        if self.min_protocol_version == 0 ||
+            self.min_protocol_version > self.max_protocol_version ||
+            self.min_protocol_version > PROTOCOL_VERSION
+        {
+            let msg = format!("Invalid protocol version range {}-{}. Versions 1-{} are supported.",
+                              self.min_protocol_version, self.max_protocol_version,
+                              PROTOCOL_VERSION);
+            return Err(ErrorKind::ConfigError(msg).into());
+        }
+        if self.timer_resolutions.is_empty() {
+            let msg = "timer_resolutions must not be empty".to_string();
+            return Err(ErrorKind::ConfigError(msg).into());
+        }
+        Ok(())
+    }
+
+    /// The number of cluster ticks after which an idle peer connection times out
+    pub fn request_timeout_ticks(&self) -> usize {
+        (self.request_timeout_ms / self.tick_time_ms).max(1)
+    }
+}
+
+/// Build a `RabbleConfig`, starting from the default values
+#[derive(Debug, Clone)]
+pub struct RabbleConfigBuilder {
+    config: RabbleConfig
+}
+
+impl RabbleConfigBuilder {
+    pub fn new() -> RabbleConfigBuilder {
+        RabbleConfigBuilder {
+            config: RabbleConfig::default()
+        }
+    }
+
+    pub fn poll_timeout_ms(mut self, timeout: usize) -> RabbleConfigBuilder {
+        self.config.poll_timeout_ms = timeout;
+        self
+    }
+
+    pub fn tick_time_ms(mut self, tick_time: usize) -> RabbleConfigBuilder {
+        self.config.tick_time_ms = tick_time;
+        self
+    }
+
+    pub fn request_timeout_ms(mut self, timeout: usize) -> RabbleConfigBuilder {
+        self.config.request_timeout_ms = timeout;
+        self
+    }
+
+    pub fn pending_queue_size(mut self, size: usize) -> RabbleConfigBuilder {
+        self.config.pending_queue_size = size;
+        self
+    }
+
+    pub fn pending_timeout_ms(mut self, timeout: usize) -> RabbleConfigBuilder {
+        self.config.pending_timeout_ms = timeout;
+        self
+    }
+
+    pub fn phi_threshold(mut self, threshold: u32) -> RabbleConfigBuilder {
+        self.config.phi_threshold = threshold;
+        self
+    }
+
+    pub fn failure_detector_window(mut self, window: usize) -> RabbleConfigBuilder {
+        self.config.failure_detector_window = window;
+        self
+    }
+
+    pub fn min_heartbeat_std_dev_ms(mut self, std_dev: usize) -> RabbleConfigBuilder {
+        self.config.min_heartbeat_std_dev_ms = std_dev;
+        self
+    }
+
+    pub fn acceptable_heartbeat_pause_ms(mut self, pause: usize) -> RabbleConfigBuilder {
+        self.config.acceptable_heartbeat_pause_ms = pause;
+        self
+    }
+
+    pub fn executor_tick_time_ms(mut self, tick_time: usize) -> RabbleConfigBuilder {
+        self.config.executor_tick_time_ms = tick_time;
+        self
+    }
+
+    pub fn max_frame_size(mut self, size: u32) -> RabbleConfigBuilder {
+        self.config.max_frame_size = size;
+        self
+    }
+
+    pub fn timer_resolutions(mut self, resolutions: Vec<TimerResolution>) -> RabbleConfigBuilder {
+        self.config.timer_resolutions = resolutions;
+        self
+    }
+
+    pub fn executor_workers(mut self, workers: usize) -> RabbleConfigBuilder {
+        self.config.executor_workers = workers;
+        self
+    }
+
+    pub fn default_mailbox(mut self, mailbox: MailboxConfig) -> RabbleConfigBuilder {
+        self.config.default_mailbox = mailbox;
+        self
+    }
+
+    pub fn executor_channel_bound(mut self, bound: usize) -> RabbleConfigBuilder {
+        self.config.executor_channel_bound = bound;
+        self
+    }
+
+    pub fn cluster_channel_bound(mut self, bound: usize) -> RabbleConfigBuilder {
+        self.config.cluster_channel_bound = bound;
+        self
+    }
+
+    pub fn worker_channel_bound(mut self, bound: usize) -> RabbleConfigBuilder {
+        self.config.worker_channel_bound = bound;
+        self
+    }
+
+    pub fn cookie(mut self, cookie: String) -> RabbleConfigBuilder {
+        self.config.cookie = cookie;
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsConfig) -> RabbleConfigBuilder {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    pub fn protocol_versions(mut self, min: u32, max: u32) -> RabbleConfigBuilder {
+        self.config.min_protocol_version = min;
+        self.config.max_protocol_version = max;
+        self
+    }
+
+    pub fn blacklist_timeout_ms(mut self, timeout: usize) -> RabbleConfigBuilder {
+        self.config.blacklist_timeout_ms = timeout;
+        self
+    }
+
+    pub fn error_log_size(mut self, size: usize) -> RabbleConfigBuilder {
+        self.config.error_log_size = size;
+        self
+    }
+
+    pub fn ring_vnodes(mut self, vnodes: usize) -> RabbleConfigBuilder {
+        self.config.ring_vnodes = vnodes;
+        self
+    }
+
+    pub fn replication_factor(mut self, replication_factor: usize) -> RabbleConfigBuilder {
+        self.config.replication_factor = replication_factor;
+        self
+    }
+
+    /// Return the config, or a `ConfigError` if it is invalid
+    pub fn build(self) -> Result<RabbleConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
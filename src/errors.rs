@@ -1,6 +1,8 @@
 use std::io;
 use msgpack;
 use protobuf;
+use toml;
+use serde_json;
 use pid::Pid;
 use node_id::NodeId;
 
@@ -11,6 +13,8 @@ error_chain! {
         msgpack::encode::Error, MsgpackEncode;
         msgpack::decode::Error, MsgpackDecode;
         protobuf::error::ProtobufError, Protobuf;
+        toml::de::Error, Toml;
+        serde_json::Error, Json;
     }
 
     errors {
@@ -42,6 +46,15 @@ error_chain! {
             description("Failed to process poll notifications")
             display("Failed to process poll notifications: errors = {:?}", errors)
         }
+        AuthError(id: usize, node: Option<NodeId>) {
+            description("Failed to authenticate peer")
+            display("Failed to authenticate peer: id={}, peer={:?}", id, node)
+        }
+        IncompatibleVersion(id: usize, node: Option<NodeId>, min: u32, max: u32) {
+            description("Incompatible protocol version")
+            display("Peer only supports protocol versions {} to {}: id={}, peer={:?}",
+                    min, max, id, node)
+        }
         ConnectError(node: NodeId) {
             description("Failed to connect")
             display("Failed to connect to {}", node)
@@ -50,6 +63,14 @@ error_chain! {
             description("Failed to send")
             display("Failed to send {} to {:?}", msg, pid)
         }
+        ConfigError(msg: String) {
+            description("Invalid config")
+            display("Invalid config: {}", msg)
+        }
+        Timeout(pid: Pid) {
+            description("Timed out waiting for a reply")
+            display("Timed out waiting for a reply from {}", pid)
+        }
         Shutdown(pid: Pid) {
             description("Shutting down")
             display("Shutting down {}", pid)
@@ -66,6 +87,8 @@ impl ErrorKind {
             ErrorKind::RegistrarError(id, _) => id.map_or(vec![], |id| vec![id]),
             ErrorKind::WriteError(id, _) => vec![id],
             ErrorKind::ReadError(id, _) => vec![id],
+            ErrorKind::AuthError(id, _) => vec![id],
+            ErrorKind::IncompatibleVersion(id, ..) => vec![id],
             ErrorKind::BroadcastError(ref errors) =>
                 errors.iter().flat_map(|e| e.kind().get_ids()).collect(),
             ErrorKind::PollNotificationErrors(ref errors) =>
@@ -1,90 +1,335 @@
 use serde::{Serialize, Deserialize};
-use std::mem;
 use std::fmt::Debug;
-use std::sync::mpsc::{Sender, Receiver};
-use std::collections::HashMap;
+use std::thread::{self, JoinHandle};
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender, SyncSender, Receiver, TrySendError, RecvTimeoutError};
+use std::collections::{HashMap, HashSet, VecDeque};
 use amy;
 use slog;
 use time::Duration;
 use ferris::{Wheel, CopyWheel, Resolution};
 use envelope::Envelope;
 use pid::Pid;
-use process::Process;
+use process::{Process, EntityFactory};
 use node_id::NodeId;
-use msg::Msg;
-use cluster::ClusterMsg;
+use msg::{Msg, DownReason, DeadLetterReason, UndeliverableReason};
+use cluster::{ClusterMsg, ClusterRing};
 use correlation_id::CorrelationId;
 use metrics::Metrics;
-use super::{ExecutorStatus, ExecutorMetrics, ExecutorMsg};
+use config::RabbleConfig;
+use node::ShutdownReport;
+use supervisor::{Supervisor, SupervisorSpec, SupervisedChild, Child, ChildSpec};
+use super::{ExecutorStatus, WorkerStatus, ExecutorMetrics, ExecutorMsg};
+use super::watches::Watches;
+use super::worker::{Worker, WorkerMsg, panic_reason};
+use super::mailbox::{MailboxConfig, WorkerCounters, MailboxSizes};
 
+/// The executor's end of a worker thread
+struct WorkerHandle<T> {
+    tx: SyncSender<WorkerMsg<T>>,
+    // Control messages that didn't fit in the worker channel, in the order they were sent
+    backlog: VecDeque<WorkerMsg<T>>,
+    counters: Arc<WorkerCounters>,
+    processes: usize,
+    // Workers are moved into their own threads when the executor starts running
+    worker: Option<Worker<T>>,
+    thread: Option<JoinHandle<usize>>
+}
+
+/// The executor routes all envelopes on a node, and manages the lifecycle of all processes.
+///
+/// Each process is owned by exactly one worker thread, chosen by the hash of its pid. The executor
+/// forwards envelopes to the owning worker, which calls the process's handle method. Since a single
+/// executor channel and a single channel per worker are used, envelopes from a given sender are
+/// always delivered in order.
+///
+/// The executor, cluster and worker channels are bounded. Threads sending to the executor block
+/// when it falls behind, but the executor itself never blocks, since the cluster server and the
+/// workers also send to it. Envelopes for remote pids that don't fit in the cluster channel are
+/// returned to their senders as `Msg::Undeliverable`, and envelopes that don't fit in the channel
+/// of a worker are sent to the dead letter service. Process mailboxes may be bounded as well, in
+/// which case envelopes sent to a full mailbox are handled according to the mailbox's
+/// `OverflowPolicy`.
+///
+/// Envelopes that can't be delivered are forwarded to the dead letter service, `Pid::dead_letters`,
+/// if a service or process is registered with that pid.
 pub struct Executor<T> {
     pid: Pid,
     node: NodeId,
-    envelopes: Vec<Envelope<T>>,
-    processes: HashMap<Pid, Box<Process<T>>>,
+    // Each running process maps to the index of its worker and its instance number. Instance
+    // numbers distinguish restarted processes from failed instances with the same pid.
+    processes: HashMap<Pid, (usize, u64)>,
+    next_instance: u64,
+    workers: Vec<WorkerHandle<T>>,
+    // Each worker thread holds a sender to this channel, so it disconnects once all workers exit
+    worker_exits: Option<Receiver<()>>,
+    supervisors: HashMap<Pid, Supervisor<T>>,
+    parents: HashMap<Pid, Pid>,
+    monitors: Watches,
+    links: Watches,
     service_senders: HashMap<Pid, amy::Sender<Envelope<T>>>,
-    tx: Sender<ExecutorMsg<T>>,
+    // Names registered on this node, and the resolution of the cluster wide registry
+    names: HashMap<String, Pid>,
+    global_names: HashMap<String, Pid>,
+    // The local processes in each group
+    groups: HashMap<String, HashSet<Pid>>,
+    // The groups last sent to the cluster server. This is `None` if the last send failed.
+    published_groups: Option<HashSet<String>>,
+    // The factories of the entity types registered on this node, and the ring used to place
+    // entities
+    entity_types: HashMap<String, EntityFactory<T>>,
+    ring: ClusterRing,
+    // Envelopes for entities being handed off to another node, held until the local instance has
+    // stopped so that the new owner never runs the entity at the same time
+    handoffs: HashMap<Pid, Vec<Envelope<T>>>,
     rx: Receiver<ExecutorMsg<T>>,
-    cluster_tx: Sender<ClusterMsg<T>>,
+    cluster_tx: SyncSender<ClusterMsg<T>>,
+    default_mailbox: MailboxConfig,
+    mailbox_sizes: MailboxSizes,
+    // Envelopes for remote pids dropped because the cluster channel was full
+    dropped_remote_envelopes: u64,
     timer_wheel: CopyWheel<(Pid, Option<CorrelationId>)>,
     logger: slog::Logger,
     metrics: ExecutorMetrics
 }
 
-impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
+impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone + 'static> Executor<T> {
     pub fn new(node: NodeId,
-               tx: Sender<ExecutorMsg<T>>,
+               config: &RabbleConfig,
+               tx: SyncSender<ExecutorMsg<T>>,
                rx: Receiver<ExecutorMsg<T>>,
-               cluster_tx: Sender<ClusterMsg<T>>,
+               cluster_tx: SyncSender<ClusterMsg<T>>,
+               ring: ClusterRing,
                logger: slog::Logger) -> Executor<T> {
-        let pid = Pid {
-            group: Some("rabble".to_string()),
-            name: "executor".to_string(),
-            node: node.clone()
-        };
+        let pid = Pid::executor(&node);
+        let logger = logger.new(o!("component" => "executor"));
+        let mailbox_sizes = MailboxSizes::new();
+        let workers = (0..config.executor_workers).map(|i| {
+            let (worker_tx, worker_rx) = mpsc::sync_channel(config.worker_channel_bound);
+            let counters = Arc::new(WorkerCounters::new());
+            let worker = Worker::new(i,
+                                     pid.clone(),
+                                     worker_rx,
+                                     tx.clone(),
+                                     cluster_tx.clone(),
+                                     counters.clone(),
+                                     mailbox_sizes.clone(),
+                                     &logger);
+            WorkerHandle {
+                tx: worker_tx,
+                backlog: VecDeque::new(),
+                counters: counters,
+                processes: 0,
+                worker: Some(worker),
+                thread: None
+            }
+        }).collect();
         Executor {
             pid: pid,
             node: node,
-            envelopes: Vec::new(),
             processes: HashMap::new(),
+            next_instance: 0,
+            workers: workers,
+            worker_exits: None,
+            supervisors: HashMap::new(),
+            parents: HashMap::new(),
+            monitors: Watches::new(),
+            links: Watches::new(),
             service_senders: HashMap::new(),
-            tx: tx,
+            names: HashMap::new(),
+            global_names: HashMap::new(),
+            groups: HashMap::new(),
+            published_groups: Some(HashSet::new()),
+            entity_types: HashMap::new(),
+            ring: ring,
+            handoffs: HashMap::new(),
             rx: rx,
             cluster_tx: cluster_tx,
-            timer_wheel: CopyWheel::new(vec![Resolution::TenMs, Resolution::Sec, Resolution::Min]),
-            logger: logger.new(o!("component" => "executor")),
+            default_mailbox: config.default_mailbox,
+            mailbox_sizes: mailbox_sizes,
+            dropped_remote_envelopes: 0,
+            timer_wheel: CopyWheel::new(config.timer_resolutions.iter()
+                                              .map(|&r| Resolution::from(r))
+                                              .collect()),
+            logger: logger,
             metrics: ExecutorMetrics::new()
         }
     }
 
+    /// Return a handle to the mailbox sizes of the processes on this node
+    pub fn mailbox_sizes(&self) -> MailboxSizes {
+        self.mailbox_sizes.clone()
+    }
+
     /// Run the executor
     ///
-    ///This call blocks the current thread indefinitely.
+    /// This call spawns the worker threads and blocks the current thread until shutdown.
     pub fn run(mut self) {
+        self.spawn_workers();
         while let Ok(msg) = self.rx.recv() {
+            self.flush_worker_backlogs();
             match msg {
                 ExecutorMsg::Envelope(envelope) => {
                     self.metrics.received_envelopes += 1;
                     self.route(envelope);
                 },
-                ExecutorMsg::Start(pid, process) => self.start(pid, process),
+                ExecutorMsg::Forwarded(envelope) => {
+                    self.metrics.received_envelopes += 1;
+                    self.route_forwarded(envelope);
+                },
+                ExecutorMsg::DeadLetter(envelope, reason) => self.dead_letter(envelope, reason),
+                ExecutorMsg::Start(pid, process, mailbox) => {
+                    let mailbox = mailbox.unwrap_or(self.default_mailbox);
+                    self.start(pid, process, mailbox)
+                },
+                ExecutorMsg::StartSupervisor(pid, spec) => {
+                    self.register_supervisor(pid.clone(), spec);
+                    if self.start_children(&pid).is_err() {
+                        self.terminate(&pid);
+                        self.child_failed(pid);
+                    }
+                },
                 ExecutorMsg::Stop(pid) => self.stop(pid),
+                ExecutorMsg::Exited(pid, instance, reason) => self.exited(pid, instance, reason),
                 ExecutorMsg::RegisterService(pid, tx) => {
                     self.service_senders.insert(pid, tx);
                 },
+                ExecutorMsg::UnregisterService(pid) => self.unregister_service(pid),
+                ExecutorMsg::RegisterName(name, pid) => {
+                    self.names.insert(name, pid);
+                },
+                ExecutorMsg::UnregisterName(name) => {
+                    self.names.remove(&name);
+                },
+                ExecutorMsg::GlobalNames(names) => self.global_names = names,
+                ExecutorMsg::RegisterEntityType(entity_type, factory) => {
+                    self.entity_types.insert(entity_type, factory);
+                },
+                ExecutorMsg::RingChanged => self.hand_off_entities(),
                 ExecutorMsg::GetStatus(correlation_id) => self.get_status(correlation_id),
+                ExecutorMsg::NodeDown(node) => self.node_down(node),
                 ExecutorMsg::Tick => self.tick(),
 
-                // Just return so the thread exits
-                ExecutorMsg::Shutdown => return
+                ExecutorMsg::Shutdown(reply) => return self.shutdown(reply)
+            }
+        }
+    }
+
+    fn spawn_workers(&mut self) {
+        let (exit_tx, exit_rx) = mpsc::channel();
+        for (i, handle) in self.workers.iter_mut().enumerate() {
+            let worker = handle.worker.take().unwrap();
+            let exit_tx = exit_tx.clone();
+            let thread = thread::Builder::new()
+                .name(format!("executor::{}::worker{}", self.node, i))
+                .spawn(move || {
+                    let _exit_tx = exit_tx;
+                    worker.run()
+                })
+                .unwrap();
+            handle.thread = Some(thread);
+        }
+        self.worker_exits = Some(exit_rx);
+    }
+
+    /// Stop routing envelopes, deliver a `Msg::Shutdown` to every process and service, and shut
+    /// down the cluster server once all workers have exited.
+    ///
+    /// The executor keeps draining its channel until the cluster server has finished, since the
+    /// cluster server may block sending to it.
+    fn shutdown(&mut self, reply: Option<Sender<ShutdownReport>>) {
+        info!(self.logger, "Shutting down");
+        let mut report = ShutdownReport::default();
+        report.processes = self.processes.len();
+        report.services = self.service_senders.len();
+        for (pid, tx) in self.service_senders.iter() {
+            // The service may have already exited
+            let _ = tx.send(Envelope::new(pid.clone(), self.pid.clone(), Msg::Shutdown, None));
+        }
+        report.dropped_envelopes = self.shutdown_workers() +
+            self.handoffs.values().map(|buffered| buffered.len()).sum::<usize>();
+
+        let (tx, rx) = mpsc::channel();
+        if let Err(_) = self.cluster_tx.send(ClusterMsg::Shutdown(report, tx)) {
+            return;
+        }
+        let mut dropped = 0;
+        loop {
+            dropped += self.drain();
+            match rx.recv_timeout(::std::time::Duration::from_millis(10)) {
+                Ok(mut report) => {
+                    report.dropped_envelopes += dropped + self.drain();
+                    if let Some(reply) = reply {
+                        let _ = reply.send(report);
+                    }
+                    return;
+                },
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return
             }
         }
     }
 
-    fn get_status(&self, correlation_id: CorrelationId) {
+    /// Shutdown all workers and wait for them to exit. Returns the number of envelopes they
+    /// dropped while shutting down, along with any envelopes drained from the executor channel.
+    ///
+    /// The executor channel is drained while waiting, since the cluster server may block sending
+    /// to the executor while a worker blocks sending to the cluster server.
+    fn shutdown_workers(&mut self) -> usize {
+        for index in 0..self.workers.len() {
+            self.send_to_worker(index, WorkerMsg::Shutdown);
+        }
+        let mut dropped = 0;
+        if let Some(exits) = self.worker_exits.take() {
+            loop {
+                self.flush_worker_backlogs();
+                dropped += self.drain();
+                match exits.recv_timeout(::std::time::Duration::from_millis(10)) {
+                    Err(RecvTimeoutError::Disconnected) => break,
+                    _ => ()
+                }
+            }
+        }
+        for handle in self.workers.iter_mut() {
+            if let Some(thread) = handle.thread.take() {
+                match thread.join() {
+                    Ok(n) => dropped += n,
+                    Err(_) => error!(self.logger, "Executor worker thread panicked")
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Discard all messages sent to the executor after it stopped routing. Returns the number of
+    /// discarded envelopes.
+    fn drain(&mut self) -> usize {
+        let mut dropped = 0;
+        while let Ok(msg) = self.rx.try_recv() {
+            match msg {
+                ExecutorMsg::Envelope(_) |
+                ExecutorMsg::Forwarded(_) |
+                ExecutorMsg::DeadLetter(..) => dropped += 1,
+                _ => ()
+            }
+        }
+        dropped
+    }
+
+    fn get_status(&mut self, correlation_id: CorrelationId) {
         let status = ExecutorStatus {
             total_processes: self.processes.len(),
-            services: self.service_senders.keys().cloned().collect()
+            services: self.service_senders.keys().cloned().collect(),
+            workers: self.workers.iter().map(|w| {
+                WorkerStatus {
+                    processes: w.processes,
+                    pending_envelopes: w.counters.pending.load(Ordering::Relaxed)
+                }
+            }).collect()
         };
         let envelope = Envelope {
             to: correlation_id.pid.clone(),
@@ -95,20 +340,338 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
         self.route_to_service(envelope);
     }
 
-    fn start(&mut self, pid: Pid, mut process: Box<Process<T>>) {
-        let envelopes = process.init(self.pid.clone());
-        self.processes.insert(pid, process);
-        for envelope in envelopes {
-            if envelope.to == self.pid {
-                self.handle_executor_envelope(envelope);
-            } else {
+    /// Choose the worker that owns the process with the given pid
+    fn worker_index(&self, pid: &Pid) -> usize {
+        let mut hasher = DefaultHasher::new();
+        pid.hash(&mut hasher);
+        (hasher.finish() % self.workers.len() as u64) as usize
+    }
+
+    fn start(&mut self, pid: Pid, process: Box<Process<T>>, mailbox: MailboxConfig) {
+        // Replace any running process with the same pid. The old instance is stopped first so
+        // that its worker discards its mailbox.
+        if let Some((index, _)) = self.processes.remove(&pid) {
+            self.workers[index].processes -= 1;
+            self.send_to_worker(index, WorkerMsg::Stop(pid.clone()));
+        }
+        let index = self.worker_index(&pid);
+        let instance = self.next_instance;
+        self.next_instance += 1;
+        self.processes.insert(pid.clone(), (index, instance));
+        self.workers[index].processes += 1;
+        if let Some(ref group) = pid.group {
+            self.groups.entry(group.clone()).or_insert_with(HashSet::new).insert(pid.clone());
+        }
+        self.publish_groups();
+        // The worker calls process.init(), so any envelopes it returns are sent from its thread
+        self.send_to_worker(index, WorkerMsg::Start(pid, instance, process, mailbox));
+    }
+
+    /// A process running on a worker panicked
+    fn exited(&mut self, pid: Pid, instance: u64, reason: DownReason) {
+        // Ignore exits of processes that were already stopped or restarted
+        match self.processes.get(&pid) {
+            Some(&(_, running)) if running == instance => (),
+            _ => return
+        }
+        let (index, _) = self.processes.remove(&pid).unwrap();
+        self.workers[index].processes -= 1;
+        match reason {
+            DownReason::Panicked(reason) => self.process_failed(pid, reason),
+            reason => self.process_exited(&pid, reason)
+        }
+    }
+
+    /// Remove a process or supervisor from the executor.
+    ///
+    /// Stopping a supervisor stops all of its children. Explicitly stopped children are removed
+    /// from their supervisor and will not be restarted.
+    fn stop(&mut self, pid: Pid) {
+        if let Some(supervisor) = self.parents.remove(&pid) {
+            if let Some(supervisor) = self.supervisors.get_mut(&supervisor) {
+                supervisor.remove_child(&pid);
+            }
+        }
+        self.terminate(&pid);
+        self.remove_supervisor(&pid);
+    }
+
+    /// Add a supervisor, and recursively any supervisors in its children, to the executor. Note
+    /// that this does not start any children.
+    fn register_supervisor(&mut self, pid: Pid, spec: SupervisorSpec<T>) {
+        let SupervisorSpec {strategy, max_restarts, max_seconds, children} = spec;
+        let mut entries = Vec::with_capacity(children.len());
+        for ChildSpec {pid: child_pid, child} in children {
+            self.parents.insert(child_pid.clone(), pid.clone());
+            match child {
+                Child::Worker(factory) => entries.push((child_pid, SupervisedChild::Worker(factory))),
+                Child::Supervisor(spec) => {
+                    self.register_supervisor(child_pid.clone(), spec);
+                    entries.push((child_pid, SupervisedChild::Supervisor));
+                }
+            }
+        }
+        let supervisor = Supervisor::new(strategy, max_restarts, max_seconds, entries);
+        self.supervisors.insert(pid, supervisor);
+    }
+
+    /// Remove a supervisor and all supervisors below it in the tree
+    fn remove_supervisor(&mut self, pid: &Pid) {
+        if let Some(supervisor) = self.supervisors.remove(pid) {
+            for (child, _) in supervisor.children {
+                self.parents.remove(&child);
+                self.remove_supervisor(&child);
+            }
+        }
+    }
+
+    /// Start all children of a supervisor in order
+    fn start_children(&mut self, supervisor: &Pid) -> Result<(), Pid> {
+        let num_children = self.supervisors.get(supervisor).map_or(0, |s| s.children.len());
+        self.start_children_at(supervisor, (0..num_children).collect())
+    }
+
+    /// Start the children of a supervisor at the given indexes in order, stopping at the first
+    /// child that fails to start. Return the pid of that child.
+    fn start_children_at(&mut self, supervisor: &Pid, indexes: Vec<usize>) -> Result<(), Pid> {
+        for index in indexes {
+            try!(self.start_child(supervisor, index));
+        }
+        Ok(())
+    }
+
+    /// Start a child of a supervisor. A worker fails to start if its factory panics, and a nested
+    /// supervisor fails to start if any of its children fail to start.
+    fn start_child(&mut self, supervisor: &Pid, index: usize) -> Result<(), Pid> {
+        let (pid, process) = match self.supervisors.get(supervisor)
+                                                   .and_then(|s| s.children.get(index)) {
+            Some(&(ref pid, SupervisedChild::Worker(ref factory))) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| factory())) {
+                    Ok(process) => (pid.clone(), Some(process)),
+                    Err(payload) => {
+                        error!(self.logger, "Process factory panicked";
+                               "pid" => pid.to_string(), "reason" => panic_reason(payload));
+                        return Err(pid.clone());
+                    }
+                }
+            },
+            Some(&(ref pid, SupervisedChild::Supervisor)) => (pid.clone(), None),
+            None => return Ok(())
+        };
+        match process {
+            Some(process) => {
+                let mailbox = self.default_mailbox;
+                self.start(pid, process, mailbox);
+                Ok(())
+            },
+            None => {
+                if let Some(child) = self.supervisors.get_mut(&pid) {
+                    child.clear_restarts();
+                }
+                if self.start_children(&pid).is_err() {
+                    self.terminate(&pid);
+                    return Err(pid);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Stop a running process, or all children of a supervisor in reverse start order.
+    fn terminate(&mut self, pid: &Pid) {
+        let children = self.supervisors.get(pid).map(|s| s.child_pids());
+        match children {
+            Some(children) => {
+                for child in children.iter().rev() {
+                    self.terminate(child);
+                }
+            },
+            None => {
+                if let Some((index, _)) = self.processes.remove(pid) {
+                    self.workers[index].processes -= 1;
+                    self.send_to_worker(index, WorkerMsg::Stop(pid.clone()));
+                    self.process_exited(pid, DownReason::Stopped);
+                }
+            }
+        }
+    }
+
+    /// Remove a registered service, notifying any pids monitoring or linked to it
+    fn unregister_service(&mut self, pid: Pid) {
+        if self.service_senders.remove(&pid).is_some() {
+            self.process_exited(&pid, DownReason::Stopped);
+        }
+    }
+
+    /// A process panicked. It has already been removed from the executor.
+    fn process_failed(&mut self, pid: Pid, reason: String) {
+        error!(self.logger, "Process failed"; "pid" => pid.to_string(), "reason" => reason.clone());
+        self.process_exited(&pid, DownReason::Panicked(reason));
+        self.child_failed(pid);
+    }
+
+    /// Notify all monitoring and linked pids that a process terminated, and remove any local names
+    /// registered for it. If the process was an entity being handed off, the envelopes held for it
+    /// are routed to its new owner.
+    ///
+    /// Executors on other nodes are told to drop the monitors the process held on their pids.
+    /// Linked pids on other nodes receive an `Exit`, which removes the link on their node.
+    fn process_exited(&mut self, pid: &Pid, reason: DownReason) {
+        self.names.retain(|_, registered| registered != pid);
+        self.leave_group(pid);
+        for target in self.monitors.targets(pid) {
+            if target.node != self.node {
+                let to = Pid::executor(&target.node);
+                self.route(Envelope::new(to, pid.clone(), Msg::Demonitor(target), None));
+            }
+        }
+        for watcher in self.monitors.remove_pid(pid) {
+            let msg = Msg::Down {pid: pid.clone(), reason: reason.clone()};
+            self.route(Envelope::new(watcher, self.pid.clone(), msg, None));
+        }
+        for linked in self.links.remove_pid(pid) {
+            let msg = Msg::Exit {pid: pid.clone(), reason: reason.clone()};
+            self.route(Envelope::new(linked, self.pid.clone(), msg, None));
+        }
+        if let Some(buffered) = self.handoffs.remove(pid) {
+            debug!(self.logger, "Handed off entity";
+                   "pid" => pid.to_string(), "buffered" => buffered.len());
+            for envelope in buffered {
                 self.route(envelope);
             }
         }
     }
 
-    fn stop(&mut self, pid: Pid) {
-        self.processes.remove(&pid);
+    /// The connection to a node was lost. Notify local pids monitoring or linked to pids on that
+    /// node, and forget all monitors and links held by pids on that node.
+    fn node_down(&mut self, node: NodeId) {
+        for (pid, watcher) in self.monitors.remove_node(&node) {
+            let msg = Msg::Down {pid: pid, reason: DownReason::NodeDown};
+            self.route(Envelope::new(watcher, self.pid.clone(), msg, None));
+        }
+        for (pid, linked) in self.links.remove_node(&node) {
+            let msg = Msg::Exit {pid: pid, reason: DownReason::NodeDown};
+            self.route(Envelope::new(linked, self.pid.clone(), msg, None));
+        }
+    }
+
+    /// Resolve a registered name. Names registered on this node shadow cluster wide names.
+    fn whereis(&self, name: &str) -> Option<Pid> {
+        self.names.get(name).or_else(|| self.global_names.get(name)).cloned()
+    }
+
+    /// Return true if there is a process, supervisor or service with the given pid on this node
+    fn is_local_pid(&self, pid: &Pid) -> bool {
+        self.processes.contains_key(pid) ||
+            self.supervisors.contains_key(pid) ||
+            self.service_senders.contains_key(pid)
+    }
+
+    fn monitor(&mut self, target: Pid, watcher: Pid) {
+        if target.node != self.node {
+            // Track the monitor locally in case the node goes down, and have the remote executor
+            // send the `Down` directly to the watcher.
+            self.monitors.add(target.clone(), watcher.clone());
+            let to = Pid::executor(&target.node);
+            return self.route(Envelope::new(to, watcher, Msg::Monitor(target), None));
+        }
+        if self.is_local_pid(&target) {
+            self.monitors.add(target, watcher);
+        } else {
+            let msg = Msg::Down {pid: target, reason: DownReason::NoProcess};
+            self.route(Envelope::new(watcher, self.pid.clone(), msg, None));
+        }
+    }
+
+    fn demonitor(&mut self, target: Pid, watcher: Pid) {
+        self.monitors.remove(&target, &watcher);
+        if target.node != self.node {
+            let to = Pid::executor(&target.node);
+            self.route(Envelope::new(to, watcher, Msg::Demonitor(target), None));
+        }
+    }
+
+    fn link(&mut self, target: Pid, from: Pid) {
+        if target.node == self.node && !self.is_local_pid(&target) {
+            let msg = Msg::Exit {pid: target, reason: DownReason::NoProcess};
+            return self.route(Envelope::new(from, self.pid.clone(), msg, None));
+        }
+        self.links.add(target.clone(), from.clone());
+        self.links.add(from.clone(), target.clone());
+        if target.node != self.node {
+            let to = Pid::executor(&target.node);
+            self.route(Envelope::new(to, from, Msg::Link(target), None));
+        }
+    }
+
+    fn unlink(&mut self, target: Pid, from: Pid) {
+        self.links.remove(&target, &from);
+        self.links.remove(&from, &target);
+        if target.node != self.node {
+            let to = Pid::executor(&target.node);
+            self.route(Envelope::new(to, from, Msg::Unlink(target), None));
+        }
+    }
+
+    /// A remote executor sent a `Down` or `Exit` notification directly to a local pid. The
+    /// watch no longer exists on the remote node, so remove the local record of it.
+    fn remove_remote_watch(&mut self, envelope: &Envelope<T>) {
+        match envelope.msg {
+            Msg::Down {ref pid, ..} if pid.node != self.node => {
+                self.monitors.remove(pid, &envelope.to);
+            },
+            Msg::Exit {ref pid, ..} if pid.node != self.node => {
+                self.links.remove(pid, &envelope.to);
+                self.links.remove(&envelope.to, pid);
+            },
+            _ => ()
+        }
+    }
+
+    /// Restart children of the failed child's supervisor according to its restart strategy.
+    ///
+    /// If a restarted child fails to start, the children after it are not started, and the failed
+    /// start is handled like any other failure of that child. If the supervisor exceeded its
+    /// maximum restart intensity, stop all of its children and escalate the failure to its own
+    /// supervisor.
+    fn child_failed(&mut self, pid: Pid) {
+        let mut failed = pid;
+        loop {
+            let supervisor = match self.parents.get(&failed).cloned() {
+                Some(supervisor) => supervisor,
+                None => {
+                    // Unsupervised processes are just removed. A failed top level supervisor is
+                    // gone.
+                    self.remove_supervisor(&failed);
+                    return;
+                }
+            };
+            let indexes = match self.supervisors.get_mut(&supervisor) {
+                Some(s) => if s.record_restart() { Some(s.restart_indexes(&failed)) } else { None },
+                None => return
+            };
+            failed = match indexes {
+                Some(indexes) => {
+                    for &index in indexes.iter().rev() {
+                        let child = self.supervisors.get(&supervisor)
+                                                    .and_then(|s| s.child_pid(index));
+                        if let Some(child) = child {
+                            self.terminate(&child);
+                        }
+                    }
+                    match self.start_children_at(&supervisor, indexes) {
+                        Ok(()) => return,
+                        Err(child) => child
+                    }
+                },
+                None => {
+                    error!(self.logger, "Supervisor reached maximum restart intensity";
+                           "pid" => supervisor.to_string());
+                    self.terminate(&supervisor);
+                    supervisor
+                }
+            };
+        }
     }
 
     fn tick(&mut self) {
@@ -116,25 +679,158 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
             let envelope = Envelope::new(pid, self.pid.clone(), Msg::Timeout, c_id);
             let _ = self.route_to_process(envelope);
         }
+        // Retry publishing groups if the cluster channel was full
+        self.publish_groups();
     }
 
-    /// Route envelopes to local or remote processes
+    fn leave_group(&mut self, pid: &Pid) {
+        let empty = match pid.group.as_ref().and_then(|group| self.groups.get_mut(group)) {
+            Some(members) => {
+                members.remove(pid);
+                members.is_empty()
+            },
+            None => return
+        };
+        if empty {
+            self.groups.remove(pid.group.as_ref().unwrap());
+        }
+        self.publish_groups();
+    }
+
+    /// Tell the cluster server about any change to the set of groups with local processes, so
+    /// that it can forward envelopes for cluster wide groups to this node.
     ///
-    /// Retrieve any envelopes from processes handling local messages and put them on either the
-    /// executor or the cluster channel depending upon whether they are local or remote.
+    /// The cluster server sends messages to the executor, so this never blocks. If the cluster
+    /// channel is full, the groups are sent again on the next tick.
+    fn publish_groups(&mut self) {
+        let groups: HashSet<String> = self.groups.keys().cloned().collect();
+        if self.published_groups.as_ref() == Some(&groups) {
+            return;
+        }
+        match self.cluster_tx.try_send(ClusterMsg::LocalGroups(groups.clone())) {
+            Ok(()) => self.published_groups = Some(groups),
+            Err(_) => self.published_groups = None
+        }
+    }
+
+    /// Return the node owning an entity on the cluster ring
+    fn entity_owner(&self, pid: &Pid) -> Option<NodeId> {
+        pid.entity_type().and_then(|entity_type| self.ring.owner(&(entity_type, &pid.name)))
+    }
+
+    /// Start the entity an envelope is addressed to if it isn't running yet. Entities are only
+    /// started by user messages, so that late monitor notifications or timeouts for an entity
+    /// that was handed off don't start it again.
+    fn start_entity(&mut self, envelope: &Envelope<T>) {
+        if self.processes.contains_key(&envelope.to) {
+            return;
+        }
+        if let Msg::User(_) = envelope.msg {
+            let process = match envelope.to.entity_type().and_then(|t| self.entity_types.get(t)) {
+                Some(factory) => factory(&envelope.to),
+                None => return
+            };
+            self.metrics.entities_started += 1;
+            let mailbox = self.default_mailbox;
+            self.start(envelope.to.clone(), process, mailbox);
+        }
+    }
+
+    /// Hand off the local entities whose keys are now owned by another node. Each entity handles
+    /// the envelopes already in its mailbox followed by a `Msg::Shutdown`, and is then stopped.
+    /// Envelopes for the entity are held until it has stopped, and then routed to the new owner.
+    fn hand_off_entities(&mut self) {
+        let moved: Vec<(Pid, usize)> = self.processes.iter()
+            .filter(|&(pid, _)| {
+                !self.handoffs.contains_key(pid) &&
+                    self.entity_owner(pid).map_or(false, |owner| owner != self.node)
+            })
+            .map(|(pid, &(index, _))| (pid.clone(), index))
+            .collect();
+        for (pid, index) in moved {
+            debug!(self.logger, "Handing off entity"; "pid" => pid.to_string());
+            self.metrics.entity_handoffs += 1;
+            self.handoffs.insert(pid.clone(), Vec::new());
+            self.send_to_worker(index, WorkerMsg::HandOff(pid));
+        }
+    }
+
+    /// Deliver a copy of an envelope to every local process in a group
+    fn route_to_group(&mut self, envelope: Envelope<T>) {
+        let members: Vec<Pid> = match self.groups.get(&envelope.to.name) {
+            Some(members) => members.iter().cloned().collect(),
+            None => return
+        };
+        for pid in members {
+            let mut envelope = envelope.clone();
+            envelope.to = pid;
+            if let Err(envelope) = self.route_to_process(envelope) {
+                self.dead_letter(envelope, DeadLetterReason::NoProcess);
+            }
+        }
+    }
+
+    /// Route envelopes to local or remote processes
     ///
-    /// Note that all envelopes sent to an executor are sent from the local cluster server and must
-    /// be addressed to local processes.
-    fn route(&mut self, envelope: Envelope<T>) {
+    /// Envelopes for local processes are forwarded to the worker owning the process. Envelopes for
+    /// remote processes are put on the cluster channel. Envelopes for names registered on this node
+    /// are routed to the registered pid, and envelopes for groups to each member of the group.
+    /// Envelopes for entities are forwarded to the node owning the entity, which starts it if
+    /// necessary. Envelopes for entities being handed off are held until the handoff completes.
+    fn route(&mut self, mut envelope: Envelope<T>) {
+        if envelope.to.is_group_members() && envelope.to.node == self.node {
+            return self.route_to_group(envelope);
+        }
+        if envelope.to.is_cluster_group_members() && envelope.to.node == self.node {
+            // The cluster server forwards the envelope to the members on other nodes
+            self.send_to_cluster(envelope.clone());
+            return self.route_to_group(envelope);
+        }
+        if envelope.to.is_entity() && envelope.to.node == self.node {
+            if let Some(buffered) = self.handoffs.get_mut(&envelope.to) {
+                buffered.push(envelope);
+                return;
+            }
+            if let Some(owner) = self.entity_owner(&envelope.to) {
+                if owner != self.node {
+                    self.metrics.forwarded_entity_envelopes += 1;
+                    envelope.to.node = owner;
+                    return self.try_send_to_cluster(ClusterMsg::Forwarded(envelope));
+                }
+            }
+            self.start_entity(&envelope);
+        }
+        if envelope.to.is_named() && envelope.to.node == self.node {
+            match self.whereis(&envelope.to.name) {
+                Some(pid) => envelope.to = pid,
+                None => return self.dead_letter(envelope, DeadLetterReason::NoProcess)
+            }
+        }
         if self.node != envelope.to.node {
-            self.cluster_tx.send(ClusterMsg::Envelope(envelope)).unwrap();
-            return;
+            return self.send_to_cluster(envelope);
         }
+        self.remove_remote_watch(&envelope);
         if let Err(envelope) = self.route_to_process(envelope) {
             self.route_to_service(envelope);
         }
     }
 
+    /// Route an envelope for an entity that another node forwarded to this one
+    ///
+    /// Nodes with different views of the cluster membership may disagree on the owner of an
+    /// entity, so forwarded envelopes are never forwarded again. If another node owns the entity
+    /// according to this node's ring, the envelope is delivered to the entity if it's still running
+    /// here, and sent to the dead letter service otherwise.
+    fn route_forwarded(&mut self, envelope: Envelope<T>) {
+        let owned = self.entity_owner(&envelope.to).map_or(true, |owner| owner == self.node);
+        if owned || envelope.to.node != self.node || self.handoffs.contains_key(&envelope.to) {
+            return self.route(envelope);
+        }
+        if let Err(envelope) = self.route_to_process(envelope) {
+            self.dead_letter(envelope, DeadLetterReason::NotOwner);
+        }
+    }
+
     /// Route an envelope to a process if it exists on this node.
     ///
     /// Return Ok(()) if the process exists, Err(envelope) otherwise.
@@ -144,48 +840,146 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
             return Ok(());
         }
 
-        if &envelope.to.name == "cluster_server" &&
-            envelope.to.group.as_ref().unwrap() == "rabble"
+        // The pub/sub broker runs in the cluster server
+        if (&envelope.to.name == "cluster_server" &&
+            envelope.to.group.as_ref().unwrap() == "rabble") ||
+            envelope.to == Pid::broker(&self.node)
         {
-            self.cluster_tx.send(ClusterMsg::Envelope(envelope)).unwrap();
+            self.send_to_cluster(envelope);
             return Ok(());
         }
 
-        if let Some(process) = self.processes.get_mut(&envelope.to) {
-            let Envelope {from, msg, correlation_id, ..} = envelope;
-            process.handle(msg, from, correlation_id, &mut self.envelopes);
-        } else {
-            return Err(envelope);
+        let index = match self.processes.get(&envelope.to) {
+            Some(&(index, _)) => index,
+            None => return Err(envelope)
         };
-
-        // Take envelopes out of self temporarily so we don't get a borrowck error
-        let mut envelopes = mem::replace(&mut self.envelopes, Vec::new());
-        for envelope in envelopes.drain(..) {
-            if envelope.to == self.pid {
-                self.handle_executor_envelope(envelope);
-                continue;
-            }
-            if envelope.to.node == self.node {
-                // This won't ever fail because we hold a ref to both ends of the channel
-                self.tx.send(ExecutorMsg::Envelope(envelope)).unwrap();
+        let result = {
+            let worker = &self.workers[index];
+            worker.counters.pending.fetch_add(1, Ordering::Relaxed);
+            if worker.backlog.is_empty() {
+                worker.tx.try_send(WorkerMsg::Envelope(envelope))
             } else {
-                self.cluster_tx.send(ClusterMsg::Envelope(envelope)).unwrap();
+                Err(TrySendError::Full(WorkerMsg::Envelope(envelope)))
             }
+        };
+        match result {
+            Ok(()) => (),
+            Err(TrySendError::Full(WorkerMsg::Envelope(envelope))) => {
+                self.workers[index].counters.pending.fetch_sub(1, Ordering::Relaxed);
+                self.dead_letter(envelope, DeadLetterReason::MailboxFull);
+            },
+            // The worker has already exited during shutdown
+            Err(_) => ()
         }
-        // Return the allocated vec back to self
-        let _ = mem::replace(&mut self.envelopes, envelopes);
         Ok(())
     }
 
-    /// Route an envelope to a service on this node
-    fn route_to_service(&self, envelope: Envelope<T>) {
-        if let Some(tx) = self.service_senders.get(&envelope.to) {
-            tx.send(envelope).unwrap();
-        } else {
-            warn!(self.logger, "Failed to find service"; "pid" => envelope.to.to_string());
+    /// Send a control message to a worker.
+    ///
+    /// The worker may be blocked sending to the executor, so the executor never blocks here.
+    /// Messages that don't fit in the worker channel are kept in order and sent once the worker
+    /// catches up.
+    fn send_to_worker(&mut self, index: usize, msg: WorkerMsg<T>) {
+        let worker = &mut self.workers[index];
+        if !worker.backlog.is_empty() {
+            return worker.backlog.push_back(msg);
+        }
+        if let Err(TrySendError::Full(msg)) = worker.tx.try_send(msg) {
+            worker.backlog.push_back(msg);
+        }
+    }
+
+    /// Send as many held control messages to the workers as fit in their channels
+    fn flush_worker_backlogs(&mut self) {
+        for worker in self.workers.iter_mut() {
+            while let Some(msg) = worker.backlog.pop_front() {
+                if let Err(TrySendError::Full(msg)) = worker.tx.try_send(msg) {
+                    worker.backlog.push_front(msg);
+                    break;
+                }
+            }
         }
     }
 
+    /// Put an envelope on the cluster channel.
+    fn send_to_cluster(&mut self, envelope: Envelope<T>) {
+        self.try_send_to_cluster(ClusterMsg::Envelope(envelope))
+    }
+
+    /// Put a message carrying an envelope on the cluster channel.
+    ///
+    /// The cluster server sends messages to the executor, so blocking here could deadlock.
+    /// Envelopes are returned to their senders as undeliverable instead if the cluster channel is
+    /// full.
+    fn try_send_to_cluster(&mut self, msg: ClusterMsg<T>) {
+        match self.cluster_tx.try_send(msg) {
+            Ok(()) => (),
+            Err(TrySendError::Full(ClusterMsg::Envelope(envelope))) |
+            Err(TrySendError::Full(ClusterMsg::Forwarded(envelope))) => {
+                self.dropped_remote_envelopes += 1;
+                warn!(self.logger, "Cluster channel full. Dropping envelope.";
+                      "to" => envelope.to.to_string());
+                self.undeliverable(envelope, UndeliverableReason::QueueFull);
+            },
+            // The cluster server has exited during shutdown
+            Err(_) => ()
+        }
+    }
+
+    /// Notify the sender of an envelope for a remote pid that it could not be delivered
+    fn undeliverable(&mut self, envelope: Envelope<T>, reason: UndeliverableReason) {
+        // Never bounce a bounce
+        if let Msg::Undeliverable(_) = envelope.msg {
+            return;
+        }
+        let Envelope {to, from, correlation_id, ..} = envelope;
+        self.route(Envelope::new(from, to, Msg::Undeliverable(reason), correlation_id));
+    }
+
+    /// Route an envelope to a service on this node, or to the dead letter service if there is no
+    /// such service.
+    ///
+    /// A service whose receiver was dropped without unregistering it is unregistered.
+    fn route_to_service(&mut self, envelope: Envelope<T>) {
+        if !self.service_senders.contains_key(&envelope.to) {
+            self.dead_letter(envelope, DeadLetterReason::NoProcess);
+            return;
+        }
+        let to = envelope.to.clone();
+        if self.service_senders[&to].send(envelope).is_err() {
+            warn!(self.logger, "Service receiver dropped. Unregistering it";
+                  "pid" => to.to_string());
+            self.unregister_service(to);
+        }
+    }
+
+    /// Forward an envelope that could not be delivered to the dead letter service, if one is
+    /// registered on this node.
+    ///
+    /// Envelopes for the dead letter service itself and dead letters that can't be delivered are
+    /// only logged, so they never loop.
+    fn dead_letter(&mut self, envelope: Envelope<T>, reason: DeadLetterReason) {
+        match reason {
+            DeadLetterReason::NoProcess => self.metrics.dead_letters_no_process += 1,
+            DeadLetterReason::Stopped => self.metrics.dead_letters_stopped += 1,
+            DeadLetterReason::UnknownNode => self.metrics.dead_letters_unknown_node += 1,
+            DeadLetterReason::MailboxFull => self.metrics.dead_letters_mailbox_full += 1,
+            DeadLetterReason::NotOwner => self.metrics.dead_letters_not_owner += 1
+        }
+        let dead_letters = Pid::dead_letters(&self.node);
+        let is_dead_letter = match envelope.msg {
+            Msg::DeadLetter {..} => true,
+            _ => false
+        };
+        if is_dead_letter || envelope.to == dead_letters || !self.is_local_pid(&dead_letters) {
+            warn!(self.logger, "Failed to deliver envelope";
+                  "pid" => envelope.to.to_string(), "reason" => format!("{:?}", reason));
+            return;
+        }
+        let msg = Msg::DeadLetter {envelope: Box::new(envelope), reason: reason};
+        self.route(Envelope::new(dead_letters, self.pid.clone(), msg, None));
+    }
+
     fn handle_executor_envelope(&mut self, envelope: Envelope<T>) {
         let Envelope {from, msg, correlation_id, ..} = envelope;
         match msg {
@@ -199,6 +993,10 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
                 self.metrics.timers_cancelled += 1;
             }
             Msg::GetMetrics => self.send_metrics(from, correlation_id),
+            Msg::Monitor(pid) => self.monitor(pid, from),
+            Msg::Demonitor(pid) => self.demonitor(pid, from),
+            Msg::Link(pid) => self.link(pid, from),
+            Msg::Unlink(pid) => self.unlink(pid, from),
             _ => error!(self.logger, "Invalid message sent to executor";
                         "from" => from.to_string(), "msg" => format!("{:?}", msg))
         }
@@ -207,6 +1005,16 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
     fn send_metrics(&mut self, from: Pid, correlation_id: Option<CorrelationId>) {
         self.metrics.processes = self.processes.len() as i64;
         self.metrics.services = self.service_senders.len() as i64;
+        self.metrics.monitors = self.monitors.len() as i64;
+        self.metrics.links = self.links.len() as i64;
+        self.metrics.dropped_envelopes =
+            self.workers.iter().map(|w| w.counters.dropped.load(Ordering::Relaxed) as u64).sum();
+        self.metrics.rejected_envelopes =
+            self.workers.iter().map(|w| w.counters.rejected.load(Ordering::Relaxed) as u64).sum();
+        self.metrics.dropped_dead_letters = self.workers.iter().map(|w| {
+            w.counters.dropped_dead_letters.load(Ordering::Relaxed) as u64
+        }).sum();
+        self.metrics.dropped_remote_envelopes = self.dropped_remote_envelopes;
         let envelope = Envelope {
             to: from,
             from: self.pid.clone(),
@@ -216,4 +1024,3 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
         self.route(envelope);
     }
 }
-
@@ -0,0 +1,105 @@
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashMap;
+use pid::Pid;
+
+/// What to do with an envelope sent to a process whose mailbox is full
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Discard the envelope being delivered
+    DropNewest,
+
+    /// Discard the oldest envelope in the mailbox to make room for the one being delivered
+    DropOldest,
+
+    /// Discard the envelope being delivered and send a `Msg::MailboxFull` back to its sender
+    Reject
+}
+
+/// The configuration of a process mailbox. Mailboxes are unbounded if `capacity` is `None`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MailboxConfig {
+    pub capacity: Option<usize>,
+    pub overflow: OverflowPolicy
+}
+
+impl MailboxConfig {
+    pub fn unbounded() -> MailboxConfig {
+        MailboxConfig {
+            capacity: None,
+            overflow: OverflowPolicy::DropNewest
+        }
+    }
+
+    pub fn bounded(capacity: usize, overflow: OverflowPolicy) -> MailboxConfig {
+        MailboxConfig {
+            capacity: Some(capacity),
+            overflow: overflow
+        }
+    }
+}
+
+impl Default for MailboxConfig {
+    fn default() -> MailboxConfig {
+        MailboxConfig::unbounded()
+    }
+}
+
+/// Counters shared between a worker and the executor
+pub struct WorkerCounters {
+    /// Envelopes routed to the worker that have not been handled yet
+    pub pending: AtomicUsize,
+
+    /// Envelopes discarded because of a full mailbox
+    pub dropped: AtomicUsize,
+
+    /// Envelopes discarded because of a full mailbox, with a `MailboxFull` sent to the sender
+    pub rejected: AtomicUsize,
+
+    /// Dead letters discarded because the executor channel was full
+    pub dropped_dead_letters: AtomicUsize
+}
+
+impl WorkerCounters {
+    pub fn new() -> WorkerCounters {
+        WorkerCounters {
+            pending: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+            dropped_dead_letters: AtomicUsize::new(0)
+        }
+    }
+}
+
+/// The number of envelopes waiting in the mailbox of each process on a node
+///
+/// Workers register the mailbox of each process they start. Handles are cheap to clone and can be
+/// read from any thread, for example by a router using `RoutingStrategy::SmallestMailbox`.
+#[derive(Debug, Clone, Default)]
+pub struct MailboxSizes {
+    sizes: Arc<RwLock<HashMap<Pid, Arc<AtomicUsize>>>>
+}
+
+impl MailboxSizes {
+    pub fn new() -> MailboxSizes {
+        MailboxSizes::default()
+    }
+
+    /// Return the number of envelopes in the mailbox of a process, or `None` if the process isn't
+    /// running on this node
+    pub fn get(&self, pid: &Pid) -> Option<usize> {
+        let sizes = self.sizes.read().unwrap();
+        sizes.get(pid).map(|size| size.load(Ordering::Relaxed))
+    }
+
+    /// Start tracking the mailbox of a process. Returns the counter the owning worker updates.
+    pub fn register(&self, pid: Pid) -> Arc<AtomicUsize> {
+        let size = Arc::new(AtomicUsize::new(0));
+        self.sizes.write().unwrap().insert(pid, size.clone());
+        size
+    }
+
+    pub fn unregister(&self, pid: &Pid) {
+        self.sizes.write().unwrap().remove(pid);
+    }
+}
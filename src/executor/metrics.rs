@@ -3,7 +3,21 @@ use metrics::{Metric, Metrics};
 metrics!(ExecutorMetrics {
     processes: i64,
     services: i64,
+    monitors: i64,
+    links: i64,
     received_envelopes: u64,
     timers_started: u64,
-    timers_cancelled: u64
+    timers_cancelled: u64,
+    dropped_envelopes: u64,
+    rejected_envelopes: u64,
+    dropped_remote_envelopes: u64,
+    dead_letters_no_process: u64,
+    dead_letters_stopped: u64,
+    dead_letters_unknown_node: u64,
+    dead_letters_mailbox_full: u64,
+    dead_letters_not_owner: u64,
+    dropped_dead_letters: u64,
+    entities_started: u64,
+    entity_handoffs: u64,
+    forwarded_entity_envelopes: u64
 });
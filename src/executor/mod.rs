@@ -2,8 +2,12 @@ mod executor;
 mod status;
 mod msg;
 mod metrics;
+mod mailbox;
+mod watches;
+mod worker;
 
 pub use self::executor::Executor;
-pub use self::status::ExecutorStatus;
+pub use self::status::{ExecutorStatus, WorkerStatus};
 pub use self::msg::ExecutorMsg;
 pub use self::metrics::ExecutorMetrics;
+pub use self::mailbox::{MailboxConfig, OverflowPolicy, MailboxSizes};
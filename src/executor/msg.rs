@@ -1,15 +1,38 @@
+use std::sync::mpsc::Sender;
+use std::collections::HashMap;
 use envelope::Envelope;
-use process::Process;
+use process::{Process, EntityFactory};
+use supervisor::SupervisorSpec;
+use super::MailboxConfig;
 use pid::Pid;
+use node_id::NodeId;
+use msg::{DownReason, DeadLetterReason};
 use correlation_id::CorrelationId;
+use node::ShutdownReport;
 use amy;
 
 pub enum ExecutorMsg<T> {
-    Start(Pid, Box<Process<T>>),
+    // A mailbox config of `None` uses the executor's default
+    Start(Pid, Box<Process<T>>, Option<MailboxConfig>),
+    StartSupervisor(Pid, SupervisorSpec<T>),
     Stop(Pid),
+    Exited(Pid, u64, DownReason),
     Envelope(Envelope<T>),
+    // An envelope for an entity forwarded by another node, which is never forwarded again
+    Forwarded(Envelope<T>),
+    DeadLetter(Envelope<T>, DeadLetterReason),
     RegisterService(Pid, amy::Sender<Envelope<T>>),
+    UnregisterService(Pid),
+    RegisterName(String, Pid),
+    UnregisterName(String),
+    // Sent by the cluster server whenever the resolution of the global name registry changes
+    GlobalNames(HashMap<String, Pid>),
+    RegisterEntityType(String, EntityFactory<T>),
+    // Sent by the cluster server whenever nodes are added to or removed from the cluster ring
+    RingChanged,
     GetStatus(CorrelationId),
-    Shutdown,
+    NodeDown(NodeId),
+    // The report of an orderly shutdown is sent to the given sender once the node has stopped
+    Shutdown(Option<Sender<ShutdownReport>>),
     Tick
 }
@@ -4,5 +4,15 @@ use pid::Pid;
 pub struct ExecutorStatus {
     pub total_processes: usize,
     pub services: Vec<Pid>,
+    pub workers: Vec<WorkerStatus>
     //... Some stats
 }
+
+/// The load on a single executor worker thread
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WorkerStatus {
+    pub processes: usize,
+
+    /// Envelopes routed to processes on this worker that have not been handled yet
+    pub pending_envelopes: usize
+}
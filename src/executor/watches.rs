@@ -0,0 +1,88 @@
+use std::collections::{HashMap, HashSet};
+use pid::Pid;
+use node_id::NodeId;
+
+/// An index of which pids are watching which other pids. Used to track both monitors and links.
+///
+/// Both directions are indexed so that all state related to a pid can be removed when it
+/// terminates, whether it was watching or being watched.
+pub struct Watches {
+    // target -> watchers
+    watchers: HashMap<Pid, HashSet<Pid>>,
+    // watcher -> targets
+    targets: HashMap<Pid, HashSet<Pid>>
+}
+
+impl Watches {
+    pub fn new() -> Watches {
+        Watches {
+            watchers: HashMap::new(),
+            targets: HashMap::new()
+        }
+    }
+
+    pub fn add(&mut self, target: Pid, watcher: Pid) {
+        self.targets.entry(watcher.clone()).or_insert_with(HashSet::new).insert(target.clone());
+        self.watchers.entry(target).or_insert_with(HashSet::new).insert(watcher);
+    }
+
+    pub fn remove(&mut self, target: &Pid, watcher: &Pid) {
+        remove_entry(&mut self.watchers, target, watcher);
+        remove_entry(&mut self.targets, watcher, target);
+    }
+
+    /// Return all the pids watched by `watcher`
+    pub fn targets(&self, watcher: &Pid) -> Vec<Pid> {
+        self.targets.get(watcher).map_or_else(Vec::new, |targets| targets.iter().cloned().collect())
+    }
+
+    /// Return the number of (target, watcher) pairs
+    pub fn len(&self) -> usize {
+        self.watchers.values().map(|watchers| watchers.len()).sum()
+    }
+
+    /// Remove a terminated pid, returning all the pids that were watching it
+    pub fn remove_pid(&mut self, pid: &Pid) -> HashSet<Pid> {
+        if let Some(targets) = self.targets.remove(pid) {
+            for target in targets {
+                remove_entry(&mut self.watchers, &target, pid);
+            }
+        }
+        let watchers = self.watchers.remove(pid).unwrap_or_else(HashSet::new);
+        for watcher in watchers.iter() {
+            remove_entry(&mut self.targets, watcher, pid);
+        }
+        watchers
+    }
+
+    /// Remove all watches involving pids on `node`. Return the (target, watcher) pairs for all
+    /// targets on that node that are watched by pids on other nodes.
+    pub fn remove_node(&mut self, node: &NodeId) -> Vec<(Pid, Pid)> {
+        let on_node: Vec<Pid> = self.watchers.keys().chain(self.targets.keys())
+                                    .filter(|pid| pid.node == *node)
+                                    .cloned()
+                                    .collect();
+        let mut down = Vec::new();
+        for pid in on_node {
+            for watcher in self.remove_pid(&pid) {
+                if watcher.node != *node {
+                    down.push((pid.clone(), watcher));
+                }
+            }
+        }
+        down
+    }
+}
+
+fn remove_entry(map: &mut HashMap<Pid, HashSet<Pid>>, key: &Pid, val: &Pid) {
+    let empty = match map.get_mut(key) {
+        Some(set) => {
+            set.remove(val);
+            set.is_empty()
+        },
+        None => false
+    };
+    if empty {
+        map.remove(key);
+    }
+}
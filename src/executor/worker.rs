@@ -0,0 +1,410 @@
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::any::Any;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{SyncSender, Receiver, TrySendError};
+use std::collections::{HashMap, VecDeque};
+use slog;
+use envelope::Envelope;
+use pid::Pid;
+use process::Process;
+use node_id::NodeId;
+use msg::{Msg, DownReason, DeadLetterReason};
+use cluster::ClusterMsg;
+use super::ExecutorMsg;
+use super::mailbox::{MailboxConfig, OverflowPolicy, WorkerCounters, MailboxSizes};
+
+// The maximum number of envelopes a process handles before other processes get a turn
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Messages sent from the executor to its workers
+pub enum WorkerMsg<T> {
+    Start(Pid, u64, Box<Process<T>>, MailboxConfig),
+    Stop(Pid),
+    // Stop a process once it has handled the envelopes in its mailbox followed by a
+    // `Msg::Shutdown`
+    HandOff(Pid),
+    Envelope(Envelope<T>),
+    Shutdown
+}
+
+/// A running process along with the envelopes waiting to be handled by it
+struct Slot<T> {
+    instance: u64,
+    process: Box<Process<T>>,
+    mailbox: VecDeque<Envelope<T>>,
+    // The length of the mailbox, shared via `MailboxSizes`
+    size: Arc<AtomicUsize>,
+    config: MailboxConfig,
+    // Set when the process is being handed off to another node
+    handing_off: bool
+}
+
+/// A scheduler thread that owns a subset of the processes on a node.
+///
+/// Each process is owned by exactly one worker. Envelopes for a process are queued in its mailbox
+/// and processes with non-empty mailboxes are run in round robin order, so that a single busy
+/// process cannot starve the others owned by the same worker.
+pub struct Worker<T> {
+    executor_pid: Pid,
+    node: NodeId,
+    slots: HashMap<Pid, Slot<T>>,
+    run_queue: VecDeque<Pid>,
+    output: Vec<Envelope<T>>,
+    rx: Receiver<WorkerMsg<T>>,
+    executor_tx: SyncSender<ExecutorMsg<T>>,
+    cluster_tx: SyncSender<ClusterMsg<T>>,
+    counters: Arc<WorkerCounters>,
+    mailbox_sizes: MailboxSizes,
+    // Set once the worker receives a `WorkerMsg::Shutdown`, after which the executor no longer
+    // routes envelopes
+    shutting_down: bool,
+    dropped_on_shutdown: Cell<usize>,
+    logger: slog::Logger
+}
+
+impl<T: Send> Worker<T> {
+    pub fn new(index: usize,
+               executor_pid: Pid,
+               rx: Receiver<WorkerMsg<T>>,
+               executor_tx: SyncSender<ExecutorMsg<T>>,
+               cluster_tx: SyncSender<ClusterMsg<T>>,
+               counters: Arc<WorkerCounters>,
+               mailbox_sizes: MailboxSizes,
+               logger: &slog::Logger) -> Worker<T> {
+        Worker {
+            node: executor_pid.node.clone(),
+            executor_pid: executor_pid,
+            slots: HashMap::new(),
+            run_queue: VecDeque::new(),
+            output: Vec::new(),
+            rx: rx,
+            executor_tx: executor_tx,
+            cluster_tx: cluster_tx,
+            counters: counters,
+            mailbox_sizes: mailbox_sizes,
+            shutting_down: false,
+            dropped_on_shutdown: Cell::new(0),
+            logger: logger.new(o!("worker" => index))
+        }
+    }
+
+    /// Run the worker
+    ///
+    /// This call blocks the current thread until the worker receives a `WorkerMsg::Shutdown`. It
+    /// returns the number of envelopes dropped while shutting down.
+    pub fn run(mut self) -> usize {
+        'run: loop {
+            // Only block waiting for messages when there is no work to do
+            if self.run_queue.is_empty() {
+                match self.rx.recv() {
+                    Ok(msg) => if !self.handle_worker_msg(msg) { break 'run },
+                    Err(_) => break 'run
+                }
+            }
+            while let Ok(msg) = self.rx.try_recv() {
+                if !self.handle_worker_msg(msg) {
+                    break 'run;
+                }
+            }
+            self.run_processes();
+        }
+        self.shutdown()
+    }
+
+    /// Deliver a `Msg::Shutdown` to every process after the envelopes already in its mailbox,
+    /// and run all processes until their mailboxes are empty.
+    ///
+    /// The executor no longer routes envelopes, so envelopes for local pids sent while shutting
+    /// down are dropped. Mailboxes only shrink, which guarantees that the shutdown completes.
+    fn shutdown(&mut self) -> usize {
+        self.shutting_down = true;
+        for (pid, slot) in self.slots.iter_mut() {
+            if slot.mailbox.is_empty() {
+                self.run_queue.push_back(pid.clone());
+            }
+            slot.mailbox.push_back(Envelope {
+                to: pid.clone(),
+                from: self.executor_pid.clone(),
+                msg: Msg::Shutdown,
+                correlation_id: None
+            });
+            slot.size.fetch_add(1, Ordering::Relaxed);
+            self.counters.pending.fetch_add(1, Ordering::Relaxed);
+        }
+        while !self.run_queue.is_empty() {
+            self.run_processes();
+        }
+        for pid in self.slots.keys() {
+            self.mailbox_sizes.unregister(pid);
+        }
+        self.slots.clear();
+        self.dropped_on_shutdown.get()
+    }
+
+    /// Return false if the worker should exit
+    fn handle_worker_msg(&mut self, msg: WorkerMsg<T>) -> bool {
+        match msg {
+            WorkerMsg::Start(pid, instance, process, config) => {
+                self.start(pid, instance, process, config)
+            },
+            WorkerMsg::Stop(pid) => self.stop(&pid),
+            WorkerMsg::HandOff(pid) => self.hand_off(pid),
+            WorkerMsg::Envelope(envelope) => self.enqueue(envelope),
+            WorkerMsg::Shutdown => return false
+        }
+        true
+    }
+
+    fn start(&mut self,
+             pid: Pid,
+             instance: u64,
+             mut process: Box<Process<T>>,
+             config: MailboxConfig) {
+        let result = {
+            let executor_pid = self.executor_pid.clone();
+            panic::catch_unwind(AssertUnwindSafe(|| process.init(executor_pid)))
+        };
+        match result {
+            Ok(envelopes) => {
+                let size = self.mailbox_sizes.register(pid.clone());
+                self.slots.insert(pid, Slot {
+                    instance: instance,
+                    process: process,
+                    mailbox: VecDeque::new(),
+                    size: size,
+                    config: config,
+                    handing_off: false
+                });
+                for envelope in envelopes {
+                    self.send(envelope);
+                }
+            },
+            Err(payload) => self.exited(pid, instance, panic_reason(payload))
+        }
+    }
+
+    fn stop(&mut self, pid: &Pid) {
+        if let Some(slot) = self.slots.remove(pid) {
+            self.mailbox_sizes.unregister(pid);
+            self.discard_mailbox(slot.mailbox);
+        }
+    }
+
+    fn hand_off(&mut self, pid: Pid) {
+        if let Some(slot) = self.slots.get_mut(&pid) {
+            if slot.handing_off {
+                return;
+            }
+            slot.handing_off = true;
+            if slot.mailbox.is_empty() {
+                self.run_queue.push_back(pid.clone());
+            }
+            slot.mailbox.push_back(Envelope {
+                to: pid,
+                from: self.executor_pid.clone(),
+                msg: Msg::Shutdown,
+                correlation_id: None
+            });
+            slot.size.fetch_add(1, Ordering::Relaxed);
+            self.counters.pending.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Send the unhandled envelopes of a stopped or failed process to the dead letter service
+    fn discard_mailbox(&self, mailbox: VecDeque<Envelope<T>>) {
+        self.counters.pending.fetch_sub(mailbox.len(), Ordering::Relaxed);
+        for envelope in mailbox {
+            self.dead_letter(envelope, DeadLetterReason::Stopped);
+        }
+    }
+
+    fn enqueue(&mut self, envelope: Envelope<T>) {
+        let (discarded, reason) = match self.slots.get_mut(&envelope.to) {
+            Some(slot) => {
+                let full = slot.config.capacity.map_or(false, |c| slot.mailbox.len() >= c);
+                if !full {
+                    if slot.mailbox.is_empty() {
+                        self.run_queue.push_back(envelope.to.clone());
+                    }
+                    slot.mailbox.push_back(envelope);
+                    slot.size.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                match slot.config.overflow {
+                    OverflowPolicy::DropNewest => {
+                        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                        (envelope, DeadLetterReason::MailboxFull)
+                    },
+                    OverflowPolicy::DropOldest => {
+                        // The mailbox is full, so it is non-empty and already on the run queue
+                        let oldest = slot.mailbox.pop_front().unwrap();
+                        slot.mailbox.push_back(envelope);
+                        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                        (oldest, DeadLetterReason::MailboxFull)
+                    },
+                    OverflowPolicy::Reject => {
+                        self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+                        self.reject(&envelope);
+                        (envelope, DeadLetterReason::MailboxFull)
+                    }
+                }
+            },
+            None => {
+                debug!(self.logger, "Process not running on worker";
+                       "pid" => envelope.to.to_string());
+                (envelope, DeadLetterReason::Stopped)
+            }
+        };
+        self.counters.pending.fetch_sub(1, Ordering::Relaxed);
+        self.dead_letter(discarded, reason);
+    }
+
+    /// Tell the sender of an envelope that it was discarded because the mailbox was full
+    fn reject(&self, envelope: &Envelope<T>) {
+        // Don't bounce rejections or executor messages such as timeouts
+        if envelope.from == self.executor_pid {
+            return;
+        }
+        if let Msg::MailboxFull = envelope.msg {
+            return;
+        }
+        self.send(Envelope {
+            to: envelope.from.clone(),
+            from: envelope.to.clone(),
+            msg: Msg::MailboxFull,
+            correlation_id: envelope.correlation_id.clone()
+        });
+    }
+
+    /// Have the executor forward a discarded envelope to the dead letter service
+    fn dead_letter(&self, envelope: Envelope<T>, reason: DeadLetterReason) {
+        if self.shutting_down {
+            return self.drop_on_shutdown();
+        }
+        // The executor may itself be blocked sending to this worker, so never block on a full
+        // executor channel
+        if let Err(TrySendError::Full(_)) =
+            self.executor_tx.try_send(ExecutorMsg::DeadLetter(envelope, reason))
+        {
+            self.counters.dropped_dead_letters.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Give each runnable process a turn to handle the envelopes in its mailbox
+    fn run_processes(&mut self) {
+        for _ in 0..self.run_queue.len() {
+            let pid = match self.run_queue.pop_front() {
+                Some(pid) => pid,
+                None => return
+            };
+            self.run_process(pid);
+        }
+    }
+
+    fn run_process(&mut self, pid: Pid) {
+        let mut handled = 0;
+        let result = match self.slots.get_mut(&pid) {
+            Some(slot) => {
+                let process = &mut slot.process;
+                let mailbox = &mut slot.mailbox;
+                let output = &mut self.output;
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    while handled < MAX_BATCH_SIZE {
+                        match mailbox.pop_front() {
+                            Some(Envelope {from, msg, correlation_id, ..}) => {
+                                handled += 1;
+                                process.handle(msg, from, correlation_id, output);
+                            },
+                            None => break
+                        }
+                    }
+                }))
+            },
+            None => return
+        };
+        self.counters.pending.fetch_sub(handled, Ordering::Relaxed);
+        if let Some(slot) = self.slots.get(&pid) {
+            slot.size.fetch_sub(handled, Ordering::Relaxed);
+        }
+
+        if let Err(payload) = result {
+            // Any output of the failed process is discarded, since its state may be corrupt
+            self.output.clear();
+            if let Some(slot) = self.slots.remove(&pid) {
+                self.mailbox_sizes.unregister(&pid);
+                self.exited(pid, slot.instance, panic_reason(payload));
+                self.discard_mailbox(slot.mailbox);
+            }
+            return;
+        }
+
+        let mut output = mem::replace(&mut self.output, Vec::new());
+        for envelope in output.drain(..) {
+            self.send(envelope);
+        }
+        let _ = mem::replace(&mut self.output, output);
+
+        let (runnable, handed_off) = match self.slots.get(&pid) {
+            Some(slot) => (!slot.mailbox.is_empty(), slot.handing_off && slot.mailbox.is_empty()),
+            None => (false, false)
+        };
+        if runnable {
+            self.run_queue.push_back(pid);
+        } else if handed_off {
+            let slot = self.slots.remove(&pid).unwrap();
+            self.mailbox_sizes.unregister(&pid);
+            if !self.shutting_down {
+                let _ = self.executor_tx.send(ExecutorMsg::Exited(pid, slot.instance,
+                                                                  DownReason::Stopped));
+            }
+        }
+    }
+
+    /// Send remote envelopes directly to the cluster server. All local envelopes, including those
+    /// for processes owned by this worker, are routed by the executor.
+    fn send(&self, envelope: Envelope<T>) {
+        if envelope.to.node == self.node {
+            if self.shutting_down {
+                return self.drop_on_shutdown();
+            }
+            // This won't ever fail unless the executor is shutting down
+            let _ = self.executor_tx.send(ExecutorMsg::Envelope(envelope));
+        } else if self.shutting_down {
+            // The cluster server may be blocked sending to the executor, which is waiting for
+            // this worker to exit, so never block here.
+            if let Err(_) = self.cluster_tx.try_send(ClusterMsg::Envelope(envelope)) {
+                self.drop_on_shutdown();
+            }
+        } else {
+            let _ = self.cluster_tx.send(ClusterMsg::Envelope(envelope));
+        }
+    }
+
+    fn drop_on_shutdown(&self) {
+        self.dropped_on_shutdown.set(self.dropped_on_shutdown.get() + 1);
+    }
+
+    fn exited(&self, pid: Pid, instance: u64, reason: String) {
+        if self.shutting_down {
+            warn!(self.logger, "Process panicked while shutting down";
+                  "pid" => pid.to_string(), "reason" => reason);
+            return;
+        }
+        let reason = DownReason::Panicked(reason);
+        let _ = self.executor_tx.send(ExecutorMsg::Exited(pid, instance, reason));
+    }
+}
+
+/// Extract a printable reason from the payload of a caught panic
+pub fn panic_reason(payload: Box<Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        return s.to_string();
+    }
+    if let Some(s) = payload.downcast_ref::<String>() {
+        return s.clone();
+    }
+    "Unknown panic".to_string()
+}
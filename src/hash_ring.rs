@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+/// A consistent hash ring mapping keys to members
+///
+/// Each member is placed on the ring at `vnodes` points, and a key is owned by the member at the
+/// first point at or after the hash of the key, wrapping around at the end of the ring. Adding or
+/// removing a member therefore only moves the keys owned by that member.
+///
+/// Points are computed with 64 bit FNV-1a, with integers hashed as little endian bytes and vnode
+/// indexes as `u64`, so every node builds the same ring from the same members regardless of the
+/// platform it runs on. Members and keys are still fed to the hasher through their `Hash` impls,
+/// and the standard library doesn't guarantee how those encode strings, so all nodes of a cluster
+/// must be built with the same Rust version.
+#[derive(Debug, Clone)]
+pub struct HashRing<M> {
+    vnodes: usize,
+    ring: BTreeMap<u64, M>
+}
+
+impl<M: Hash + Eq + Clone> HashRing<M> {
+    /// Create an empty ring that places each member at `vnodes` points
+    pub fn new(vnodes: usize) -> HashRing<M> {
+        HashRing {
+            vnodes: vnodes.max(1),
+            ring: BTreeMap::new()
+        }
+    }
+
+    /// Add a member to the ring. Adding a member that is already on the ring has no effect.
+    pub fn add(&mut self, member: M) {
+        for i in 0..self.vnodes {
+            self.ring.entry(hash(&(&member, i as u64))).or_insert_with(|| member.clone());
+        }
+    }
+
+    pub fn remove(&mut self, member: &M) {
+        for i in 0..self.vnodes {
+            let point = hash(&(member, i as u64));
+            if self.ring.get(&point) == Some(member) {
+                self.ring.remove(&point);
+            }
+        }
+    }
+
+    pub fn contains(&self, member: &M) -> bool {
+        (0..self.vnodes).any(|i| self.ring.get(&hash(&(member, i as u64))) == Some(member))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+
+    /// Return all members on the ring, in no particular order
+    pub fn members(&self) -> Vec<M> {
+        let mut members: Vec<M> = Vec::new();
+        for member in self.ring.values() {
+            if !members.contains(member) {
+                members.push(member.clone());
+            }
+        }
+        members
+    }
+
+    /// Return the member that owns `key`, or `None` if the ring is empty
+    pub fn owner<K: Hash>(&self, key: &K) -> Option<&M> {
+        let point = hash(key);
+        self.ring.range(point..).chain(self.ring.iter()).next().map(|(_, member)| member)
+    }
+
+    /// Return up to `n` distinct members responsible for `key`, starting with its owner and
+    /// continuing clockwise around the ring
+    pub fn owners<K: Hash>(&self, key: &K, n: usize) -> Vec<M> {
+        let point = hash(key);
+        let mut owners: Vec<M> = Vec::with_capacity(n);
+        for (_, member) in self.ring.range(point..).chain(self.ring.range(..point)) {
+            if owners.len() == n {
+                break;
+            }
+            if !owners.contains(member) {
+                owners.push(member.clone());
+            }
+        }
+        owners
+    }
+}
+
+fn hash<K: Hash>(key: &K) -> u64 {
+    let mut hasher = FnvHasher(FNV_OFFSET_BASIS);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A 64 bit FNV-1a hasher
+///
+/// Unlike `DefaultHasher`, whose algorithm may change between Rust releases, the output of this
+/// hasher is fully specified. Integers are written as little endian bytes, and `usize` as a `u64`.
+struct FnvHasher(u64);
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16)
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32)
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128)
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64)
+    }
+}
@@ -11,6 +11,16 @@ extern crate time;
 extern crate net2;
 extern crate libc;
 extern crate ferris;
+extern crate toml;
+extern crate serde_json;
+extern crate hmac;
+extern crate sha2;
+extern crate rand;
+extern crate rustls;
+extern crate webpki;
+extern crate untrusted;
+extern crate bincode;
+extern crate serde_cbor;
 //extern crate hdrsample;
 
 #[macro_use]
@@ -28,6 +38,7 @@ mod metrics;
 mod node_id;
 mod node;
 mod members;
+mod registry;
 mod pid;
 mod process;
 mod envelope;
@@ -37,29 +48,66 @@ mod msg;
 mod timer_wheel;
 mod service;
 mod correlation_id;
+mod supervisor;
+mod router;
+mod hash_ring;
+mod config;
 pub mod serialize;
+pub mod sim;
 
 pub mod errors;
 
 pub use errors::Result;
 pub use node_id::NodeId;
-pub use node::Node;
+pub use node::{Node, AskHandle, ShutdownReport};
 pub use pid::Pid;
-pub use process::Process;
+pub use process::{Process, EntityFactory};
 pub use envelope::Envelope;
 pub use correlation_id::CorrelationId;
-pub use msg::Msg;
+pub use config::{RabbleConfig, RabbleConfigBuilder, TimerResolution};
+pub use msg::{Msg, DownReason, UndeliverableReason, DeadLetterReason};
 pub use metrics::Metric;
+pub use hash_ring::HashRing;
+pub use router::{Router, RoutingStrategy, RouterKey};
+pub use supervisor::{
+    SupervisorSpec,
+    ChildSpec,
+    Child,
+    RestartStrategy,
+    ProcessFactory
+};
 
 pub use cluster::{
     ClusterServer,
     ClusterStatus,
+    ClusterEvent,
+    ClusterRing,
+    ExternalMsg,
+    PROTOCOL_VERSION,
+    Codec,
+    MsgpackCodec,
+    BincodeCodec,
+    CborCodec,
+    TlsConfig,
+    Transport,
+    Listener,
+    Connection,
+    TcpTransport,
+    UnixTransport,
+    MemoryTransport
 };
 
+#[cfg(feature = "fault_injection")]
+pub use cluster::Fault;
+
 pub use executor::{
     Executor,
     ExecutorStatus,
-    ExecutorMetrics
+    WorkerStatus,
+    ExecutorMetrics,
+    MailboxConfig,
+    MailboxSizes,
+    OverflowPolicy
 };
 
 pub use service::{
@@ -71,43 +119,98 @@ pub use service::{
 };
 
 use std::thread::{self, JoinHandle};
-use std::sync::mpsc::channel;
+use std::sync::mpsc::sync_channel;
 use std::fmt::Debug;
 use serde::{Deserialize, Serialize};
 use amy::Poller;
 use slog::DrainExt;
 use cluster::ClusterMsg;
 
-const TIMEOUT: usize = 5000; // ms
-
 /// Start a node in the rabble cluster and return it along with the handles to all threads started
 /// by rabble.
 ///
+/// The node uses the default `RabbleConfig`, connects to other nodes over TCP and encodes messages
+/// with MessagePack. Use `rouse_with_config` or `rouse_with` to change any of these, or to handle
+/// errors starting the node.
+///
 /// All nodes in a cluster must be parameterized by the same type.
+///
+/// # Panics
+///
+/// Panics if the node can't listen at the `addr` of its `NodeId`.
 pub fn rouse<'de, T>(node_id: NodeId, logger: Option<slog::Logger>) -> (Node<T>, Vec<JoinHandle<()>>)
   where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
 {
+    let config = RabbleConfig::default();
+    // The default config doesn't use TLS, so creating the transport can't fail
+    let transport = TcpTransport::new(&config).unwrap();
+    rouse_with(node_id, config, Box::new(transport), Box::new(MsgpackCodec), logger).unwrap()
+}
+
+/// Start a node tuned with the given config
+///
+/// Nodes connect over TCP, secured with TLS if the config contains a `TlsConfig`, and encode
+/// messages with MessagePack. An error is returned if the config is invalid, if the TLS
+/// certificates or key can't be loaded, or if the node can't listen at the `addr` of its `NodeId`.
+pub fn rouse_with_config<'de, T>(node_id: NodeId,
+                                 config: RabbleConfig,
+                                 logger: Option<slog::Logger>)
+    -> Result<(Node<T>, Vec<JoinHandle<()>>)>
+  where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
+{
+    let transport = try!(TcpTransport::new(&config));
+    rouse_with(node_id, config, Box::new(transport), Box::new(MsgpackCodec), logger)
+}
+
+/// Start a node with the given config, transport and codec
+///
+/// Nodes usually connect over a `TcpTransport` created from the same config, which secures
+/// connections with TLS if the config contains a `TlsConfig`. All nodes in a cluster must use the
+/// same codec.
+///
+/// An error is returned if the config is invalid, or if the node can't listen at the `addr` of its
+/// `NodeId`.
+pub fn rouse_with<'de, T>(node_id: NodeId,
+                          config: RabbleConfig,
+                          transport: Box<Transport>,
+                          codec: Box<Codec<T>>,
+                          logger: Option<slog::Logger>) -> Result<(Node<T>, Vec<JoinHandle<()>>)>
+  where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
+{
+    try!(config.validate());
     let logger = match logger {
         Some(logger) => logger.new(o!("node_id" => node_id.to_string())),
         None => slog::Logger::root(slog_stdlog::StdLog.fuse(), o!("node_id" => node_id.to_string()))
     };
 
     let mut poller = Poller::new().unwrap();
-    let (exec_tx, exec_rx) = channel();
-    let (cluster_tx, cluster_rx) = channel();
-    let cluster_server = ClusterServer::new(node_id.clone(),
-                                            cluster_rx,
-                                            exec_tx.clone(),
-                                            poller.get_registrar().unwrap(),
-                                            logger.clone());
+    let (exec_tx, exec_rx) = sync_channel(config.executor_channel_bound);
+    let (cluster_tx, cluster_rx) = sync_channel(config.cluster_channel_bound);
+    let ring = ClusterRing::new(&node_id, config.ring_vnodes, config.replication_factor);
     let executor = Executor::new(node_id.clone(),
+                                 &config,
                                  exec_tx.clone(),
                                  exec_rx,
                                  cluster_tx.clone(),
+                                 ring.clone(),
                                  logger.clone());
-
+    let poll_timeout = config.poll_timeout_ms;
+    let cluster_server = try!(ClusterServer::new(node_id.clone(),
+                                                 config,
+                                                 transport,
+                                                 codec,
+                                                 cluster_rx,
+                                                 exec_tx.clone(),
+                                                 poller.get_registrar().unwrap(),
+                                                 ring.clone(),
+                                                 logger.clone()));
+    let mailbox_sizes = executor.mailbox_sizes();
+
+    let poller_registrar = poller.get_registrar().unwrap();
     let h1 = thread::Builder::new().name(format!("cluster_server::{}", node_id)).spawn(move || {
-        cluster_server.run()
+        cluster_server.run();
+        // Wake up the poller so that it exits right away instead of after the poll timeout
+        let _ = poller_registrar.set_timeout(1);
     }).unwrap();
 
     let h2 = thread::Builder::new().name(format!("executor::{}", node_id)).spawn(move || {
@@ -117,7 +220,7 @@ pub fn rouse<'de, T>(node_id: NodeId, logger: Option<slog::Logger>) -> (Node<T>,
     let _cluster_tx = cluster_tx.clone();
     let h3 = thread::Builder::new().name(format!("poller::{}", node_id)).spawn(move || {
         loop {
-            let notifications = poller.wait(TIMEOUT).unwrap();
+            let notifications = poller.wait(poll_timeout).unwrap();
             if let Err(_) = _cluster_tx.send(ClusterMsg::PollNotifications(notifications)) {
                 // The process is exiting
                 return;
@@ -125,5 +228,6 @@ pub fn rouse<'de, T>(node_id: NodeId, logger: Option<slog::Logger>) -> (Node<T>,
         }
     }).unwrap();
 
-    (Node::new(node_id, exec_tx, cluster_tx, logger), vec![h1, h2, h3])
+    let node = Node::new(node_id, exec_tx, cluster_tx, mailbox_sizes, ring, logger);
+    Ok((node, vec![h1, h2, h3]))
 }
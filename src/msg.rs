@@ -1,7 +1,10 @@
-use cluster::ClusterStatus;
+use cluster::{ClusterStatus, ClusterEvent};
 use executor::ExecutorStatus;
 use correlation_id::CorrelationId;
 use metrics::Metric;
+use pid::Pid;
+use node_id::NodeId;
+use envelope::Envelope;
 
 type Name = String;
 
@@ -15,5 +18,105 @@ pub enum Msg<T> {
     Timeout,
     Shutdown,
     GetMetrics,
-    Metrics(Vec<(Name, Metric)>)
+    Metrics(Vec<(Name, Metric)>),
+
+    // Monitors are unidirectional. Sent to the executor to start or stop receiving a `Down` when
+    // the given pid terminates.
+    Monitor(Pid),
+    Demonitor(Pid),
+    Down {pid: Pid, reason: DownReason},
+
+    // Links are bidirectional. Sent to the executor to start or stop receiving an `Exit` when the
+    // given pid terminates, and to have the given pid receive an `Exit` when the sender terminates.
+    Link(Pid),
+    Unlink(Pid),
+    Exit {pid: Pid, reason: DownReason},
+
+    // Sent to the cluster server to start or stop receiving a `ClusterEvent` whenever cluster
+    // membership or connectivity changes
+    SubscribeClusterEvents,
+    UnsubscribeClusterEvents,
+    ClusterEvent(ClusterEvent),
+
+    // Sent back to the sender of an envelope for a remote pid that could not be delivered
+    Undeliverable(UndeliverableReason),
+
+    // Sent back to the sender of an envelope that was discarded because the mailbox of the
+    // receiving process was full and its overflow policy is `OverflowPolicy::Reject`
+    MailboxFull,
+
+    // Sent to the dead letter service, `Pid::dead_letters`, with an envelope that was discarded
+    DeadLetter {envelope: Box<Envelope<T>>, reason: DeadLetterReason},
+
+    // Sent to the local broker, `Pid::broker`, to start or stop receiving messages published to a
+    // topic
+    Subscribe(String),
+    Unsubscribe(String),
+
+    // Sent to a broker to deliver a message to all subscribers of a topic in the cluster.
+    // Subscribers receive the same message, from the publisher.
+    Publish(String, T),
+
+    // Sent to a `Router` to grow or shrink its pool of routees, or to get the current pool
+    AddRoutee(Pid),
+    RemoveRoutee(Pid),
+    GetRoutees,
+    Routees(Vec<Pid>),
+
+    // Sent to the cluster server to start or stop receiving a `RingChanged`, with the members of
+    // the new ring, whenever nodes are added to or removed from the `ClusterRing`
+    SubscribeRingChanges,
+    UnsubscribeRingChanges,
+    RingChanged(Vec<NodeId>)
+}
+
+/// The reason an envelope was sent to the dead letter service
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DeadLetterReason {
+    /// No process or service with the destination pid exists on the node
+    NoProcess,
+
+    /// The destination process stopped or panicked before handling the envelope
+    Stopped,
+
+    /// The destination node is not a member of the cluster
+    UnknownNode,
+
+    /// The mailbox of the destination process was full
+    MailboxFull,
+
+    /// The envelope was forwarded to a node that doesn't own the destination entity, which may
+    /// happen while nodes disagree on the cluster membership. Forwarded envelopes are never
+    /// forwarded again.
+    NotOwner
+}
+
+/// The reason an envelope could not be sent to a remote node
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum UndeliverableReason {
+    /// The destination node is not a member of the cluster
+    NotAMember,
+
+    /// Too many envelopes are already waiting for a connection to the destination node, or to be
+    /// handed to the cluster server by the executor
+    QueueFull,
+
+    /// A connection to the destination node was not established in time
+    Expired
+}
+
+/// The reason a monitored or linked process terminated
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DownReason {
+    /// The process was stopped via `Node::stop` or by its supervisor
+    Stopped,
+
+    /// The process panicked
+    Panicked(String),
+
+    /// The process did not exist when the monitor or link was created
+    NoProcess,
+
+    /// The connection to the node hosting the process was lost
+    NodeDown
 }
@@ -1,14 +1,22 @@
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{self, SyncSender, RecvTimeoutError, TrySendError};
 use std::fmt::Debug;
+use std::hash::Hash;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use serde::{Serialize, Deserialize};
 use node_id::NodeId;
-use executor::ExecutorMsg;
-use cluster::ClusterMsg;
+use executor::{ExecutorMsg, MailboxConfig, MailboxSizes};
+use cluster::{ClusterMsg, ClusterRing};
+#[cfg(feature = "fault_injection")]
+use cluster::Fault;
 use pid::Pid;
 use correlation_id::CorrelationId;
-use process::Process;
+use process::{Process, EntityFactory};
+use supervisor::SupervisorSpec;
 use envelope::Envelope;
-use amy;
+use msg::Msg;
+use amy::{self, Poller};
 use errors::*;
 use slog;
 
@@ -22,6 +30,34 @@ macro_rules! send {
     }
 }
 
+// Used to generate unique pids for the replies to `Node::ask`
+static NEXT_ASK_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A summary of an orderly node shutdown, returned by `Node::shutdown_and_wait`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ShutdownReport {
+    /// The number of processes that were sent a `Msg::Shutdown`
+    pub processes: usize,
+
+    /// The number of registered services that were sent a `Msg::Shutdown`
+    pub services: usize,
+
+    /// Envelopes for local pids that were discarded. These include envelopes left in the
+    /// mailboxes of panicked processes, envelopes sent between local pids after the shutdown
+    /// began, and envelopes sent to the node after the executor stopped routing.
+    pub dropped_envelopes: usize,
+
+    /// Envelopes for remote pids that were still waiting for a connection to their node
+    pub dropped_remote_envelopes: usize,
+
+    /// Connections to other nodes whose queued writes couldn't be flushed before closing
+    pub unflushed_connections: usize,
+
+    /// True if the node's threads didn't all exit before the timeout. The counts are incomplete
+    /// in that case.
+    pub timed_out: bool
+}
+
 /// A Node represents a way for services to interact with rabble internals.
 ///
 /// The Node api is used by services and their handlers to send messages, get status, join
@@ -30,25 +66,52 @@ macro_rules! send {
 pub struct Node<T> {
     pub id: NodeId,
     pub logger: slog::Logger,
-    executor_tx: Sender<ExecutorMsg<T>>,
-    cluster_tx: Sender<ClusterMsg<T>>
+    executor_tx: SyncSender<ExecutorMsg<T>>,
+    cluster_tx: SyncSender<ClusterMsg<T>>,
+    mailbox_sizes: MailboxSizes,
+    ring: ClusterRing
 }
 
 impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
     /// Create a new node. This function should not be called by the user directly. It is called by
     /// by the user call to `rabble::rouse(..)` that initializes a rabble system for a single node.
     pub fn new(id: NodeId,
-               executor_tx: Sender<ExecutorMsg<T>>,
-               cluster_tx: Sender<ClusterMsg<T>>,
+               executor_tx: SyncSender<ExecutorMsg<T>>,
+               cluster_tx: SyncSender<ClusterMsg<T>>,
+               mailbox_sizes: MailboxSizes,
+               ring: ClusterRing,
                logger: slog::Logger) -> Node<T> {
         Node {
             id: id,
             executor_tx: executor_tx,
             cluster_tx: cluster_tx,
+            mailbox_sizes: mailbox_sizes,
+            ring: ring,
             logger: logger
         }
     }
 
+    /// Return a handle to the consistent hash ring of the cluster members
+    pub fn ring(&self) -> ClusterRing {
+        self.ring.clone()
+    }
+
+    /// Return the nodes that own `key` on the cluster ring, primary first. The number of owners
+    /// is limited by `RabbleConfig::replication_factor` and the number of cluster members.
+    pub fn owners<K: Hash>(&self, key: &K) -> Vec<NodeId> {
+        self.ring.owners(key)
+    }
+
+    /// Return the node that owns `key` on the cluster ring
+    pub fn owner<K: Hash>(&self, key: &K) -> Option<NodeId> {
+        self.ring.owner(key)
+    }
+
+    /// Return a handle to the number of envelopes waiting in the mailbox of each local process
+    pub fn mailbox_sizes(&self) -> MailboxSizes {
+        self.mailbox_sizes.clone()
+    }
+
     /// Join 1 node to another to form a cluster.
     ///
     /// Node joins are transitive such that if `Node A` joins `Node B` which is already joined with
@@ -74,12 +137,35 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
     /// Add a process to the executor that can be sent Envelopes addressed to its pid
     pub fn spawn(&self, pid: &Pid, process: Box<Process<T>>) -> Result<()> {
         send!(self.executor_tx,
-              ExecutorMsg::Start(pid.clone(), process),
+              ExecutorMsg::Start(pid.clone(), process, None),
+              Some(pid),
+              format!("ExecutorMsg::Start({}, ..)", pid))
+    }
+
+    /// Add a process to the executor with a mailbox configuration other than the node's default
+    pub fn spawn_with_mailbox(&self,
+                              pid: &Pid,
+                              process: Box<Process<T>>,
+                              mailbox: MailboxConfig) -> Result<()>
+    {
+        send!(self.executor_tx,
+              ExecutorMsg::Start(pid.clone(), process, Some(mailbox)),
               Some(pid),
               format!("ExecutorMsg::Start({}, ..)", pid))
     }
 
-    /// Remove a process from the executor
+    /// Add a supervisor to the executor and start all of its children.
+    ///
+    /// Supervised processes that panic are restarted according to the supervisor's restart
+    /// strategy.
+    pub fn spawn_supervisor(&self, pid: &Pid, spec: SupervisorSpec<T>) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::StartSupervisor(pid.clone(), spec),
+              Some(pid),
+              format!("ExecutorMsg::StartSupervisor({}, ..)", pid))
+    }
+
+    /// Remove a process or supervisor from the executor
     pub fn stop(&self, pid: &Pid) -> Result<()> {
         send!(self.executor_tx,
               ExecutorMsg::Stop(pid.clone()),
@@ -97,6 +183,111 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
               format!("ExecutorMsg::RegisterService({}, ..)", pid))
     }
 
+    /// Remove a registered Service so that it no longer receives envelopes
+    pub fn unregister_service(&self, pid: &Pid) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::UnregisterService(pid.clone()),
+              Some(pid),
+              format!("ExecutorMsg::UnregisterService({})", pid))
+    }
+
+    /// Register a name for a pid on this node.
+    ///
+    /// Envelopes sent to `Pid::named(name, node)` for this node are routed to the pid, until the
+    /// name is unregistered or the pid terminates. Local names shadow global names.
+    pub fn register_name(&self, name: &str, pid: &Pid) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::RegisterName(name.to_string(), pid.clone()),
+              Some(pid),
+              format!("ExecutorMsg::RegisterName({}, {})", name, pid))
+    }
+
+    /// Register the factory used to start entities of the given type on this node.
+    ///
+    /// Entities are addressed by `Pid::entity(entity_type, key, node)`, and started on the node
+    /// owning their key on the cluster ring when they receive their first user message. Entity
+    /// types must be registered on every node in the cluster. When the ring changes, entities
+    /// whose keys moved to another node handle a `Msg::Shutdown` and are stopped, and the new
+    /// owner starts them again on demand, so entities with state must save it on shutdown and
+    /// restore it on start.
+    pub fn register_entity_type(&self, entity_type: &str, factory: EntityFactory<T>) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::RegisterEntityType(entity_type.to_string(), factory),
+              None,
+              format!("ExecutorMsg::RegisterEntityType({})", entity_type))
+    }
+
+    pub fn unregister_name(&self, name: &str) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::UnregisterName(name.to_string()),
+              None,
+              format!("ExecutorMsg::UnregisterName({})", name))
+    }
+
+    /// Register a name for a pid on all nodes in the cluster.
+    ///
+    /// The registration is replicated to the other nodes asynchronously, after which envelopes
+    /// sent to `Pid::named(name, node)` for any node are routed to the pid. If the name is also
+    /// registered elsewhere, e.g. on the other side of a partition, it resolves to the lowest of
+    /// the registered pids on every node. Global names remain registered when their pid
+    /// terminates.
+    pub fn register_global_name(&self, name: &str, pid: &Pid) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::RegisterName(name.to_string(), pid.clone()),
+              Some(pid),
+              format!("ClusterMsg::RegisterName({}, {})", name, pid))
+    }
+
+    pub fn unregister_global_name(&self, name: &str) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::UnregisterName(name.to_string()),
+              None,
+              format!("ClusterMsg::UnregisterName({})", name))
+    }
+
+    /// Send a message to a pid and block until it replies, or until `timeout` elapses.
+    ///
+    /// The message is sent from a temporary pid registered as a service, along with a unique
+    /// correlation id. The first envelope sent back to that pid with the same correlation id is the
+    /// reply. A `Timeout` error is returned if no reply arrives in time.
+    pub fn ask(&self, to: &Pid, msg: Msg<T>, timeout: Duration) -> Result<Msg<T>> {
+        try!(self.ask_async(to, msg, timeout)).wait()
+    }
+
+    /// Send a message to a pid without waiting for the reply.
+    ///
+    /// The reply is retrieved from the returned `AskHandle`. Dropping the handle unregisters the
+    /// temporary reply pid, so that any late reply goes to the dead letter service.
+    pub fn ask_async(&self, to: &Pid, msg: Msg<T>, timeout: Duration) -> Result<AskHandle<T>> {
+        let id = NEXT_ASK_ID.fetch_add(1, Ordering::Relaxed);
+        let pid = Pid {
+            name: format!("ask-{}", id),
+            group: Some("rabble".to_string()),
+            node: self.id.clone()
+        };
+        let correlation_id = CorrelationId {
+            pid: pid.clone(),
+            connection: None,
+            request: Some(id as u64)
+        };
+        let poller = try!(Poller::new());
+        let (tx, rx) = try!(try!(poller.get_registrar()).channel());
+        try!(self.register_service(&pid, &tx));
+        // Construct the handle before sending, so that the reply pid is unregistered on failure
+        let handle = AskHandle {
+            to: to.clone(),
+            pid: pid.clone(),
+            correlation_id: correlation_id.clone(),
+            deadline: Instant::now() + timeout,
+            poller: poller,
+            rx: rx,
+            executor_tx: self.executor_tx.clone(),
+            logger: self.logger.clone()
+        };
+        try!(self.send(Envelope::new(to.clone(), pid, msg, Some(correlation_id))));
+        Ok(handle)
+    }
+
     /// Send an envelope to the executor so it gets routed to the appropriate process or service
     pub fn send(&self, envelope: Envelope<T>) -> Result<()> {
         let to = envelope.to.clone();
@@ -124,9 +315,143 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
               "ClusterMsg::GetStatus".to_string())
     }
 
-    /// Shutdown the node
+    /// Inject a fault into all traffic between this node and the given node, replacing any fault
+    /// already injected for it.
+    ///
+    /// Only available with the `fault_injection` feature.
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_fault(&self, node_id: &NodeId, fault: Fault) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::SetFault(node_id.clone(), Some(fault)),
+              None,
+              format!("ClusterMsg::SetFault({:?}, ..)", *node_id))
+    }
+
+    /// Remove any fault injected into the traffic between this node and the given node
+    ///
+    /// Only available with the `fault_injection` feature.
+    #[cfg(feature = "fault_injection")]
+    pub fn clear_fault(&self, node_id: &NodeId) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::SetFault(node_id.clone(), None),
+              None,
+              format!("ClusterMsg::SetFault({:?}, None)", *node_id))
+    }
+
+    /// Shutdown the node without waiting for it to stop.
+    ///
+    /// The node leaves the cluster, stops accepting envelopes, and delivers a `Msg::Shutdown` to
+    /// every process and registered service. Processes handle the envelopes already in their
+    /// mailboxes before stopping, and writes queued for other nodes are flushed before the cluster
+    /// server exits. Shutting down a node that is already stopped does nothing.
     pub fn shutdown(&self) {
-        self.executor_tx.send(ExecutorMsg::Shutdown).unwrap();
-        self.cluster_tx.send(ClusterMsg::Shutdown).unwrap();
+        self.begin_shutdown(None);
+    }
+
+    /// Shutdown the node and wait up to `timeout` for all the threads returned by `rouse` to exit.
+    ///
+    /// Returns a report of what was discarded during the shutdown. See `Node::shutdown` for the
+    /// order in which the node is stopped.
+    pub fn shutdown_and_wait(&self, handles: Vec<JoinHandle<()>>, timeout: Duration)
+        -> ShutdownReport
+    {
+        let deadline = Instant::now() + timeout;
+        let (tx, rx) = mpsc::channel();
+        self.begin_shutdown(Some(tx));
+
+        let mut report = match rx.recv_timeout(timeout) {
+            Ok(report) => report,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(self.logger, "Timed out waiting for shutdown");
+                return ShutdownReport {timed_out: true, ..ShutdownReport::default()};
+            },
+            // The node was already stopped
+            Err(RecvTimeoutError::Disconnected) => ShutdownReport::default()
+        };
+
+        // Threads can't be joined with a timeout, so join them on another thread instead
+        let (joined_tx, joined_rx) = mpsc::channel();
+        thread::spawn(move || {
+            for handle in handles {
+                let _ = handle.join();
+            }
+            let _ = joined_tx.send(());
+        });
+        let now = Instant::now();
+        let remaining = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+        if joined_rx.recv_timeout(remaining).is_err() {
+            warn!(self.logger, "Timed out waiting for threads to exit");
+            report.timed_out = true;
+        }
+        report
+    }
+
+    fn begin_shutdown(&self, reply: Option<mpsc::Sender<ShutdownReport>>) {
+        info!(self.logger, "Shutting down");
+        // Leave first so that peers stop routing envelopes to this node. This fails if the node
+        // was already stopped, and so does the shutdown itself.
+        let _ = self.cluster_tx.send(ClusterMsg::Leave(self.id.clone()));
+        let _ = self.executor_tx.send(ExecutorMsg::Shutdown(reply));
+    }
+}
+
+/// A pending reply to a message sent with `Node::ask_async`
+pub struct AskHandle<T> {
+    to: Pid,
+    pid: Pid,
+    correlation_id: CorrelationId,
+    deadline: Instant,
+    poller: Poller,
+    rx: amy::Receiver<Envelope<T>>,
+    executor_tx: SyncSender<ExecutorMsg<T>>,
+    logger: slog::Logger
+}
+
+impl<T> AskHandle<T> {
+    /// The correlation id sent along with the message
+    pub fn correlation_id(&self) -> &CorrelationId {
+        &self.correlation_id
+    }
+
+    /// Return the reply if it has arrived, without blocking.
+    ///
+    /// Returns `Ok(None)` while still waiting, and a `Timeout` error once the timeout has elapsed.
+    pub fn try_recv(&mut self) -> Result<Option<Msg<T>>> {
+        while let Ok(envelope) = self.rx.try_recv() {
+            if envelope.correlation_id.as_ref() == Some(&self.correlation_id) {
+                return Ok(Some(envelope.msg));
+            }
+        }
+        if Instant::now() >= self.deadline {
+            return Err(ErrorKind::Timeout(self.to.clone()).into());
+        }
+        Ok(None)
+    }
+
+    /// Block until the reply arrives, or until the timeout elapses
+    pub fn wait(mut self) -> Result<Msg<T>> {
+        loop {
+            if let Some(msg) = try!(self.try_recv()) {
+                return Ok(msg);
+            }
+            let now = Instant::now();
+            let remaining = if self.deadline > now { self.deadline - now } else { continue };
+            // Round up so that the poller doesn't wake up just before the deadline
+            let ms = remaining.as_secs() as usize * 1000 +
+                remaining.subsec_nanos() as usize / 1_000_000 + 1;
+            try!(self.poller.wait(ms));
+        }
+    }
+}
+
+impl<T> Drop for AskHandle<T> {
+    // Never block the dropping thread on a full executor channel. If the unregister can't be
+    // queued, the executor removes the service once it fails to deliver to it.
+    fn drop(&mut self) {
+        let msg = ExecutorMsg::UnregisterService(self.pid.clone());
+        if let Err(TrySendError::Full(_)) = self.executor_tx.try_send(msg) {
+            warn!(self.logger, "Executor channel full. Failed to unregister ask handle";
+                  "pid" => self.pid.to_string());
+        }
     }
 }
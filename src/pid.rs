@@ -13,6 +13,122 @@ pub struct Pid {
     pub node: NodeId,
 }
 
+impl Pid {
+    /// The well-known pid of the dead letter service on the given node.
+    ///
+    /// A service or process registered with this pid receives a `Msg::DeadLetter` for every
+    /// envelope on the node that could not be delivered.
+    pub fn dead_letters(node: &NodeId) -> Pid {
+        Pid {
+            group: Some("rabble".to_string()),
+            name: "dead_letters".to_string(),
+            node: node.clone()
+        }
+    }
+
+    /// The well-known pid of the executor on the given node.
+    ///
+    /// Processes send timer requests, and monitor and link requests, to the executor on their own
+    /// node.
+    pub fn executor(node: &NodeId) -> Pid {
+        Pid {
+            group: Some("rabble".to_string()),
+            name: "executor".to_string(),
+            node: node.clone()
+        }
+    }
+
+    /// The well-known pid of the pub/sub broker on the given node.
+    ///
+    /// Processes send a `Msg::Subscribe` or `Msg::Unsubscribe` to the broker on their own node,
+    /// and a `Msg::Publish` to any broker.
+    pub fn broker(node: &NodeId) -> Pid {
+        Pid {
+            group: Some("rabble".to_string()),
+            name: "broker".to_string(),
+            node: node.clone()
+        }
+    }
+
+    /// A pid addressing every process in `group` on `node`
+    pub fn group_members(group: &str, node: &NodeId) -> Pid {
+        Pid {
+            group: Some("rabble.group".to_string()),
+            name: group.to_string(),
+            node: node.clone()
+        }
+    }
+
+    /// A pid addressing every process in `group` on all nodes in the cluster.
+    ///
+    /// The executor on `node`, normally the sender's own node, delivers the envelope to the local
+    /// members of the group, and the cluster server forwards a single copy to each other node with
+    /// members in the group.
+    pub fn cluster_group_members(group: &str, node: &NodeId) -> Pid {
+        Pid {
+            group: Some("rabble.cluster_group".to_string()),
+            name: group.to_string(),
+            node: node.clone()
+        }
+    }
+
+    /// Return true if this pid was created with `Pid::group_members`
+    pub fn is_group_members(&self) -> bool {
+        self.group.as_ref().map_or(false, |group| group == "rabble.group")
+    }
+
+    /// Return true if this pid was created with `Pid::cluster_group_members`
+    pub fn is_cluster_group_members(&self) -> bool {
+        self.group.as_ref().map_or(false, |group| group == "rabble.cluster_group")
+    }
+
+    /// A pid addressing whichever process or service is registered with the given name.
+    ///
+    /// The executor on `node` resolves the name when routing an envelope, preferring names
+    /// registered locally with `Node::register_name` over names registered cluster wide with
+    /// `Node::register_global_name`. Envelopes for unregistered names are dead letters.
+    pub fn named(name: &str, node: &NodeId) -> Pid {
+        Pid {
+            group: Some("rabble.names".to_string()),
+            name: name.to_string(),
+            node: node.clone()
+        }
+    }
+
+    /// Return true if this pid addresses a registered name rather than a process
+    pub fn is_named(&self) -> bool {
+        self.group.as_ref().map_or(false, |group| group == "rabble.names")
+    }
+
+    /// The pid of the sharded entity of type `entity_type` with the given key.
+    ///
+    /// Envelopes for an entity may be sent to any node, normally the sender's own node, and are
+    /// forwarded to the node owning the entity's key on the cluster ring. The entity is started
+    /// there by the factory registered with `Node::register_entity_type` when it receives its
+    /// first user message.
+    pub fn entity(entity_type: &str, key: &str, node: &NodeId) -> Pid {
+        Pid {
+            group: Some(format!("rabble.entities.{}", entity_type)),
+            name: key.to_string(),
+            node: node.clone()
+        }
+    }
+
+    /// Return true if this pid was created with `Pid::entity`
+    pub fn is_entity(&self) -> bool {
+        self.entity_type().is_some()
+    }
+
+    /// Return the entity type if this pid was created with `Pid::entity`
+    pub fn entity_type(&self) -> Option<&str> {
+        let prefix = "rabble.entities.";
+        match self.group {
+            Some(ref group) if group.starts_with(prefix) => Some(&group[prefix.len()..]),
+            _ => None
+        }
+    }
+}
+
 /// Explicitly format Pid in the display format since it is huge when pretty printing and they are
 /// used all over the place.
 impl Debug for Pid {
@@ -3,6 +3,9 @@ use msg::Msg;
 use envelope::Envelope;
 use correlation_id::CorrelationId;
 
+/// Creates the process for a sharded entity, given the entity's pid
+pub type EntityFactory<T> = Box<Fn(&Pid) -> Box<Process<T>> + Send>;
+
 pub trait Process<T> : Send {
     /// Initialize process state if necessary
     fn init(&mut self, _executor_pid: Pid) -> Vec<Envelope<T>> {
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use orset::{ORSet, Delta};
+use node_id::NodeId;
+use pid::Pid;
+
+/// The cluster wide name registry, replicated between cluster servers as an ORSet of
+/// `(name, pid)` registrations.
+///
+/// A name registered on different nodes concurrently, or on both sides of a partition, has more
+/// than one registration once the replicas are joined. Such a name resolves to the lowest of its
+/// pids, so all nodes agree on the winner as soon as they have seen the same registrations.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Registry {
+    orset: ORSet<(String, Pid)>
+}
+
+impl Registry {
+    pub fn new(node: &NodeId) -> Registry {
+        Registry {
+            orset: ORSet::new(node.to_string())
+        }
+    }
+
+    /// Register a name, replacing all registrations of the name seen by this node
+    pub fn register(&mut self, name: String, pid: Pid) -> Vec<Delta<(String, Pid)>> {
+        let mut deltas = self.unregister(&name);
+        deltas.push(self.orset.add((name, pid)));
+        deltas
+    }
+
+    /// Remove all registrations of a name seen by this node
+    pub fn unregister(&mut self, name: &str) -> Vec<Delta<(String, Pid)>> {
+        let registrations: Vec<(String, Pid)> =
+            self.orset.elements().into_iter().filter(|&(ref n, _)| n == name).collect();
+        let mut deltas = Vec::new();
+        for registration in registrations {
+            if let Some(dots) = self.orset.seen(&registration) {
+                deltas.push(self.orset.remove(registration, dots));
+            }
+        }
+        deltas
+    }
+
+    pub fn join(&mut self, other: Registry) {
+        self.orset.join_state(other.orset);
+    }
+
+    pub fn join_delta(&mut self, delta: Delta<(String, Pid)>) -> bool {
+        self.orset.join(delta)
+    }
+
+    /// Map each registered name to the pid it resolves to
+    pub fn resolve(&self) -> HashMap<String, Pid> {
+        let mut names: HashMap<String, Pid> = HashMap::new();
+        for (name, pid) in self.orset.elements() {
+            let lower = names.get(&name).map_or(true, |winner| pid < *winner);
+            if lower {
+                names.insert(name, pid);
+            }
+        }
+        names
+    }
+}
@@ -0,0 +1,171 @@
+use rand::{self, Rng};
+use pid::Pid;
+use process::Process;
+use envelope::Envelope;
+use correlation_id::CorrelationId;
+use msg::{Msg, DeadLetterReason};
+use executor::MailboxSizes;
+use hash_ring::HashRing;
+
+/// Extracts the key used to pick a routee from a user message
+pub type RouterKey<T> = Box<Fn(&T) -> u64 + Send>;
+
+// The number of points each routee has on the hash ring of a `ConsistentHash` router
+const ROUTER_VNODES: usize = 100;
+
+/// Determines which routee a `Router` forwards each user message to
+pub enum RoutingStrategy<T> {
+    /// Cycle through the routees in order
+    RoundRobin,
+
+    /// Pick a routee at random
+    Random,
+
+    /// Pick the routee with the fewest envelopes in its mailbox. The mailboxes of remote routees
+    /// aren't visible, so they are only picked if there are no local routees.
+    SmallestMailbox(MailboxSizes),
+
+    /// Pick the routee owning the key of the message on a consistent hash ring, so that messages
+    /// with the same key go to the same routee as long as the pool doesn't change
+    ConsistentHash(RouterKey<T>)
+}
+
+/// A process that forwards user messages to a pool of routees
+///
+/// Messages are forwarded unchanged, with the original sender and correlation id, so routees
+/// reply directly to the sender. Routees can be local or remote. They are monitored, and removed
+/// from the pool when they terminate. The pool is changed by sending the router a
+/// `Msg::AddRoutee` or `Msg::RemoveRoutee`. A `Msg::GetRoutees` is answered with a
+/// `Msg::Routees`.
+///
+/// User messages received while the pool is empty are sent to the dead letter service.
+pub struct Router<T> {
+    pid: Pid,
+    strategy: RoutingStrategy<T>,
+    routees: Vec<Pid>,
+    ring: HashRing<Pid>,
+    next: usize
+}
+
+impl<T> Router<T> {
+    pub fn new(pid: Pid, strategy: RoutingStrategy<T>, routees: Vec<Pid>) -> Router<T> {
+        let mut router = Router {
+            pid: pid,
+            strategy: strategy,
+            routees: Vec::new(),
+            ring: HashRing::new(ROUTER_VNODES),
+            next: 0
+        };
+        for routee in routees {
+            router.add(routee);
+        }
+        router
+    }
+
+    fn add(&mut self, routee: Pid) -> bool {
+        if self.routees.contains(&routee) {
+            return false;
+        }
+        self.ring.add(routee.clone());
+        self.routees.push(routee);
+        true
+    }
+
+    fn remove(&mut self, routee: &Pid) -> bool {
+        let len = self.routees.len();
+        self.routees.retain(|pid| pid != routee);
+        self.ring.remove(routee);
+        self.routees.len() != len
+    }
+
+    /// Return the routee for a message, or `None` if the pool is empty
+    fn select(&mut self, msg: &T) -> Option<Pid> {
+        if self.routees.is_empty() {
+            return None;
+        }
+        let len = self.routees.len();
+        let index = match self.strategy {
+            RoutingStrategy::RoundRobin => {
+                let index = self.next % len;
+                self.next = index + 1;
+                index
+            },
+            RoutingStrategy::Random => rand::thread_rng().gen_range(0, len),
+            RoutingStrategy::SmallestMailbox(ref sizes) => {
+                // Start from the next routee in order, so that routees with equal mailboxes are
+                // picked in turn
+                let start = self.next % len;
+                self.next = start + 1;
+                let routees = &self.routees;
+                (start..start + len).map(|i| i % len).min_by_key(|&i| {
+                    sizes.get(&routees[i]).unwrap_or(usize::max_value())
+                }).unwrap()
+            },
+            RoutingStrategy::ConsistentHash(ref key) => {
+                return self.ring.owner(&key(msg)).cloned();
+            }
+        };
+        Some(self.routees[index].clone())
+    }
+
+    fn watch(&self, msg: Msg<T>, output: &mut Vec<Envelope<T>>) {
+        let executor = Pid::executor(&self.pid.node);
+        output.push(Envelope::new(executor, self.pid.clone(), msg, None));
+    }
+}
+
+impl<T: Send> Process<T> for Router<T> {
+    fn init(&mut self, executor_pid: Pid) -> Vec<Envelope<T>> {
+        self.routees.iter().map(|routee| {
+            let msg = Msg::Monitor(routee.clone());
+            Envelope::new(executor_pid.clone(), self.pid.clone(), msg, None)
+        }).collect()
+    }
+
+    fn handle(&mut self,
+              msg: Msg<T>,
+              from: Pid,
+              correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<T>>)
+    {
+        match msg {
+            Msg::User(msg) => {
+                match self.select(&msg) {
+                    Some(routee) => {
+                        output.push(Envelope::new(routee, from, Msg::User(msg), correlation_id));
+                    },
+                    None => {
+                        let envelope = Envelope::new(self.pid.clone(),
+                                                     from,
+                                                     Msg::User(msg),
+                                                     correlation_id);
+                        let msg = Msg::DeadLetter {
+                            envelope: Box::new(envelope),
+                            reason: DeadLetterReason::NoProcess
+                        };
+                        let dead_letters = Pid::dead_letters(&self.pid.node);
+                        output.push(Envelope::new(dead_letters, self.pid.clone(), msg, None));
+                    }
+                }
+            },
+            Msg::AddRoutee(routee) => {
+                if self.add(routee.clone()) {
+                    self.watch(Msg::Monitor(routee), output);
+                }
+            },
+            Msg::RemoveRoutee(routee) => {
+                if self.remove(&routee) {
+                    self.watch(Msg::Demonitor(routee), output);
+                }
+            },
+            Msg::Down {pid, ..} => {
+                self.remove(&pid);
+            },
+            Msg::GetRoutees => {
+                let msg = Msg::Routees(self.routees.clone());
+                output.push(Envelope::new(from, self.pid.clone(), msg, correlation_id));
+            },
+            _ => ()
+        }
+    }
+}
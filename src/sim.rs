@@ -0,0 +1,268 @@
+//! A deterministic, single threaded simulator for testing processes.
+//!
+//! The simulator hosts processes from any number of logical nodes in the calling thread. Instead of
+//! running an executor and cluster server per node, envelopes returned by processes are put on a
+//! single queue ordered by virtual time, and delivered one at a time by `Simulator::step`. Timers
+//! started with `Msg::StartTimer` fire in virtual time, so a simulation covering minutes of
+//! timeouts runs in milliseconds.
+//!
+//! Envelopes sent between different nodes cross a simulated network that can drop, delay and
+//! duplicate them. Since delays are chosen independently for each envelope, envelopes may also be
+//! reordered. All randomness comes from a single RNG seeded from `SimConfig::seed`, so a failing
+//! run can be reproduced exactly by running it again with the same seed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use serde::{Serialize, Deserialize};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use envelope::Envelope;
+use pid::Pid;
+use process::Process;
+use node_id::NodeId;
+use msg::Msg;
+use correlation_id::CorrelationId;
+use errors::*;
+
+/// Configuration of the simulated network between nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    /// The seed of the RNG that drives all fault injection
+    pub seed: u64,
+
+    /// The probability that an envelope sent between nodes is dropped
+    pub drop_probability: f64,
+
+    /// The probability that an envelope sent between nodes is delivered twice
+    pub duplicate_probability: f64,
+
+    /// Envelopes sent between nodes are delivered after a random delay in this range, in ms
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64
+}
+
+impl SimConfig {
+    /// A reliable network that delivers envelopes in order after 1ms
+    pub fn new(seed: u64) -> SimConfig {
+        SimConfig {
+            seed: seed,
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            min_delay_ms: 1,
+            max_delay_ms: 1
+        }
+    }
+
+    /// Check that the probabilities and the delay range are valid
+    pub fn validate(&self) -> Result<()> {
+        for &(name, p) in [("drop_probability", self.drop_probability),
+                           ("duplicate_probability", self.duplicate_probability)].iter() {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(ErrorKind::ConfigError(format!("{} must be between 0 and 1",
+                                                          name)).into());
+            }
+        }
+        if self.min_delay_ms > self.max_delay_ms {
+            let msg = format!("min_delay_ms ({}) is greater than max_delay_ms ({})",
+                              self.min_delay_ms, self.max_delay_ms);
+            return Err(ErrorKind::ConfigError(msg).into());
+        }
+        Ok(())
+    }
+}
+
+/// Counts of what happened to envelopes during a simulation
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SimStats {
+    pub delivered: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub timeouts: u64
+}
+
+enum Event<T> {
+    Deliver(Envelope<T>),
+    Timeout(Pid, Option<CorrelationId>)
+}
+
+/// A simulated cluster running in a single thread
+pub struct Simulator<T> {
+    config: SimConfig,
+    rng: XorShiftRng,
+    // Virtual time in ms since the start of the simulation
+    now: u64,
+    // Events are ordered by delivery time, and then by the order they were scheduled in
+    events: BTreeMap<(u64, u64), Event<T>>,
+    next_seq: u64,
+    processes: HashMap<Pid, Box<Process<T>>>,
+    output: Vec<Envelope<T>>,
+    stats: SimStats
+}
+
+impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Simulator<T> {
+    /// Create a simulator, or return a `ConfigError` if the config is invalid
+    pub fn new(config: SimConfig) -> Result<Simulator<T>> {
+        try!(config.validate());
+        let seed = [config.seed as u32, (config.seed >> 32) as u32, 0x9e37_79b9, 0x243f_6a88];
+        Ok(Simulator {
+            config: config,
+            rng: XorShiftRng::from_seed(seed),
+            now: 0,
+            events: BTreeMap::new(),
+            next_seq: 0,
+            processes: HashMap::new(),
+            output: Vec::new(),
+            stats: SimStats::default()
+        })
+    }
+
+    /// The current virtual time in ms
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn stats(&self) -> &SimStats {
+        &self.stats
+    }
+
+    /// Start a process, replacing any process with the same pid
+    pub fn spawn(&mut self, pid: Pid, mut process: Box<Process<T>>) {
+        let envelopes = process.init(Pid::executor(&pid.node));
+        self.processes.insert(pid.clone(), process);
+        for envelope in envelopes {
+            self.send_from(&pid.node, envelope);
+        }
+    }
+
+    /// Remove a process. Envelopes that arrive for it afterwards are returned by `take_output`.
+    pub fn stop(&mut self, pid: &Pid) {
+        self.processes.remove(pid);
+    }
+
+    /// Send an envelope from outside the simulation. It crosses the simulated network if the sender
+    /// and receiver are on different nodes.
+    pub fn send(&mut self, envelope: Envelope<T>) {
+        let node = envelope.from.node.clone();
+        self.send_from(&node, envelope);
+    }
+
+    /// Return all envelopes delivered so far to pids without a running process, such as test
+    /// clients, in delivery order
+    pub fn take_output(&mut self) -> Vec<Envelope<T>> {
+        ::std::mem::replace(&mut self.output, Vec::new())
+    }
+
+    /// Deliver the next envelope or timeout, advancing virtual time to when it is due.
+    ///
+    /// Return false if there is nothing left to deliver.
+    pub fn step(&mut self) -> bool {
+        let key = match self.events.keys().next() {
+            Some(&key) => key,
+            None => return false
+        };
+        let event = self.events.remove(&key).unwrap();
+        self.now = key.0;
+        match event {
+            Event::Deliver(envelope) => self.deliver(envelope),
+            Event::Timeout(pid, correlation_id) => {
+                self.stats.timeouts += 1;
+                let from = Pid::executor(&pid.node);
+                self.deliver(Envelope::new(pid, from, Msg::Timeout, correlation_id));
+            }
+        }
+        true
+    }
+
+    /// Deliver everything due within `ms` of the current time, then advance to the end of that
+    /// interval
+    pub fn run_for(&mut self, ms: u64) {
+        let end = self.now + ms;
+        while self.events.keys().next().map_or(false, |&(time, _)| time <= end) {
+            self.step();
+        }
+        self.now = end;
+    }
+
+    /// Deliver envelopes until there is nothing left to deliver.
+    ///
+    /// This never returns if processes keep restarting timers. Use `run_for` for those.
+    pub fn run_until_idle(&mut self) {
+        while self.step() {}
+    }
+
+    fn deliver(&mut self, envelope: Envelope<T>) {
+        if envelope.to == Pid::executor(&envelope.to.node) {
+            return self.handle_executor_envelope(envelope);
+        }
+        let node = envelope.to.node.clone();
+        let mut output = Vec::new();
+        match self.processes.get_mut(&envelope.to) {
+            Some(process) => {
+                let Envelope {from, msg, correlation_id, ..} = envelope;
+                process.handle(msg, from, correlation_id, &mut output);
+            },
+            None => return self.output.push(envelope)
+        }
+        self.stats.delivered += 1;
+        for envelope in output {
+            self.send_from(&node, envelope);
+        }
+    }
+
+    /// Only timers are supported. Other requests to the executor are ignored.
+    fn handle_executor_envelope(&mut self, envelope: Envelope<T>) {
+        let Envelope {from, msg, correlation_id, ..} = envelope;
+        match msg {
+            Msg::StartTimer(time_in_ms) => {
+                let time = self.now + time_in_ms as u64;
+                self.schedule(time, Event::Timeout(from, correlation_id));
+            },
+            Msg::CancelTimer(correlation_id) => {
+                let cancelled: Vec<_> = self.events.iter().filter_map(|(key, event)| {
+                    match *event {
+                        Event::Timeout(ref pid, ref c_id) if *pid == from &&
+                                                             *c_id == correlation_id => Some(*key),
+                        _ => None
+                    }
+                }).collect();
+                for key in cancelled {
+                    self.events.remove(&key);
+                }
+            },
+            _ => ()
+        }
+    }
+
+    /// Schedule an envelope sent by a process on the given node
+    fn send_from(&mut self, node: &NodeId, envelope: Envelope<T>) {
+        if envelope.to.node == *node {
+            let now = self.now;
+            return self.schedule(now, Event::Deliver(envelope));
+        }
+        if self.rng.gen::<f64>() < self.config.drop_probability {
+            self.stats.dropped += 1;
+            return;
+        }
+        if self.rng.gen::<f64>() < self.config.duplicate_probability {
+            self.stats.duplicated += 1;
+            let time = self.now.saturating_add(self.delay());
+            self.schedule(time, Event::Deliver(envelope.clone()));
+        }
+        let time = self.now.saturating_add(self.delay());
+        self.schedule(time, Event::Deliver(envelope));
+    }
+
+    /// Pick a delay from the inclusive range `[min_delay_ms, max_delay_ms]`
+    fn delay(&mut self) -> u64 {
+        let (min, max) = (self.config.min_delay_ms, self.config.max_delay_ms);
+        match max.checked_add(1) {
+            Some(end) => self.rng.gen_range(min, end),
+            None if min == 0 => self.rng.gen(),
+            None => self.rng.gen_range(min - 1, max) + 1
+        }
+    }
+
+    fn schedule(&mut self, time: u64, event: Event<T>) {
+        self.events.insert((time, self.next_seq), event);
+        self.next_seq += 1;
+    }
+}
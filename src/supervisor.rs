@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use time::{SteadyTime, Duration};
+use pid::Pid;
+use process::Process;
+
+/// Creates a fresh instance of a process each time a supervisor (re)starts it
+pub type ProcessFactory<T> = Box<Fn() -> Box<Process<T>> + Send>;
+
+/// Determines which children get restarted when a child of a supervisor fails
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RestartStrategy {
+    /// Only restart the failed child
+    OneForOne,
+
+    /// Stop all remaining children and then restart all of them
+    OneForAll,
+
+    /// Stop the children started after the failed child and restart them along with it
+    RestForOne
+}
+
+/// A child can either be a worker process or another supervisor, forming a supervision tree.
+pub enum Child<T> {
+    Worker(ProcessFactory<T>),
+    Supervisor(SupervisorSpec<T>)
+}
+
+/// The specification of a single child of a supervisor
+pub struct ChildSpec<T> {
+    pub pid: Pid,
+    pub child: Child<T>
+}
+
+impl<T> ChildSpec<T> {
+    pub fn worker(pid: Pid, factory: ProcessFactory<T>) -> ChildSpec<T> {
+        ChildSpec {
+            pid: pid,
+            child: Child::Worker(factory)
+        }
+    }
+
+    pub fn supervisor(pid: Pid, spec: SupervisorSpec<T>) -> ChildSpec<T> {
+        ChildSpec {
+            pid: pid,
+            child: Child::Supervisor(spec)
+        }
+    }
+}
+
+/// The specification of a supervisor
+///
+/// Children are started in the order they were added and stopped in the reverse order. If more
+/// than `max_restarts` restarts occur within `max_seconds`, the supervisor stops all its children
+/// and fails itself, escalating the failure to its own supervisor if there is one.
+pub struct SupervisorSpec<T> {
+    pub strategy: RestartStrategy,
+    pub max_restarts: usize,
+    pub max_seconds: u64,
+    pub children: Vec<ChildSpec<T>>
+}
+
+impl<T> SupervisorSpec<T> {
+    /// Create a spec with no children that allows 3 restarts in 5 seconds
+    pub fn new(strategy: RestartStrategy) -> SupervisorSpec<T> {
+        SupervisorSpec {
+            strategy: strategy,
+            max_restarts: 3,
+            max_seconds: 5,
+            children: Vec::new()
+        }
+    }
+
+    pub fn max_restarts(mut self, max_restarts: usize, max_seconds: u64) -> SupervisorSpec<T> {
+        self.max_restarts = max_restarts;
+        self.max_seconds = max_seconds;
+        self
+    }
+
+    pub fn child(mut self, child: ChildSpec<T>) -> SupervisorSpec<T> {
+        self.children.push(child);
+        self
+    }
+}
+
+/// The runtime representation of a child inside the executor
+///
+/// Nested supervisors are stored separately in the executor, keyed by their pid.
+pub enum SupervisedChild<T> {
+    Worker(ProcessFactory<T>),
+    Supervisor
+}
+
+/// The runtime state of a supervisor maintained by the executor
+pub struct Supervisor<T> {
+    pub children: Vec<(Pid, SupervisedChild<T>)>,
+    strategy: RestartStrategy,
+    max_restarts: usize,
+    max_seconds: u64,
+    restarts: VecDeque<SteadyTime>
+}
+
+impl<T> Supervisor<T> {
+    pub fn new(strategy: RestartStrategy,
+               max_restarts: usize,
+               max_seconds: u64,
+               children: Vec<(Pid, SupervisedChild<T>)>) -> Supervisor<T> {
+        Supervisor {
+            children: children,
+            strategy: strategy,
+            max_restarts: max_restarts,
+            max_seconds: max_seconds,
+            restarts: VecDeque::new()
+        }
+    }
+
+    /// Record a restart and return false if the maximum restart intensity has been exceeded
+    pub fn record_restart(&mut self) -> bool {
+        let now = SteadyTime::now();
+        let period = Duration::seconds(self.max_seconds as i64);
+        while self.restarts.front().map_or(false, |&t| now - t > period) {
+            self.restarts.pop_front();
+        }
+        self.restarts.push_back(now);
+        self.restarts.len() <= self.max_restarts
+    }
+
+    pub fn clear_restarts(&mut self) {
+        self.restarts.clear();
+    }
+
+    /// Return the indexes of the children to restart, in start order, when `failed` fails
+    pub fn restart_indexes(&self, failed: &Pid) -> Vec<usize> {
+        let index = match self.children.iter().position(|&(ref pid, _)| pid == failed) {
+            Some(index) => index,
+            None => return Vec::new()
+        };
+        match self.strategy {
+            RestartStrategy::OneForOne => vec![index],
+            RestartStrategy::OneForAll => (0..self.children.len()).collect(),
+            RestartStrategy::RestForOne => (index..self.children.len()).collect()
+        }
+    }
+
+    pub fn child_pid(&self, index: usize) -> Option<Pid> {
+        self.children.get(index).map(|&(ref pid, _)| pid.clone())
+    }
+
+    pub fn child_pids(&self) -> Vec<Pid> {
+        self.children.iter().map(|&(ref pid, _)| pid.clone()).collect()
+    }
+
+    pub fn remove_child(&mut self, pid: &Pid) {
+        self.children.retain(|&(ref child, _)| child != pid);
+    }
+}
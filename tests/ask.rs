@@ -0,0 +1,115 @@
+//! Test sending requests to processes and waiting for their replies from outside the executor
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod utils;
+
+use std::thread;
+use std::time::Duration;
+
+use rabble::{
+    Pid,
+    Node,
+    Process,
+    Envelope,
+    Msg,
+    Metric,
+    CorrelationId
+};
+use rabble::errors::ErrorKind;
+
+use utils::{
+    start_node,
+    pid
+};
+
+/// A process that echoes back all user messages
+struct Echo {
+    pid: Pid
+}
+
+impl Process<u64> for Echo {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              from: Pid,
+              correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::User(n) = msg {
+            output.push(Envelope::new(from, self.pid.clone(), Msg::User(n), correlation_id));
+        }
+    }
+}
+
+/// A process that never replies
+struct Silent;
+
+impl Process<u64> for Silent {
+    fn handle(&mut self,
+              _msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<u64>>)
+    {
+    }
+}
+
+/// The number of registered services, which includes the pid of the ask used to get the metrics
+fn services(node: &Node<u64>) -> i64 {
+    let executor = Pid::executor(&node.id);
+    match node.ask(&executor, Msg::GetMetrics, Duration::from_secs(5)).unwrap() {
+        Msg::Metrics(metrics) => {
+            match metrics.into_iter().find(|&(ref name, _)| name == "services") {
+                Some((_, Metric::Gauge(count))) => count,
+                metric => panic!("Unexpected metric {:?}", metric)
+            }
+        },
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+}
+
+#[test]
+fn ask() {
+    let (node, handles) = start_node::<u64>(1);
+
+    let echo = pid("echo", &node.id);
+    node.spawn(&echo, Box::new(Echo {pid: echo.clone()})).unwrap();
+    let silent = pid("silent", &node.id);
+    node.spawn(&silent, Box::new(Silent)).unwrap();
+
+    // Blocking
+    assert_eq!(node.ask(&echo, Msg::User(1), Duration::from_secs(5)).unwrap(), Msg::User(1));
+
+    // Non-blocking
+    let mut handle = node.ask_async(&echo, Msg::User(2), Duration::from_secs(5)).unwrap();
+    loop {
+        if let Some(msg) = handle.try_recv().unwrap() {
+            assert_eq!(msg, Msg::User(2));
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    drop(handle);
+
+    // Timeouts
+    match node.ask(&silent, Msg::User(3), Duration::from_millis(50)) {
+        Err(e) => match *e.kind() {
+            ErrorKind::Timeout(ref pid) => assert_eq!(*pid, silent),
+            _ => panic!("Unexpected error {}", e)
+        },
+        Ok(msg) => panic!("Unexpected reply {:?}", msg)
+    }
+
+    // All temporary reply pids were unregistered
+    assert_eq!(services(&node), 1);
+
+    node.shutdown_and_wait(handles, Duration::from_secs(5));
+}
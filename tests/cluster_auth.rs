@@ -0,0 +1,73 @@
+//! Test authentication of cluster connections with a shared cookie
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use amy::Poller;
+
+use rabble::{
+    Node,
+    RabbleConfigBuilder
+};
+
+use utils::{
+    wait_for,
+    start_node_with_config,
+    test_pid,
+    cluster_server,
+    established,
+    counter
+};
+
+fn start_node(n: usize, cookie: &str) -> (Node<()>, Vec<thread::JoinHandle<()>>) {
+    let config = RabbleConfigBuilder::new().cookie(cookie.to_string()).build().unwrap();
+    start_node_with_config(n, config)
+}
+
+#[test]
+fn cookie_mismatch() {
+    let (node1, mut handles) = start_node(1, "secret");
+    let (node2, handles2) = start_node(2, "secret");
+    let (node3, handles3) = start_node(3, "wrong");
+    handles.extend(handles2);
+    handles.extend(handles3);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node3.register_service(&test_pid(node3.id.clone()), &test_tx).unwrap();
+
+    // Nodes sharing a cookie connect
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        established(&node1, &mut poller, &test_rx) == 1
+    }));
+
+    // A node with a different cookie rejects the connection and never becomes established
+    node1.join(&node3.id).unwrap();
+    let server = cluster_server(&node3.id);
+    assert!(wait_for(time::Duration::seconds(5), || {
+        counter(&node3, &mut poller, &test_rx, server.clone(), "auth_failures") > 0
+    }));
+    assert_eq!(established(&node1, &mut poller, &test_rx), 1);
+    assert_eq!(established(&node3, &mut poller, &test_rx), 0);
+
+    for node in vec![node1, node2, node3] {
+        node.shutdown();
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+}
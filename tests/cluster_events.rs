@@ -0,0 +1,69 @@
+//! Test subscribing to cluster membership and connectivity events
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod utils;
+
+use amy::Poller;
+
+use rabble::{
+    Envelope,
+    Msg,
+    ClusterEvent
+};
+
+use utils::{
+    start_node,
+    test_pid,
+    cluster_server,
+    recv
+};
+
+#[test]
+fn cluster_events() {
+    let (node1, mut handles) = start_node::<()>(1);
+    let (node2, handles2) = start_node::<()>(2);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let test_pid = test_pid(node1.id.clone());
+    node1.register_service(&test_pid, &test_tx).unwrap();
+
+    // Wait for the cluster server to reply to a request sent after the subscription, so that we
+    // know the subscription is in place before joining.
+    let server = cluster_server(&node1.id);
+    node1.send(Envelope::new(server.clone(), test_pid.clone(), Msg::SubscribeClusterEvents, None))
+        .unwrap();
+    node1.send(Envelope::new(server.clone(), test_pid.clone(), Msg::GetMetrics, None)).unwrap();
+    match recv(&mut poller, &test_rx).msg {
+        Msg::Metrics(_) => (),
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+
+    node1.join(&node2.id).unwrap();
+    assert_eq!(recv(&mut poller, &test_rx).msg,
+               Msg::ClusterEvent(ClusterEvent::MemberAdded(node2.id.clone())));
+    assert_eq!(recv(&mut poller, &test_rx).msg,
+               Msg::ClusterEvent(ClusterEvent::NodeConnected(node2.id.clone())));
+
+    node1.leave(&node2.id).unwrap();
+    let mut events = vec![recv(&mut poller, &test_rx).msg, recv(&mut poller, &test_rx).msg];
+    events.sort_by_key(|msg| format!("{:?}", msg));
+    assert_eq!(events, vec![Msg::ClusterEvent(ClusterEvent::MemberRemoved(node2.id.clone())),
+                            Msg::ClusterEvent(ClusterEvent::NodeDisconnected(node2.id.clone()))]);
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
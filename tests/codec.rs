@@ -0,0 +1,106 @@
+//! Test clusters that encode messages between nodes with codecs other than the default
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use amy::Poller;
+
+use rabble::{
+    Pid,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    RabbleConfig,
+    TcpTransport,
+    Codec,
+    BincodeCodec,
+    CborCodec
+};
+
+use utils::{
+    wait_for,
+    node_id,
+    test_pid,
+    pid,
+    recv,
+    established
+};
+
+/// A process that echoes back all user messages
+struct Echo {
+    pid: Pid
+}
+
+impl Process<u64> for Echo {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              from: Pid,
+              correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::User(n) = msg {
+            output.push(Envelope::new(from, self.pid.clone(), Msg::User(n), correlation_id));
+        }
+    }
+}
+
+/// Start two nodes using the codec returned by `codec` and send an envelope round trip between them
+fn echo<F>(codec: F, nodes: (usize, usize)) where F: Fn() -> Box<Codec<u64>> {
+    let start_node = |n| {
+        let config = RabbleConfig::default();
+        let transport = Box::new(TcpTransport::new(&config).unwrap());
+        rabble::rouse_with(node_id(n), config, transport, codec(), None).unwrap()
+    };
+    let (node1, mut handles) = start_node(nodes.0);
+    let (node2, handles2) = start_node(nodes.1);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let test_pid = test_pid(node1.id.clone());
+    node1.register_service(&test_pid, &test_tx).unwrap();
+
+    let echo = pid("echo", &node2.id);
+    node2.spawn(&echo, Box::new(Echo {pid: echo.clone()})).unwrap();
+
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        established(&node1, &mut poller, &test_rx) == 1
+    }));
+
+    let c_id = CorrelationId::request(test_pid.clone(), 0, 0);
+    node1.send(Envelope::new(echo.clone(), test_pid.clone(), Msg::User(42), Some(c_id.clone())))
+        .unwrap();
+    let envelope = recv(&mut poller, &test_rx);
+    assert_eq!(envelope.msg, Msg::User(42));
+    assert_eq!(envelope.from, echo);
+    assert_eq!(envelope.correlation_id, Some(c_id));
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn bincode() {
+    echo(|| Box::new(BincodeCodec), (1, 2));
+}
+
+#[test]
+fn cbor() {
+    echo(|| Box::new(CborCodec), (3, 4));
+}
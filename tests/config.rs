@@ -0,0 +1,95 @@
+//! Test loading of node configuration
+
+extern crate rabble;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use rabble::{
+    NodeId,
+    RabbleConfig,
+    RabbleConfigBuilder,
+    TimerResolution,
+    MailboxConfig,
+    OverflowPolicy,
+    TcpTransport,
+    MsgpackCodec
+};
+
+fn write_config(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(name);
+    let mut file = File::create(&path).unwrap();
+    file.write_all(contents.as_bytes()).unwrap();
+    path
+}
+
+#[test]
+fn load_config_files() {
+    let expected = RabbleConfigBuilder::new()
+        .tick_time_ms(500)
+        .max_frame_size(1024)
+        .timer_resolutions(vec![TimerResolution::Ms, TimerResolution::Sec])
+        .default_mailbox(MailboxConfig::bounded(100, OverflowPolicy::DropOldest))
+        .build().unwrap();
+
+    let toml = write_config("rabble_config_test.toml", r#"
+        tick_time_ms = 500
+        max_frame_size = 1024
+        timer_resolutions = ["Ms", "Sec"]
+
+        [default_mailbox]
+        capacity = 100
+        overflow = "DropOldest"
+    "#);
+    assert_eq!(RabbleConfig::from_file(&toml).unwrap(), expected);
+
+    let json = write_config("rabble_config_test.json", r#"{
+        "tick_time_ms": 500,
+        "max_frame_size": 1024,
+        "timer_resolutions": ["Ms", "Sec"],
+        "default_mailbox": {"capacity": 100, "overflow": "DropOldest"}
+    }"#);
+    assert_eq!(RabbleConfig::from_file(&json).unwrap(), expected);
+
+    let unknown = write_config("rabble_config_test.yaml", "tick_time_ms: 500");
+    assert!(RabbleConfig::from_file(&unknown).is_err());
+
+    for path in vec![toml, json, unknown] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn redact_cookie() {
+    let config = RabbleConfigBuilder::new().cookie("secret cookie".to_string()).build().unwrap();
+    assert!(!format!("{:?}", config).contains("secret cookie"));
+}
+
+#[test]
+fn reject_invalid_configs() {
+    assert!(RabbleConfigBuilder::new().tick_time_ms(0).build().is_err());
+    assert!(RabbleConfigBuilder::new().executor_channel_bound(0).build().is_err());
+    assert!(RabbleConfigBuilder::new().timer_resolutions(Vec::new()).build().is_err());
+    assert!(RabbleConfigBuilder::new().protocol_versions(2, 1).build().is_err());
+    assert!(RabbleConfig::default().validate().is_ok());
+
+    let toml = write_config("rabble_invalid_config_test.toml", "tick_time_ms = 0");
+    assert!(RabbleConfig::from_file(&toml).is_err());
+    let versions = write_config("rabble_invalid_versions_test.toml", r#"
+        min_protocol_version = 2
+        max_protocol_version = 1
+    "#);
+    assert!(RabbleConfig::from_file(&versions).is_err());
+    for path in vec![toml, versions] {
+        fs::remove_file(path).unwrap();
+    }
+
+    // Configs built without the builder are validated when starting a node
+    let config = RabbleConfig {tick_time_ms: 0, ..RabbleConfig::default()};
+    let node_id = NodeId {name: "node1".to_string(), addr: "127.0.0.1:11001".to_string()};
+    let transport = Box::new(TcpTransport::new(&config).unwrap());
+    let result = rabble::rouse_with::<()>(node_id, config, transport, Box::new(MsgpackCodec), None);
+    assert!(result.is_err());
+}
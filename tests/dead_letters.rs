@@ -0,0 +1,77 @@
+//! Test forwarding of undeliverable envelopes to the dead letter service
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod utils;
+
+use amy::Poller;
+
+use rabble::{
+    Pid,
+    Envelope,
+    Msg,
+    DeadLetterReason,
+    UndeliverableReason
+};
+
+use utils::{
+    start_node,
+    node_id,
+    test_pid,
+    pid,
+    recv,
+    counter
+};
+
+#[test]
+fn dead_letters() {
+    let (node, handles) = start_node::<u64>(1);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let (dead_tx, dead_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let test_pid = test_pid(node.id.clone());
+    node.register_service(&test_pid, &test_tx).unwrap();
+    node.register_service(&Pid::dead_letters(&node.id), &dead_tx).unwrap();
+
+    // An envelope for a pid that doesn't exist on this node
+    let missing = pid("missing", &node.id);
+    let envelope = Envelope::new(missing, test_pid.clone(), Msg::User(0), None);
+    node.send(envelope.clone()).unwrap();
+    let dead_letter = recv(&mut poller, &dead_rx);
+    assert_eq!(dead_letter.msg, Msg::DeadLetter {
+        envelope: Box::new(envelope),
+        reason: DeadLetterReason::NoProcess
+    });
+
+    // An envelope for a node that isn't a member of the cluster is bounced to the sender as well
+    let remote = pid("remote", &node_id(2));
+    let envelope = Envelope::new(remote, test_pid.clone(), Msg::User(1), None);
+    node.send(envelope.clone()).unwrap();
+    assert_eq!(recv(&mut poller, &test_rx).msg,
+               Msg::Undeliverable(UndeliverableReason::NotAMember));
+    let dead_letter = recv(&mut poller, &dead_rx);
+    assert_eq!(dead_letter.msg, Msg::DeadLetter {
+        envelope: Box::new(envelope),
+        reason: DeadLetterReason::UnknownNode
+    });
+
+    // Both dead letters are counted by reason
+    let executor = Pid::executor(&node.id);
+    for name in vec!["dead_letters_no_process", "dead_letters_unknown_node"] {
+        assert_eq!(counter(&node, &mut poller, &test_rx, executor.clone(), name), 1);
+    }
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+//! Test that a peer sending undecodable frames is blacklisted without affecting other peers
+//!
+//! Run with `cargo test --features fault_injection`
+#![cfg(feature = "fault_injection")]
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use amy::Poller;
+
+use rabble::{
+    Node,
+    RabbleConfigBuilder,
+    Fault
+};
+
+use utils::{
+    wait_for,
+    start_node_with_config,
+    test_pid,
+    cluster_status
+};
+
+fn start_node(n: usize) -> (Node<()>, Vec<thread::JoinHandle<()>>) {
+    let config = RabbleConfigBuilder::new()
+        .tick_time_ms(100)
+        .request_timeout_ms(500)
+        .blacklist_timeout_ms(60000)
+        .build().unwrap();
+    start_node_with_config(n, config)
+}
+
+#[test]
+fn decode_errors_only_affect_the_offending_peer() {
+    let (node1, mut handles) = start_node(1);
+    let (node2, handles2) = start_node(2);
+    let (node3, handles3) = start_node(3);
+    handles.extend(handles2);
+    handles.extend(handles3);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    for node in &[&node1, &node2, &node3] {
+        node.register_service(&test_pid(node.id.clone()), &test_tx).unwrap();
+    }
+
+    // Every frame node2 receives from node1 fails to decode
+    node2.inject_fault(&node1.id, Fault::Corrupt).unwrap();
+    node1.join(&node2.id).unwrap();
+    node1.join(&node3.id).unwrap();
+
+    // node2 blacklists node1 but keeps running and connects to node3
+    assert!(wait_for(time::Duration::seconds(10), || {
+        let status = cluster_status(&node2, &mut poller, &test_rx);
+        status.blacklisted.contains(&node1.id) && status.established.contains(&node3.id)
+    }));
+    let status = cluster_status(&node2, &mut poller, &test_rx);
+    assert!(!status.established.contains(&node1.id));
+    assert!(!status.errors.is_empty());
+
+    // node1 and node3 are unaffected
+    assert!(wait_for(time::Duration::seconds(10), || {
+        cluster_status(&node1, &mut poller, &test_rx).established.contains(&node3.id)
+    }));
+    assert!(cluster_status(&node3, &mut poller, &test_rx).blacklisted.is_empty());
+
+    for node in vec![node1, node2, node3] {
+        node.shutdown();
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! Test running processes on multiple executor worker threads
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use std::sync::mpsc;
+use std::time::Duration;
+use amy::Poller;
+
+use rabble::{
+    Pid,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    ExecutorStatus,
+    RabbleConfigBuilder
+};
+
+use utils::{
+    wait_for,
+    start_node,
+    start_node_with_config,
+    test_pid,
+    pid,
+    recv
+};
+
+const NUM_WORKERS: usize = 4;
+const NUM_PROCESSES: usize = 20;
+const NUM_MSGS: usize = 100;
+
+/// A process that verifies that it receives messages in the order they were sent
+struct Sequencer {
+    pid: Pid,
+    expected: usize,
+
+    /// Don't do this in production!!!
+    /// This is only here to signal to the test that all messages were received in order.
+    tx: mpsc::Sender<(Pid, bool)>
+}
+
+impl Process<usize> for Sequencer {
+    fn handle(&mut self,
+              msg: Msg<usize>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<usize>>)
+    {
+        if let Msg::User(n) = msg {
+            if n != self.expected {
+                self.tx.send((self.pid.clone(), false)).unwrap();
+            }
+            self.expected += 1;
+            if self.expected == NUM_MSGS {
+                self.tx.send((self.pid.clone(), true)).unwrap();
+            }
+        }
+    }
+}
+
+/// A process that takes a while to handle each message
+struct Slow;
+
+impl Process<usize> for Slow {
+    fn handle(&mut self,
+              _msg: Msg<usize>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<usize>>)
+    {
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn per_sender_fifo_across_workers() {
+    let config = RabbleConfigBuilder::new().executor_workers(NUM_WORKERS).build().unwrap();
+    let (node, handles) = start_node_with_config::<usize>(1, config);
+    let (tx, rx) = mpsc::channel();
+
+    let pids: Vec<Pid> = (0..NUM_PROCESSES).map(|i| pid(&format!("seq{}", i), &node.id)).collect();
+    for pid in &pids {
+        let process = Sequencer {pid: pid.clone(), expected: 0, tx: tx.clone()};
+        node.spawn(pid, Box::new(process)).unwrap();
+    }
+
+    let from = test_pid(node.id.clone());
+    for n in 0..NUM_MSGS {
+        for pid in &pids {
+            node.send(Envelope::new(pid.clone(), from.clone(), Msg::User(n), None)).unwrap();
+        }
+    }
+
+    for _ in 0..NUM_PROCESSES {
+        let (pid, in_order) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(in_order, "{} received messages out of order", pid);
+    }
+
+    // The status reports all workers and the processes spread across them
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node.register_service(&from, &test_tx).unwrap();
+    node.executor_status(CorrelationId::pid(from.clone())).unwrap();
+    match recv(&mut poller, &test_rx).msg {
+        Msg::ExecutorStatus(ExecutorStatus {total_processes, workers, ..}) => {
+            assert_eq!(total_processes, NUM_PROCESSES);
+            assert_eq!(workers.len(), NUM_WORKERS);
+            assert_eq!(workers.iter().map(|w| w.processes).sum::<usize>(), NUM_PROCESSES);
+        },
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn replacing_a_process_discards_its_mailbox() {
+    let (node, handles) = start_node::<usize>(1);
+    let slow = pid("slow", &node.id);
+    let from = test_pid(node.id.clone());
+    node.spawn(&slow, Box::new(Slow)).unwrap();
+    for n in 0..50 {
+        node.send(Envelope::new(slow.clone(), from.clone(), Msg::User(n), None)).unwrap();
+    }
+
+    // Respawning the pid while its mailbox is full leaves no envelopes pending
+    node.spawn(&slow, Box::new(Slow)).unwrap();
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node.register_service(&from, &test_tx).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        node.executor_status(CorrelationId::pid(from.clone())).unwrap();
+        match recv(&mut poller, &test_rx).msg {
+            Msg::ExecutorStatus(ExecutorStatus {total_processes, workers, ..}) => {
+                assert_eq!(total_processes, 1);
+                workers[0].pending_envelopes == 0
+            },
+            msg => panic!("Unexpected msg {:?}", msg)
+        }
+    }));
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
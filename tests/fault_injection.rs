@@ -0,0 +1,201 @@
+//! Test partitioning nodes with injected network faults
+//!
+//! Run with `cargo test --features fault_injection`
+#![cfg(feature = "fault_injection")]
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use std::ops::Range;
+use amy::{Poller, Receiver};
+
+use rabble::{
+    Node,
+    Envelope,
+    Msg,
+    RabbleConfigBuilder,
+    Fault
+};
+
+use utils::{
+    wait_for,
+    start_node_with_config,
+    test_pid,
+    cluster_status,
+    established
+};
+
+fn start_node(n: usize) -> (Node<u64>, Vec<thread::JoinHandle<()>>) {
+    // Tick quickly so that partitioned peers are detected and reconnected within a second or two
+    let config = RabbleConfigBuilder::new()
+        .tick_time_ms(100)
+        .request_timeout_ms(500)
+        .min_heartbeat_std_dev_ms(50)
+        .acceptable_heartbeat_pause_ms(200)
+        .build().unwrap();
+    start_node_with_config(n, config)
+}
+
+fn wait_for_established(node: &Node<u64>,
+                        poller: &mut Poller,
+                        rx: &Receiver<Envelope<u64>>,
+                        expected: usize)
+{
+    assert!(wait_for(time::Duration::seconds(10), || established(node, poller, rx) == expected));
+}
+
+#[test]
+fn partition_and_heal() {
+    let (node1, mut handles) = start_node(1);
+    let (node2, handles2) = start_node(2);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+
+    node1.join(&node2.id).unwrap();
+    wait_for_established(&node1, &mut poller, &test_rx, 1);
+
+    // Dropping traffic on one side is enough to partition both nodes
+    node1.inject_fault(&node2.id, Fault::Drop).unwrap();
+    wait_for_established(&node1, &mut poller, &test_rx, 0);
+    wait_for_established(&node2, &mut poller, &test_rx, 0);
+
+    // Both nodes remain members, so they reconnect once the partition heals
+    node1.clear_fault(&node2.id).unwrap();
+    wait_for_established(&node1, &mut poller, &test_rx, 1);
+    wait_for_established(&node2, &mut poller, &test_rx, 1);
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn dropped_peer_is_never_established() {
+    let (node1, mut handles) = start_node(7);
+    let (node2, handles2) = start_node(8);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+
+    // The handshake is dropped whichever node connects, so neither side ever establishes a
+    // connection, even after several request timeouts and reconnects
+    node1.inject_fault(&node2.id, Fault::Drop).unwrap();
+    node1.join(&node2.id).unwrap();
+    node2.join(&node1.id).unwrap();
+    assert!(!wait_for(time::Duration::seconds(3), || {
+        established(&node1, &mut poller, &test_rx) != 0 ||
+            established(&node2, &mut poller, &test_rx) != 0
+    }));
+
+    node1.clear_fault(&node2.id).unwrap();
+    wait_for_established(&node1, &mut poller, &test_rx, 1);
+    wait_for_established(&node2, &mut poller, &test_rx, 1);
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+/// Send the given user messages from the test service on `from` to the one on `to`
+fn send(from: &Node<u64>, to: &Node<u64>, ns: Range<u64>) {
+    for n in ns {
+        let (to, from_pid) = (test_pid(to.id.clone()), test_pid(from.id.clone()));
+        from.send(Envelope::new(to, from_pid, Msg::User(n), None)).unwrap();
+    }
+}
+
+/// Receive `count` user messages sent to the test services
+fn recv(poller: &mut Poller, rx: &Receiver<Envelope<u64>>, count: usize) -> Vec<u64> {
+    let mut received = Vec::new();
+    assert!(wait_for(time::Duration::seconds(10), || {
+        poller.wait(100).unwrap();
+        while let Ok(envelope) = rx.try_recv() {
+            match envelope.msg {
+                Msg::User(n) => received.push(n),
+                msg => panic!("Unexpected msg {:?}", msg)
+            }
+        }
+        received.len() >= count
+    }));
+    received
+}
+
+#[test]
+fn delay_preserves_order() {
+    let (node1, mut handles) = start_node(3);
+    let (node2, handles2) = start_node(4);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+
+    node1.join(&node2.id).unwrap();
+    wait_for_established(&node1, &mut poller, &test_rx, 1);
+    wait_for_established(&node2, &mut poller, &test_rx, 1);
+
+    // Frames sent after the delay is shortened or removed don't overtake delayed frames
+    node1.inject_fault(&node2.id, Fault::Delay(200)).unwrap();
+    send(&node1, &node2, 0..10);
+    node1.inject_fault(&node2.id, Fault::Delay(10)).unwrap();
+    send(&node1, &node2, 10..20);
+    node1.clear_fault(&node2.id).unwrap();
+    send(&node1, &node2, 20..30);
+    assert_eq!(recv(&mut poller, &test_rx, 30), (0..30).collect::<Vec<_>>());
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn corrupt_frames_blacklist_the_peer() {
+    let (node1, mut handles) = start_node(5);
+    let (node2, handles2) = start_node(6);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+
+    node1.join(&node2.id).unwrap();
+    wait_for_established(&node2, &mut poller, &test_rx, 1);
+
+    // Frames from node1 fail to decode on node2, which disconnects and blacklists node1
+    node2.inject_fault(&node1.id, Fault::Corrupt).unwrap();
+    wait_for_established(&node2, &mut poller, &test_rx, 0);
+    assert!(cluster_status(&node2, &mut poller, &test_rx).blacklisted.contains(&node1.id));
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
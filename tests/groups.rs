@@ -0,0 +1,122 @@
+//! Test sending envelopes to all members of a process group, locally and across the cluster
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use rabble::{
+    Pid,
+    Node,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId
+};
+
+use utils::{
+    wait_for,
+    start_node
+};
+
+/// A process that reports every user message it receives
+struct Member {
+    pid: Pid,
+    tx: mpsc::Sender<(String, u64)>
+}
+
+impl Process<u64> for Member {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::User(n) = msg {
+            self.tx.send((self.pid.name.clone(), n)).unwrap();
+        }
+    }
+}
+
+fn spawn(node: &Node<u64>, name: &str, group: &str, tx: &mpsc::Sender<(String, u64)>) -> Pid {
+    let pid = Pid {name: name.to_string(), group: Some(group.to_string()), node: node.id.clone()};
+    node.spawn(&pid, Box::new(Member {pid: pid.clone(), tx: tx.clone()})).unwrap();
+    pid
+}
+
+fn send(node: &Node<u64>, to: Pid, n: u64) {
+    let from = Pid {name: "sender".to_string(), group: None, node: node.id.clone()};
+    node.send(Envelope::new(to, from, Msg::User(n), None)).unwrap();
+}
+
+/// Collect the next `count` messages received by group members, sorted by member name
+fn received(rx: &mpsc::Receiver<(String, u64)>, count: usize) -> Vec<(String, u64)> {
+    let mut received: Vec<(String, u64)> = (0..count).map(|_| {
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    }).collect();
+    received.sort();
+    received
+}
+
+fn expected(names: &[&str], n: u64) -> Vec<(String, u64)> {
+    names.iter().map(|name| (name.to_string(), n)).collect()
+}
+
+#[test]
+fn send_to_groups() {
+    let (node1, mut handles) = start_node::<u64>(1);
+    let (node2, handles2) = start_node::<u64>(2);
+    handles.extend(handles2);
+
+    let (tx, rx) = mpsc::channel();
+    let a = spawn(&node1, "a", "replicas", &tx);
+    spawn(&node1, "b", "replicas", &tx);
+    spawn(&node1, "c", "other", &tx);
+    spawn(&node2, "d", "replicas", &tx);
+    node1.join(&node2.id).unwrap();
+
+    // Local group sends only reach members on the addressed node
+    send(&node1, Pid::group_members("replicas", &node1.id), 1);
+    assert_eq!(received(&rx, 2), expected(&["a", "b"], 1));
+    send(&node1, Pid::group_members("replicas", &node2.id), 2);
+    assert_eq!(received(&rx, 1), expected(&["d"], 2));
+
+    // Send cluster wide until node2's groups have reached node1
+    assert!(wait_for(time::Duration::seconds(10), || {
+        send(&node1, Pid::cluster_group_members("replicas", &node1.id), 0);
+        rx.try_iter().any(|(name, _)| name == "d")
+    }));
+    thread::sleep(Duration::from_millis(100));
+    let _: Vec<_> = rx.try_iter().collect();
+
+    // Cluster wide group sends reach every member on every node
+    send(&node1, Pid::cluster_group_members("replicas", &node1.id), 3);
+    assert_eq!(received(&rx, 3), expected(&["a", "b", "d"], 3));
+
+    // Stopped processes leave their group
+    node1.stop(&a).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    send(&node2, Pid::cluster_group_members("replicas", &node2.id), 4);
+    assert_eq!(received(&rx, 2), expected(&["b", "d"], 4));
+    thread::sleep(Duration::from_millis(100));
+    assert!(rx.try_recv().is_err());
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
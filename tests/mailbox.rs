@@ -0,0 +1,133 @@
+//! Test bounded process mailboxes and worker channels
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod utils;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use amy::Poller;
+
+use rabble::{
+    Pid,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    MailboxConfig,
+    OverflowPolicy,
+    RabbleConfigBuilder
+};
+
+use utils::{
+    start_node,
+    start_node_with_config,
+    test_pid,
+    pid,
+    counter
+};
+
+/// A process that blocks its worker thread while handling the first message it receives
+struct Blocker {
+    /// Don't do this in production!!!
+    /// This is only here so the test controls when the process finishes handling its first message.
+    gate: Option<mpsc::Receiver<()>>,
+
+    /// Signals the test that the process is blocked
+    blocked: Option<mpsc::Sender<()>>
+}
+
+impl Process<u64> for Blocker {
+    fn handle(&mut self,
+              _msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<u64>>)
+    {
+        if let Some(blocked) = self.blocked.take() {
+            blocked.send(()).unwrap();
+        }
+        if let Some(gate) = self.gate.take() {
+            gate.recv().unwrap();
+        }
+    }
+}
+
+#[test]
+fn reject_when_mailbox_full() {
+    let (node, handles) = start_node::<u64>(1);
+    let (gate_tx, gate_rx) = mpsc::channel();
+
+    let blocker = pid("blocker", &node.id);
+    let mailbox = MailboxConfig::bounded(1, OverflowPolicy::Reject);
+    let process = Box::new(Blocker {gate: Some(gate_rx), blocked: None});
+    node.spawn_with_mailbox(&blocker, process, mailbox).unwrap();
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let from = test_pid(node.id.clone());
+    node.register_service(&from, &test_tx).unwrap();
+
+    // The first message blocks the process. The second fills the mailbox and the third is
+    // rejected.
+    for n in 0..3 {
+        let correlation_id = CorrelationId::request(from.clone(), 0, n);
+        node.send(Envelope::new(blocker.clone(), from.clone(), Msg::User(n),
+                                Some(correlation_id))).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+    gate_tx.send(()).unwrap();
+
+    assert_eq!(poller.wait(5000).unwrap().len(), 1);
+    let envelope = test_rx.try_recv().unwrap();
+    assert_eq!(envelope.msg, Msg::MailboxFull);
+    assert_eq!(envelope.from, blocker);
+    assert_eq!(envelope.correlation_id, Some(CorrelationId::request(from.clone(), 0, 2)));
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn dead_letter_when_worker_channel_full() {
+    let config = RabbleConfigBuilder::new().worker_channel_bound(2).build().unwrap();
+    let (node, handles) = start_node_with_config::<u64>(2, config);
+    let (gate_tx, gate_rx) = mpsc::channel();
+    let (blocked_tx, blocked_rx) = mpsc::channel();
+
+    let blocker = pid("blocker", &node.id);
+    let process = Box::new(Blocker {gate: Some(gate_rx), blocked: Some(blocked_tx)});
+    node.spawn(&blocker, process).unwrap();
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let from = test_pid(node.id.clone());
+    node.register_service(&from, &test_tx).unwrap();
+
+    // The first message blocks the worker. The next two fill the worker channel, and the rest are
+    // sent to the dead letter service.
+    node.send(Envelope::new(blocker.clone(), from.clone(), Msg::User(0), None)).unwrap();
+    blocked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    for n in 1..5 {
+        node.send(Envelope::new(blocker.clone(), from.clone(), Msg::User(n), None)).unwrap();
+    }
+    let executor = Pid::executor(&node.id);
+    assert_eq!(counter(&node, &mut poller, &test_rx, executor, "dead_letters_mailbox_full"), 2);
+    gate_tx.send(()).unwrap();
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
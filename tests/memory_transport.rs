@@ -0,0 +1,72 @@
+//! Test forming a cluster over the in-memory transport
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use amy::Poller;
+
+use rabble::{
+    NodeId,
+    Node,
+    Envelope,
+    Msg,
+    RabbleConfig,
+    MemoryTransport,
+    MsgpackCodec
+};
+
+use utils::{
+    wait_for,
+    test_pid,
+    recv,
+    established
+};
+
+fn start_node(n: usize, transport: &MemoryTransport) -> (Node<u64>, Vec<thread::JoinHandle<()>>) {
+    let node_id = NodeId {name: format!("node{}", n), addr: format!("memory-node{}", n)};
+    let transport = Box::new(transport.clone());
+    let codec = Box::new(MsgpackCodec);
+    rabble::rouse_with(node_id, RabbleConfig::default(), transport, codec, None).unwrap()
+}
+
+#[test]
+fn join_in_memory() {
+    let transport = MemoryTransport::new(&RabbleConfig::default());
+    let (node1, mut handles) = start_node(1, &transport);
+    let (node2, handles2) = start_node(2, &transport);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        established(&node1, &mut poller, &test_rx) == 1 &&
+            established(&node2, &mut poller, &test_rx) == 1
+    }));
+
+    // Envelopes reach the other node
+    let (to, from) = (test_pid(node2.id.clone()), test_pid(node1.id.clone()));
+    node1.send(Envelope::new(to, from, Msg::User(42), None)).unwrap();
+    assert_eq!(recv(&mut poller, &test_rx).msg, Msg::User(42));
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
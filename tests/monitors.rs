@@ -0,0 +1,247 @@
+//! Test process monitors and links
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::sync::mpsc;
+use std::time::Duration;
+use amy::Poller;
+
+use rabble::{
+    Pid,
+    Node,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    DownReason
+};
+
+use utils::{
+    wait_for,
+    start_node,
+    node_id,
+    pid,
+    test_pid,
+    established,
+    gauge
+};
+
+/// A process that monitors or links to another pid and forwards all messages it receives to the
+/// test.
+struct Watcher {
+    pid: Pid,
+    target: Pid,
+    link: bool,
+
+    /// Don't do this in production!!!
+    /// This is only here to signal to the test that the process received a message.
+    tx: mpsc::Sender<Msg<()>>
+}
+
+impl Process<()> for Watcher {
+    fn init(&mut self, executor_pid: Pid) -> Vec<Envelope<()>> {
+        let msg = if self.link {
+            Msg::Link(self.target.clone())
+        } else {
+            Msg::Monitor(self.target.clone())
+        };
+        vec![Envelope::new(executor_pid, self.pid.clone(), msg, None)]
+    }
+
+    fn handle(&mut self,
+              msg: Msg<()>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<()>>)
+    {
+        self.tx.send(msg).unwrap();
+    }
+}
+
+impl Watcher {
+    fn new(name: &str, node: &Node<()>, target: &Pid, link: bool, tx: &mpsc::Sender<Msg<()>>)
+        -> Watcher
+    {
+        Watcher {
+            pid: pid(name, &node.id),
+            target: target.clone(),
+            link: link,
+            tx: tx.clone()
+        }
+    }
+}
+
+/// A process that panics when it receives a user msg
+struct Target;
+
+impl Process<()> for Target {
+    fn handle(&mut self,
+              _msg: Msg<()>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<()>>)
+    {
+        panic!("Target received a message");
+    }
+}
+
+#[test]
+fn monitor() {
+    let (node, handles) = start_node::<()>(1);
+    let (tx, rx) = mpsc::channel();
+    let target = pid("target", &node.id);
+    let timeout = Duration::from_secs(5);
+
+    // Monitoring a pid that doesn't exist results in an immediate Down
+    let watcher = Watcher::new("watcher1", &node, &target, false, &tx);
+    node.spawn(&watcher.pid.clone(), Box::new(watcher)).unwrap();
+    assert_eq!(rx.recv_timeout(timeout).unwrap(),
+               Msg::Down {pid: target.clone(), reason: DownReason::NoProcess});
+
+    // Stopping a monitored process results in a Down
+    node.spawn(&target, Box::new(Target)).unwrap();
+    let watcher = Watcher::new("watcher2", &node, &target, false, &tx);
+    node.spawn(&watcher.pid.clone(), Box::new(watcher)).unwrap();
+    node.stop(&target).unwrap();
+    assert_eq!(rx.recv_timeout(timeout).unwrap(),
+               Msg::Down {pid: target.clone(), reason: DownReason::Stopped});
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn link() {
+    let (node, handles) = start_node::<()>(2);
+    let (tx, rx) = mpsc::channel();
+    let target = pid("target", &node.id);
+
+    node.spawn(&target, Box::new(Target)).unwrap();
+    let watcher = Watcher::new("watcher", &node, &target, true, &tx);
+    node.spawn(&watcher.pid.clone(), Box::new(watcher)).unwrap();
+
+    // A panic in a linked process results in an Exit
+    node.send(Envelope::new(target.clone(), target.clone(), Msg::User(()), None)).unwrap();
+    match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+        Msg::Exit {pid, reason: DownReason::Panicked(_)} => assert_eq!(pid, target),
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn remote_node_down() {
+    let (node1, handles) = start_node::<()>(3);
+    let (node2, handles2) = start_node::<()>(4);
+    let (tx, rx) = mpsc::channel();
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(10), || {
+        established(&node1, &mut poller, &test_rx) == 1
+    }));
+
+    let monitored = pid("monitored", &node2.id);
+    let linked = pid("linked", &node2.id);
+    node2.spawn(&monitored, Box::new(Target)).unwrap();
+    node2.spawn(&linked, Box::new(Target)).unwrap();
+    let monitor = Watcher::new("monitor", &node1, &monitored, false, &tx);
+    let link = Watcher::new("link", &node1, &linked, true, &tx);
+    node1.spawn(&monitor.pid.clone(), Box::new(monitor)).unwrap();
+    node1.spawn(&link.pid.clone(), Box::new(link)).unwrap();
+
+    // Losing the connection to the node of a watched pid results in a Down or Exit
+    node2.shutdown_and_wait(handles2, Duration::from_secs(5));
+    let mut msgs = vec![rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+                        rx.recv_timeout(Duration::from_secs(10)).unwrap()];
+    msgs.sort_by_key(|msg| format!("{:?}", msg));
+    assert_eq!(msgs, vec![Msg::Down {pid: monitored, reason: DownReason::NodeDown},
+                          Msg::Exit {pid: linked, reason: DownReason::NodeDown}]);
+
+    node1.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn unreachable_node() {
+    let (node, handles) = start_node::<()>(5);
+    let (tx, rx) = mpsc::channel();
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node.register_service(&test_pid(node.id.clone()), &test_tx).unwrap();
+
+    // Watching a pid on a node that isn't a member of the cluster results in a Down or Exit, and
+    // the watch is forgotten
+    let target = pid("target", &node_id(9));
+    let monitor = Watcher::new("monitor", &node, &target, false, &tx);
+    node.spawn(&monitor.pid.clone(), Box::new(monitor)).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+               Msg::Down {pid: target.clone(), reason: DownReason::NodeDown});
+    let link = Watcher::new("link", &node, &target, true, &tx);
+    node.spawn(&link.pid.clone(), Box::new(link)).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+               Msg::Exit {pid: target.clone(), reason: DownReason::NodeDown});
+    let executor = Pid::executor(&node.id);
+    assert_eq!(gauge(&node, &mut poller, &test_rx, executor.clone(), "monitors"), 0);
+    assert_eq!(gauge(&node, &mut poller, &test_rx, executor, "links"), 0);
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn remote_watcher_exits() {
+    let (node1, mut handles) = start_node::<()>(6);
+    let (node2, handles2) = start_node::<()>(7);
+    handles.extend(handles2);
+    let (tx, _rx) = mpsc::channel();
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+    node1.join(&node2.id).unwrap();
+
+    let target = pid("target", &node2.id);
+    node2.spawn(&target, Box::new(Target)).unwrap();
+    let monitor = Watcher::new("monitor", &node1, &target, false, &tx);
+    let monitor_pid = monitor.pid.clone();
+    node1.spawn(&monitor_pid, Box::new(monitor)).unwrap();
+    let executor = Pid::executor(&node2.id);
+    assert!(wait_for(time::Duration::seconds(10), || {
+        gauge(&node2, &mut poller, &test_rx, executor.clone(), "monitors") == 1
+    }));
+
+    // The remote executor forgets the monitor once the watcher terminates
+    node1.stop(&monitor_pid).unwrap();
+    assert!(wait_for(time::Duration::seconds(10), || {
+        gauge(&node2, &mut poller, &test_rx, executor.clone(), "monitors") == 0
+    }));
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+//! Test addressing processes by local and cluster wide names
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::time::Duration;
+
+use rabble::{
+    Pid,
+    Node,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId
+};
+
+use utils::{
+    wait_for,
+    start_node
+};
+
+/// A process that replies to all user messages with its own id
+struct Id {
+    id: u64,
+    pid: Pid
+}
+
+impl Process<u64> for Id {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              from: Pid,
+              correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::User(_) = msg {
+            output.push(Envelope::new(from, self.pid.clone(), Msg::User(self.id), correlation_id));
+        }
+    }
+}
+
+fn spawn(node: &Node<u64>, name: &str, id: u64) -> Pid {
+    let pid = Pid {name: name.to_string(), group: None, node: node.id.clone()};
+    node.spawn(&pid, Box::new(Id {id: id, pid: pid.clone()})).unwrap();
+    pid
+}
+
+/// Ask the process registered with `name`, as resolved by `node`, for its id. Returns `None` if
+/// the name isn't registered.
+fn ask(node: &Node<u64>, name: &str) -> Option<u64> {
+    match node.ask(&Pid::named(name, &node.id), Msg::User(0), Duration::from_millis(100)) {
+        Ok(Msg::User(id)) => Some(id),
+        Ok(msg) => panic!("Unexpected msg {:?}", msg),
+        Err(_) => None
+    }
+}
+
+/// Wait for `name` to resolve to the process with the given id on `node`
+fn wait_for_name(node: &Node<u64>, name: &str, id: u64) {
+    assert!(wait_for(time::Duration::seconds(10), || ask(node, name) == Some(id)));
+}
+
+#[test]
+fn local_and_global_names() {
+    let (node1, mut handles) = start_node::<u64>(1);
+    let (node2, handles2) = start_node::<u64>(2);
+    handles.extend(handles2);
+
+    // Local names are only resolved on their own node
+    let local = spawn(&node1, "local", 1);
+    node1.register_name("local", &local).unwrap();
+    assert_eq!(ask(&node1, "local"), Some(1));
+    assert_eq!(ask(&node2, "local"), None);
+
+    // The same global name registered on both sides of a partition resolves to the lowest pid on
+    // both nodes once they connect
+    let winner = spawn(&node1, "global", 2);
+    let loser = spawn(&node2, "global", 3);
+    node1.register_global_name("global", &winner).unwrap();
+    node2.register_global_name("global", &loser).unwrap();
+    wait_for_name(&node1, "global", 2);
+    wait_for_name(&node2, "global", 3);
+    node1.join(&node2.id).unwrap();
+    wait_for_name(&node2, "global", 2);
+    assert_eq!(ask(&node1, "global"), Some(2));
+
+    // Names registered locally shadow global names
+    node2.register_name("global", &loser).unwrap();
+    assert_eq!(ask(&node2, "global"), Some(3));
+    node2.unregister_name("global").unwrap();
+    assert_eq!(ask(&node2, "global"), Some(2));
+
+    // Unregistering a global name removes it from every node
+    node2.unregister_global_name("global").unwrap();
+    assert!(wait_for(time::Duration::seconds(10), || ask(&node1, "global").is_none()));
+
+    // Local names are removed when their process stops
+    node1.stop(&local).unwrap();
+    assert_eq!(ask(&node1, "local"), None);
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
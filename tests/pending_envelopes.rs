@@ -0,0 +1,94 @@
+//! Test delivery of envelopes to remote nodes that are not connected yet
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod utils;
+
+use amy::Poller;
+
+use rabble::{
+    Pid,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    UndeliverableReason
+};
+
+use utils::{
+    start_node,
+    test_pid,
+    pid,
+    recv
+};
+
+/// A process that echoes back all user messages
+struct Echo {
+    pid: Pid
+}
+
+impl Process<u64> for Echo {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              from: Pid,
+              correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::User(n) = msg {
+            output.push(Envelope::new(from, self.pid.clone(), Msg::User(n), correlation_id));
+        }
+    }
+}
+
+#[test]
+fn send_before_connection_established() {
+    let (node1, mut handles) = start_node::<u64>(1);
+    let (node2, handles2) = start_node::<u64>(2);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let test_pid = test_pid(node1.id.clone());
+    node1.register_service(&test_pid, &test_tx).unwrap();
+
+    let echo = pid("echo", &node2.id);
+    node2.spawn(&echo, Box::new(Echo {pid: echo.clone()})).unwrap();
+
+    // Sending to a node that isn't a member fails immediately
+    let c_id = CorrelationId::request(test_pid.clone(), 0, 0);
+    node1.send(Envelope::new(echo.clone(), test_pid.clone(), Msg::User(0), Some(c_id.clone())))
+        .unwrap();
+    let envelope = recv(&mut poller, &test_rx);
+    assert_eq!(envelope.msg, Msg::Undeliverable(UndeliverableReason::NotAMember));
+    assert_eq!(envelope.from, echo);
+    assert_eq!(envelope.correlation_id, Some(c_id));
+
+    // An envelope sent right after joining is queued until the connection is established. The
+    // status request ensures the join was handled before the envelope reaches the cluster server.
+    node1.join(&node2.id).unwrap();
+    node1.cluster_status(CorrelationId::pid(test_pid.clone())).unwrap();
+    match recv(&mut poller, &test_rx).msg {
+        Msg::ClusterStatus(status) => assert!(status.members.contains(&node2.id)),
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+    let c_id = CorrelationId::request(test_pid.clone(), 0, 1);
+    node1.send(Envelope::new(echo.clone(), test_pid.clone(), Msg::User(1), Some(c_id.clone())))
+        .unwrap();
+    let envelope = recv(&mut poller, &test_rx);
+    assert_eq!(envelope.msg, Msg::User(1));
+    assert_eq!(envelope.correlation_id, Some(c_id));
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
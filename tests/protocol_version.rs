@@ -0,0 +1,114 @@
+//! Test protocol version negotiation between nodes
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use amy::Poller;
+
+use rabble::{
+    Node,
+    Envelope,
+    Msg,
+    RabbleConfigBuilder,
+    PROTOCOL_VERSION
+};
+
+use utils::{
+    wait_for,
+    start_node_with_config,
+    test_pid,
+    cluster_server,
+    recv,
+    established,
+    counter
+};
+
+fn start_node(n: usize, versions: (u32, u32)) -> (Node<()>, Vec<thread::JoinHandle<()>>) {
+    let config = RabbleConfigBuilder::new()
+        .protocol_versions(versions.0, versions.1)
+        .build().unwrap();
+    start_node_with_config(n, config)
+}
+
+#[test]
+fn incompatible_versions() {
+    let (node1, mut handles) = start_node(1, (PROTOCOL_VERSION, PROTOCOL_VERSION));
+    let (node2, handles2) = start_node(2, (PROTOCOL_VERSION, PROTOCOL_VERSION));
+    // A node that only speaks the first version, which node1 no longer accepts
+    let (node3, handles3) = start_node(3, (1, 1));
+    handles.extend(handles2);
+    handles.extend(handles3);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node3.register_service(&test_pid(node3.id.clone()), &test_tx).unwrap();
+
+    // Nodes supporting the same version connect
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        established(&node1, &mut poller, &test_rx) == 1
+    }));
+
+    // The connecting node refuses a connection to a node without a common version
+    node1.join(&node3.id).unwrap();
+    let server = cluster_server(&node1.id);
+    assert!(wait_for(time::Duration::seconds(5), || {
+        counter(&node1, &mut poller, &test_rx, server.clone(), "version_mismatches") > 0
+    }));
+    assert_eq!(established(&node1, &mut poller, &test_rx), 1);
+    assert_eq!(established(&node3, &mut poller, &test_rx), 0);
+
+    for node in vec![node1, node2, node3] {
+        node.shutdown();
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn mixed_versions() {
+    let (node4, mut handles) = start_node(4, (1, PROTOCOL_VERSION));
+    // A node running the first version of the protocol
+    let (node5, handles5) = start_node(5, (1, 1));
+    handles.extend(handles5);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node4.register_service(&test_pid(node4.id.clone()), &test_tx).unwrap();
+    node5.register_service(&test_pid(node5.id.clone()), &test_tx).unwrap();
+
+    // The nodes connect using version 1, and never send each other messages from later versions
+    node4.join(&node5.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        established(&node4, &mut poller, &test_rx) == 1 &&
+            established(&node5, &mut poller, &test_rx) == 1
+    }));
+    let (to, from) = (test_pid(node5.id.clone()), test_pid(node4.id.clone()));
+    node4.send(Envelope::new(to.clone(), from, Msg::User(()), None)).unwrap();
+    assert_eq!(recv(&mut poller, &test_rx).to, to);
+
+    // Neither node was disconnected by a message it couldn't decode
+    assert_eq!(established(&node4, &mut poller, &test_rx), 1);
+    assert_eq!(established(&node5, &mut poller, &test_rx), 1);
+
+    for node in vec![node4, node5] {
+        node.shutdown();
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+}
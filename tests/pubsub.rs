@@ -0,0 +1,134 @@
+//! Test publishing messages to subscribers of a topic across the cluster
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use rabble::{
+    Pid,
+    Node,
+    Process,
+    Envelope,
+    Msg,
+    Metric,
+    CorrelationId
+};
+
+use utils::{
+    wait_for,
+    start_node,
+    cluster_server
+};
+
+/// A process that subscribes to a topic when it starts, and reports every published message it
+/// receives
+struct Subscriber {
+    pid: Pid,
+    topic: String,
+    tx: mpsc::Sender<(String, String, u64)>
+}
+
+impl Process<u64> for Subscriber {
+    fn init(&mut self, _executor_pid: Pid) -> Vec<Envelope<u64>> {
+        let broker = Pid::broker(&self.pid.node);
+        vec![Envelope::new(broker, self.pid.clone(), Msg::Subscribe(self.topic.clone()), None)]
+    }
+
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::Publish(topic, n) = msg {
+            self.tx.send((self.pid.name.clone(), topic, n)).unwrap();
+        }
+    }
+}
+
+fn subscribe(node: &Node<u64>, name: &str, topic: &str, tx: &mpsc::Sender<(String, String, u64)>) {
+    let pid = Pid {name: name.to_string(), group: None, node: node.id.clone()};
+    let subscriber = Subscriber {pid: pid.clone(), topic: topic.to_string(), tx: tx.clone()};
+    node.spawn(&pid, Box::new(subscriber)).unwrap();
+}
+
+fn publish(node: &Node<u64>, topic: &str, n: u64) {
+    let from = Pid {name: "publisher".to_string(), group: None, node: node.id.clone()};
+    let msg = Msg::Publish(topic.to_string(), n);
+    node.send(Envelope::new(Pid::broker(&node.id), from, msg, None)).unwrap();
+}
+
+fn forwarded_publishes(node: &Node<u64>) -> u64 {
+    match node.ask(&cluster_server(&node.id), Msg::GetMetrics, Duration::from_secs(5)).unwrap() {
+        Msg::Metrics(metrics) => {
+            match metrics.into_iter().find(|&(ref name, _)| name == "forwarded_publishes") {
+                Some((_, Metric::Counter(count))) => count,
+                metric => panic!("Unexpected metric {:?}", metric)
+            }
+        },
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+}
+
+#[test]
+fn publish_across_nodes() {
+    let (node1, mut handles) = start_node::<u64>(1);
+    let (node2, handles2) = start_node::<u64>(2);
+    handles.extend(handles2);
+
+    let (tx, rx) = mpsc::channel();
+    subscribe(&node1, "a", "events", &tx);
+    subscribe(&node2, "b", "events", &tx);
+    subscribe(&node2, "c", "events", &tx);
+    subscribe(&node2, "d", "other", &tx);
+    node1.join(&node2.id).unwrap();
+
+    // Publish until node2's subscriptions have reached node1
+    assert!(wait_for(time::Duration::seconds(10), || {
+        publish(&node1, "events", 0);
+        rx.try_iter().any(|(name, _, _)| name == "b")
+    }));
+    thread::sleep(Duration::from_millis(100));
+    let _: Vec<_> = rx.try_iter().collect();
+
+    // Each publish is delivered to every subscriber, but only crosses the wire once
+    let forwarded = forwarded_publishes(&node1);
+    for n in 1..11 {
+        publish(&node1, "events", n);
+    }
+    let mut received: Vec<(String, String, u64)> = (0..30).map(|_| {
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    }).collect();
+    received.sort();
+    let mut expected: Vec<(String, String, u64)> = ["a", "b", "c"].iter().flat_map(|name| {
+        (1..11).map(move |n| (name.to_string(), "events".to_string(), n))
+    }).collect();
+    expected.sort();
+    assert_eq!(received, expected);
+    assert_eq!(forwarded_publishes(&node1), forwarded + 10);
+
+    // Publishes to topics without subscribers go nowhere
+    publish(&node2, "nobody", 1);
+    thread::sleep(Duration::from_millis(100));
+    assert!(rx.try_recv().is_err());
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+//! Test mapping keys to the nodes that own them on the cluster ring
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use amy::Poller;
+
+use rabble::{
+    NodeId,
+    Node,
+    Envelope,
+    Msg,
+    RabbleConfigBuilder
+};
+
+use utils::{
+    wait_for,
+    start_node_with_config,
+    test_pid,
+    cluster_server,
+    recv
+};
+
+fn start_node(n: usize) -> (Node<()>, Vec<thread::JoinHandle<()>>) {
+    let config = RabbleConfigBuilder::new().replication_factor(2).build().unwrap();
+    start_node_with_config(n, config)
+}
+
+/// Wait until the ring of every node contains exactly the given nodes
+fn wait_for_ring(nodes: &[&Node<()>], expected: &[NodeId]) {
+    assert!(wait_for(time::Duration::seconds(10), || {
+        nodes.iter().all(|node| node.ring().members() == expected)
+    }));
+}
+
+#[test]
+fn ring() {
+    let (node1, mut handles) = start_node(1);
+    let (node2, handles2) = start_node(2);
+    let (node3, handles3) = start_node(3);
+    handles.extend(handles2);
+    handles.extend(handles3);
+
+    // A lone node owns every key
+    assert_eq!(node1.owners(&"key"), vec![node1.id.clone()]);
+    assert_eq!(node1.owner(&"key"), Some(node1.id.clone()));
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let test_pid = test_pid(node1.id.clone());
+    node1.register_service(&test_pid, &test_tx).unwrap();
+
+    // Wait for the cluster server to reply to a request sent after the subscription, so that we
+    // know the subscription is in place before joining.
+    let server = cluster_server(&node1.id);
+    node1.send(Envelope::new(server.clone(), test_pid.clone(), Msg::SubscribeRingChanges, None))
+        .unwrap();
+    node1.send(Envelope::new(server.clone(), test_pid.clone(), Msg::GetMetrics, None)).unwrap();
+    match recv(&mut poller, &test_rx).msg {
+        Msg::Metrics(_) => (),
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+
+    node1.join(&node2.id).unwrap();
+    assert_eq!(recv(&mut poller, &test_rx).msg,
+               Msg::RingChanged(vec![node1.id.clone(), node2.id.clone()]));
+    node1.join(&node3.id).unwrap();
+    let all = vec![node1.id.clone(), node2.id.clone(), node3.id.clone()];
+    assert_eq!(recv(&mut poller, &test_rx).msg, Msg::RingChanged(all.clone()));
+    wait_for_ring(&[&node1, &node2, &node3], &all);
+
+    // Every node agrees on the owners of each key, and keys are spread over all nodes
+    let mut primaries = Vec::new();
+    for key in 0..100 {
+        let owners = node1.owners(&key);
+        assert_eq!(owners.len(), 2);
+        assert_ne!(owners[0], owners[1]);
+        assert_eq!(node2.owners(&key), owners);
+        assert_eq!(node3.owners(&key), owners);
+        if !primaries.contains(&owners[0]) {
+            primaries.push(owners[0].clone());
+        }
+    }
+    assert_eq!(primaries.len(), 3);
+
+    // Removing a node only moves the keys it owned
+    let before: Vec<Vec<NodeId>> = (0..100).map(|key| node1.owners(&key)).collect();
+    node1.leave(&node3.id).unwrap();
+    assert_eq!(recv(&mut poller, &test_rx).msg,
+               Msg::RingChanged(vec![node1.id.clone(), node2.id.clone()]));
+    for key in 0..100 {
+        if before[key][0] != node3.id {
+            assert_eq!(node1.owner(&key).as_ref(), Some(&before[key][0]));
+        }
+    }
+
+    for node in vec![node1, node2, node3] {
+        node.shutdown();
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+}
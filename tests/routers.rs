@@ -0,0 +1,141 @@
+//! Test forwarding messages to a pool of routees with the built-in routers
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use rabble::{
+    Pid,
+    Node,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    Router,
+    RoutingStrategy
+};
+
+use utils::{
+    wait_for,
+    start_node,
+    pid
+};
+
+/// A process that reports every user message it receives
+struct Routee {
+    pid: Pid,
+    tx: mpsc::Sender<(String, u64)>
+}
+
+impl Process<u64> for Routee {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::User(n) = msg {
+            self.tx.send((self.pid.name.clone(), n)).unwrap();
+        }
+    }
+}
+
+fn spawn_routees(node: &Node<u64>, names: &[&str], tx: &mpsc::Sender<(String, u64)>) -> Vec<Pid> {
+    names.iter().map(|name| {
+        let pid = pid(name, &node.id);
+        node.spawn(&pid, Box::new(Routee {pid: pid.clone(), tx: tx.clone()})).unwrap();
+        pid
+    }).collect()
+}
+
+fn send(node: &Node<u64>, to: &Pid, n: u64) {
+    node.send(Envelope::new(to.clone(), pid("sender", &node.id), Msg::User(n), None)).unwrap();
+}
+
+/// Send the messages in `ns` to the router and return the name of the routee of each
+fn route(node: &Node<u64>, router: &Pid, ns: &[u64], rx: &mpsc::Receiver<(String, u64)>)
+    -> Vec<String>
+{
+    ns.iter().map(|&n| {
+        send(node, router, n);
+        let (name, received) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received, n);
+        name
+    }).collect()
+}
+
+fn resize(node: &Node<u64>, router: &Pid, msg: Msg<u64>) {
+    node.send(Envelope::new(router.clone(), pid("sender", &node.id), msg, None)).unwrap();
+}
+
+fn routees(node: &Node<u64>, router: &Pid) -> Vec<Pid> {
+    match node.ask(router, Msg::GetRoutees, Duration::from_secs(5)).unwrap() {
+        Msg::Routees(routees) => routees,
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+}
+
+#[test]
+fn routers() {
+    let (node, handles) = start_node::<u64>(1);
+    let (tx, rx) = mpsc::channel();
+    let pool = spawn_routees(&node, &["a", "b", "c"], &tx);
+
+    // Round robin cycles through the pool in order
+    let round_robin = pid("round_robin", &node.id);
+    let router = Router::new(round_robin.clone(), RoutingStrategy::RoundRobin, pool.clone());
+    node.spawn(&round_robin, Box::new(router)).unwrap();
+    assert_eq!(route(&node, &round_robin, &[1, 2, 3, 4], &rx), vec!["a", "b", "c", "a"]);
+
+    // Random routers deliver every message to some routee
+    let random = pid("random", &node.id);
+    let router = Router::new(random.clone(), RoutingStrategy::Random, pool.clone());
+    node.spawn(&random, Box::new(router)).unwrap();
+    for name in route(&node, &random, &[1, 2, 3, 4, 5], &rx) {
+        assert!(["a", "b", "c"].contains(&name.as_str()));
+    }
+
+    // Idle routees with equally empty mailboxes are picked in turn
+    let smallest = pid("smallest", &node.id);
+    let strategy = RoutingStrategy::SmallestMailbox(node.mailbox_sizes());
+    node.spawn(&smallest, Box::new(Router::new(smallest.clone(), strategy, pool.clone()))).unwrap();
+    assert_eq!(route(&node, &smallest, &[1, 2, 3], &rx), vec!["a", "b", "c"]);
+
+    // Messages with the same key always reach the same routee
+    let hash = pid("hash", &node.id);
+    let strategy = RoutingStrategy::ConsistentHash(Box::new(|n: &u64| n % 10));
+    node.spawn(&hash, Box::new(Router::new(hash.clone(), strategy, pool.clone()))).unwrap();
+    let first = route(&node, &hash, &[1, 2, 3, 4, 5], &rx);
+    assert_eq!(route(&node, &hash, &[11, 12, 13, 14, 15], &rx), first);
+
+    // Routees are added and removed at runtime
+    let extra = spawn_routees(&node, &["d"], &tx);
+    resize(&node, &round_robin, Msg::AddRoutee(extra[0].clone()));
+    resize(&node, &round_robin, Msg::RemoveRoutee(pool[0].clone()));
+    let expected = vec![pool[1].clone(), pool[2].clone(), extra[0].clone()];
+    assert_eq!(routees(&node, &round_robin), expected);
+
+    // Routees that stop are removed from the pool
+    node.stop(&pool[1]).unwrap();
+    assert!(wait_for(time::Duration::seconds(10), || {
+        !routees(&node, &round_robin).contains(&pool[1])
+    }));
+    let mut names = route(&node, &round_robin, &[1, 2, 3, 4], &rx);
+    names.sort();
+    assert_eq!(names, vec!["c", "c", "d", "d"]);
+
+    node.shutdown_and_wait(handles, Duration::from_secs(5));
+}
@@ -0,0 +1,237 @@
+//! Test placing entities on the nodes owning their keys, and handing them off when the cluster
+//! ring changes
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use rabble::{
+    Pid,
+    Node,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId
+};
+
+use utils::{
+    wait_for,
+    start_node,
+    pid
+};
+
+/// An entity that counts the user messages it receives, and reports the count along with the node
+/// it runs on. Entities report their key when they are handed off.
+struct Counter {
+    pid: Pid,
+    count: u64,
+    tx: mpsc::Sender<(String, String, u64)>,
+    handoff_tx: mpsc::Sender<String>
+}
+
+impl Process<u64> for Counter {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<u64>>)
+    {
+        match msg {
+            Msg::User(_) => {
+                self.count += 1;
+                let report = (self.pid.node.name.clone(), self.pid.name.clone(), self.count);
+                self.tx.send(report).unwrap();
+            },
+            Msg::Shutdown => self.handoff_tx.send(self.pid.name.clone()).unwrap(),
+            _ => ()
+        }
+    }
+}
+
+/// An entity that takes a while to save its state when it's handed off. It reports the user
+/// messages it handles and the end of the save on a single channel, along with the node it runs on.
+struct SlowSaver {
+    pid: Pid,
+    tx: mpsc::Sender<(String, String, &'static str)>
+}
+
+impl Process<u64> for SlowSaver {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<u64>>)
+    {
+        let event = match msg {
+            Msg::User(_) => "handled",
+            Msg::Shutdown => {
+                thread::sleep(Duration::from_millis(200));
+                "saved"
+            },
+            _ => return
+        };
+        self.tx.send((self.pid.node.name.clone(), self.pid.name.clone(), event)).unwrap();
+    }
+}
+
+fn start_counter_node(n: usize,
+                      tx: &mpsc::Sender<(String, String, u64)>,
+                      handoff_tx: &mpsc::Sender<String>)
+    -> (Node<u64>, Vec<thread::JoinHandle<()>>)
+{
+    let (node, handles) = start_node(n);
+    let (tx, handoff_tx) = (tx.clone(), handoff_tx.clone());
+    node.register_entity_type("counter", Box::new(move |pid: &Pid| {
+        Box::new(Counter {
+            pid: pid.clone(),
+            count: 0,
+            tx: tx.clone(),
+            handoff_tx: handoff_tx.clone()
+        }) as Box<Process<u64>>
+    })).unwrap();
+    (node, handles)
+}
+
+fn send(node: &Node<u64>, key: &str) {
+    send_to(node, "counter", key)
+}
+
+fn send_to(node: &Node<u64>, entity_type: &str, key: &str) {
+    let from = pid("sender", &node.id);
+    let to = Pid::entity(entity_type, key, &node.id);
+    node.send(Envelope::new(to, from, Msg::User(0), None)).unwrap();
+}
+
+fn recv(rx: &mpsc::Receiver<(String, String, u64)>) -> (String, String, u64) {
+    rx.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+fn owner(node: &Node<u64>, key: &str) -> String {
+    node.owner(&("counter", key)).unwrap().name
+}
+
+#[test]
+fn sharding() {
+    let (tx, rx) = mpsc::channel();
+    let (handoff_tx, handoff_rx) = mpsc::channel();
+    let (node1, mut handles) = start_counter_node(1, &tx, &handoff_tx);
+    let (node2, handles2) = start_counter_node(2, &tx, &handoff_tx);
+    handles.extend(handles2);
+    let keys: Vec<String> = (0..20).map(|i| format!("key{}", i)).collect();
+
+    // Entities are started on demand on the only node, and keep their state between messages
+    for key in &keys {
+        send(&node1, key);
+        assert_eq!(recv(&rx), ("node1".to_string(), key.clone(), 1));
+    }
+    send(&node1, &keys[0]);
+    assert_eq!(recv(&rx), ("node1".to_string(), keys[0].clone(), 2));
+
+    // Entities whose keys move to node2 are handed off
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(10), || {
+        node1.ring().members().len() == 2 && node2.ring().members().len() == 2
+    }));
+    let moved: Vec<String> = keys.iter()
+        .filter(|key| owner(&node1, key) == "node2")
+        .cloned()
+        .collect();
+    assert!(!moved.is_empty());
+    let mut handed_off: Vec<String> = moved.iter().map(|_| {
+        handoff_rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    }).collect();
+    handed_off.sort();
+    let mut expected = moved.clone();
+    expected.sort();
+    assert_eq!(handed_off, expected);
+
+    // Messages sent to any node reach the entity on the owning node. Moved entities start again
+    // with fresh state.
+    for key in &keys {
+        let node = owner(&node1, key);
+        assert_eq!(owner(&node2, key), node);
+        let count = if moved.contains(key) { 0 } else if *key == keys[0] { 2 } else { 1 };
+        send(&node1, key);
+        assert_eq!(recv(&rx), (node.clone(), key.clone(), count + 1));
+        send(&node2, key);
+        assert_eq!(recv(&rx), (node.clone(), key.clone(), count + 2));
+    }
+    assert!(handoff_rx.try_recv().is_err());
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn handoff_waits_for_the_old_instance() {
+    let (tx, rx) = mpsc::channel();
+    let (node3, mut handles) = start_node(3);
+    let (node4, handles4) = start_node(4);
+    handles.extend(handles4);
+    for node in &[&node3, &node4] {
+        let tx = tx.clone();
+        node.register_entity_type("saver", Box::new(move |pid: &Pid| {
+            Box::new(SlowSaver {pid: pid.clone(), tx: tx.clone()}) as Box<Process<u64>>
+        })).unwrap();
+    }
+    let keys: Vec<String> = (0..20).map(|i| format!("key{}", i)).collect();
+    for key in &keys {
+        send_to(&node3, "saver", key);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+                   ("node3".to_string(), key.clone(), "handled"));
+    }
+
+    // Envelopes sent while entities are being handed off only reach the new owner once the old
+    // instance has saved its state
+    node3.join(&node4.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(10), || {
+        node3.ring().members().len() == 2 && node4.ring().members().len() == 2
+    }));
+    let moved: Vec<String> = keys.iter()
+        .filter(|key| node3.owner(&("saver", key)).unwrap().name == "node4")
+        .cloned()
+        .collect();
+    assert!(!moved.is_empty());
+    for key in &moved {
+        send_to(&node3, "saver", key);
+    }
+    // An envelope routed before node3's executor saw the ring change is still handled by the old
+    // instance
+    let mut saved = Vec::new();
+    let mut handled = 0;
+    while handled < moved.len() {
+        let (node, key, event) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        match (&node[..], event) {
+            ("node3", "saved") => saved.push(key),
+            ("node3", "handled") => handled += 1,
+            ("node4", "handled") => {
+                assert!(saved.contains(&key));
+                handled += 1;
+            },
+            _ => ()
+        }
+    }
+
+    node3.shutdown();
+    node4.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
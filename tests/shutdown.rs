@@ -0,0 +1,90 @@
+//! Test the orderly shutdown of a node
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod utils;
+
+use std::sync::mpsc;
+use std::time::Duration;
+use amy::Poller;
+
+use rabble::{
+    Pid,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    ShutdownReport
+};
+
+use utils::{
+    start_node,
+    test_pid,
+    pid
+};
+
+/// A process that reports when it is shut down, and then sends an envelope to itself that is
+/// never delivered
+struct Notify {
+    pid: Pid,
+    tx: mpsc::Sender<Pid>
+}
+
+impl Process<()> for Notify {
+    fn handle(&mut self,
+              msg: Msg<()>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<()>>)
+    {
+        if let Msg::Shutdown = msg {
+            self.tx.send(self.pid.clone()).unwrap();
+            output.push(Envelope::new(self.pid.clone(), self.pid.clone(), Msg::User(()), None));
+        }
+    }
+}
+
+#[test]
+fn shutdown_and_wait() {
+    let (node, handles) = start_node::<()>(1);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    let test_pid = test_pid(node.id.clone());
+    node.register_service(&test_pid, &test_tx).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let pids = vec![pid("process1", &node.id), pid("process2", &node.id)];
+    for pid in &pids {
+        node.spawn(pid, Box::new(Notify {pid: pid.clone(), tx: tx.clone()})).unwrap();
+    }
+
+    let report = node.shutdown_and_wait(handles, Duration::from_secs(5));
+    assert_eq!(report, ShutdownReport {
+        processes: 2,
+        services: 1,
+        dropped_envelopes: 2,
+        dropped_remote_envelopes: 0,
+        unflushed_connections: 0,
+        timed_out: false
+    });
+
+    // Every process and service was notified
+    let mut notified: Vec<Pid> = rx.try_iter().collect();
+    notified.sort_by_key(|pid| pid.name.clone());
+    assert_eq!(notified, pids);
+    assert_eq!(poller.wait(5000).unwrap().len(), 1);
+    assert_eq!(test_rx.try_recv().unwrap().msg, Msg::Shutdown);
+
+    // The stopped node no longer accepts envelopes
+    let envelope = Envelope::new(pids[0].clone(), test_pid.clone(), Msg::User(()), None);
+    assert!(node.send(envelope).is_err());
+}
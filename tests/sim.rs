@@ -0,0 +1,135 @@
+//! Test the deterministic cluster simulator
+
+extern crate rabble;
+
+use rabble::{
+    Pid,
+    NodeId,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId
+};
+use rabble::sim::{Simulator, SimConfig};
+
+const PING_INTERVAL: usize = 100;
+
+/// A process that sends an increasing counter to the ponger on every timeout
+struct Pinger {
+    pid: Pid,
+    ponger: Pid,
+    executor_pid: Option<Pid>,
+    count: u64
+}
+
+impl Pinger {
+    fn start_timer(&self, output: &mut Vec<Envelope<u64>>) {
+        let executor_pid = self.executor_pid.clone().unwrap();
+        output.push(Envelope::new(executor_pid, self.pid.clone(),
+                                  Msg::StartTimer(PING_INTERVAL), None));
+    }
+}
+
+impl Process<u64> for Pinger {
+    fn init(&mut self, executor_pid: Pid) -> Vec<Envelope<u64>> {
+        self.executor_pid = Some(executor_pid);
+        let mut output = Vec::new();
+        self.start_timer(&mut output);
+        output
+    }
+
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::Timeout = msg {
+            output.push(Envelope::new(self.ponger.clone(), self.pid.clone(),
+                                      Msg::User(self.count), None));
+            self.count += 1;
+            self.start_timer(output);
+        }
+    }
+}
+
+/// A process that forwards all user messages to the test client
+struct Ponger {
+    pid: Pid,
+    client: Pid
+}
+
+impl Process<u64> for Ponger {
+    fn handle(&mut self,
+              msg: Msg<u64>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<u64>>)
+    {
+        if let Msg::User(n) = msg {
+            output.push(Envelope::new(self.client.clone(), self.pid.clone(), Msg::User(n), None));
+        }
+    }
+}
+
+fn pid(name: &str, n: usize) -> Pid {
+    Pid {
+        name: name.to_string(),
+        group: None,
+        node: NodeId {name: format!("node{}", n), addr: format!("127.0.0.1:1100{}", n)}
+    }
+}
+
+/// Run the simulation for 10 seconds of virtual time and return the counters received by the client
+fn run(config: SimConfig) -> Vec<u64> {
+    let mut sim = Simulator::new(config).unwrap();
+    let (pinger, ponger, client) = (pid("pinger", 1), pid("ponger", 2), pid("client", 2));
+    sim.spawn(ponger.clone(), Box::new(Ponger {pid: ponger.clone(), client: client}));
+    sim.spawn(pinger.clone(), Box::new(Pinger {
+        pid: pinger,
+        ponger: ponger,
+        executor_pid: None,
+        count: 0
+    }));
+    sim.run_for(10_000);
+    assert_eq!(sim.now(), 10_000);
+    sim.take_output().into_iter().map(|envelope| {
+        match envelope.msg {
+            Msg::User(n) => n,
+            msg => panic!("Unexpected msg {:?}", msg)
+        }
+    }).collect()
+}
+
+#[test]
+fn reliable_network() {
+    // The last ping is still in flight when the simulation stops
+    assert_eq!(run(SimConfig::new(1)), (0..99).collect::<Vec<_>>());
+}
+
+#[test]
+fn faulty_network_is_deterministic() {
+    let config = SimConfig {
+        seed: 42,
+        drop_probability: 0.2,
+        duplicate_probability: 0.2,
+        min_delay_ms: 1,
+        max_delay_ms: 300
+    };
+    let received = run(config.clone());
+    assert_ne!(received, (0..99).collect::<Vec<_>>());
+    assert_eq!(received, run(config));
+}
+
+#[test]
+fn invalid_delay_range() {
+    let mut config = SimConfig::new(1);
+    config.min_delay_ms = 10;
+    config.max_delay_ms = 5;
+    assert!(Simulator::<u64>::new(config).is_err());
+
+    // Delays up to the largest representable time don't overflow. No pong arrives in time.
+    let mut config = SimConfig::new(1);
+    config.max_delay_ms = u64::max_value();
+    assert!(run(config).is_empty());
+}
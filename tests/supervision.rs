@@ -0,0 +1,231 @@
+//! Test restarting of panicking processes by supervisors
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+mod utils;
+
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rabble::{
+    Pid,
+    Process,
+    Envelope,
+    Msg,
+    CorrelationId,
+    SupervisorSpec,
+    ChildSpec,
+    RestartStrategy
+};
+
+use utils::{
+    start_node,
+    pid
+};
+
+/// A process that signals the test when it is started and panics when it receives a user msg
+struct Crasher {
+    pid: Pid,
+
+    /// Don't do this in production!!!
+    /// This is only here to signal to the test that the process was started.
+    tx: mpsc::Sender<Pid>
+}
+
+impl Process<()> for Crasher {
+    fn init(&mut self, _executor_pid: Pid) -> Vec<Envelope<()>> {
+        self.tx.send(self.pid.clone()).unwrap();
+        Vec::new()
+    }
+
+    fn handle(&mut self,
+              _msg: Msg<()>,
+              _from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              _output: &mut Vec<Envelope<()>>)
+    {
+        panic!("Crasher received a message");
+    }
+}
+
+fn crasher(pid: Pid, tx: &mpsc::Sender<Pid>) -> ChildSpec<()> {
+    let tx = tx.clone();
+    ChildSpec::worker(pid.clone(), Box::new(move || {
+        Box::new(Crasher {pid: pid.clone(), tx: tx.clone()}) as Box<Process<()>>
+    }))
+}
+
+/// A crasher whose factory panics the `fail_on`th time it is called
+fn failing_crasher(pid: Pid, tx: &mpsc::Sender<Pid>, fail_on: usize) -> ChildSpec<()> {
+    let tx = tx.clone();
+    let calls = Arc::new(AtomicUsize::new(0));
+    ChildSpec::worker(pid.clone(), Box::new(move || {
+        if calls.fetch_add(1, Ordering::SeqCst) + 1 == fail_on {
+            panic!("Failed to create crasher");
+        }
+        Box::new(Crasher {pid: pid.clone(), tx: tx.clone()}) as Box<Process<()>>
+    }))
+}
+
+fn crash(node: &rabble::Node<()>, pid: &Pid) {
+    let from = pid.clone();
+    node.send(Envelope::new(pid.clone(), from, Msg::User(()), None)).unwrap();
+}
+
+fn recv_starts(rx: &mpsc::Receiver<Pid>, n: usize) -> Vec<Pid> {
+    (0..n).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect()
+}
+
+#[test]
+fn one_for_one() {
+    let (node, handles) = start_node::<()>(1);
+    let (tx, rx) = mpsc::channel();
+
+    let pid1 = pid("crasher1", &node.id);
+    let pid2 = pid("crasher2", &node.id);
+    let spec = SupervisorSpec::new(RestartStrategy::OneForOne)
+        .child(crasher(pid1.clone(), &tx))
+        .child(crasher(pid2.clone(), &tx));
+    node.spawn_supervisor(&pid("supervisor", &node.id), spec).unwrap();
+    assert_eq!(recv_starts(&rx, 2), vec![pid1.clone(), pid2.clone()]);
+
+    // Only the crashed process gets restarted
+    crash(&node, &pid2);
+    assert_eq!(recv_starts(&rx, 1), vec![pid2.clone()]);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn rest_for_one_and_max_restart_intensity() {
+    let (node, handles) = start_node::<()>(2);
+    let (tx, rx) = mpsc::channel();
+
+    let pid1 = pid("crasher1", &node.id);
+    let pid2 = pid("crasher2", &node.id);
+    let pid3 = pid("crasher3", &node.id);
+    let spec = SupervisorSpec::new(RestartStrategy::RestForOne)
+        .max_restarts(1, 60)
+        .child(crasher(pid1.clone(), &tx))
+        .child(crasher(pid2.clone(), &tx))
+        .child(crasher(pid3.clone(), &tx));
+    node.spawn_supervisor(&pid("supervisor", &node.id), spec).unwrap();
+    assert_eq!(recv_starts(&rx, 3), vec![pid1.clone(), pid2.clone(), pid3.clone()]);
+
+    // The crashed process and all processes started after it get restarted
+    crash(&node, &pid2);
+    assert_eq!(recv_starts(&rx, 2), vec![pid2.clone(), pid3.clone()]);
+
+    // The second crash exceeds the restart intensity so nothing gets restarted
+    crash(&node, &pid1);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn one_for_all() {
+    let (node, handles) = start_node::<()>(3);
+    let (tx, rx) = mpsc::channel();
+
+    let pid1 = pid("crasher1", &node.id);
+    let pid2 = pid("crasher2", &node.id);
+    let pid3 = pid("crasher3", &node.id);
+    let spec = SupervisorSpec::new(RestartStrategy::OneForAll)
+        .child(crasher(pid1.clone(), &tx))
+        .child(crasher(pid2.clone(), &tx))
+        .child(crasher(pid3.clone(), &tx));
+    node.spawn_supervisor(&pid("supervisor", &node.id), spec).unwrap();
+    assert_eq!(recv_starts(&rx, 3), vec![pid1.clone(), pid2.clone(), pid3.clone()]);
+
+    // All processes get restarted, in start order
+    crash(&node, &pid2);
+    assert_eq!(recv_starts(&rx, 3), vec![pid1.clone(), pid2.clone(), pid3.clone()]);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn one_for_all_child_fails_to_start() {
+    let (node, handles) = start_node::<()>(4);
+    let (tx, rx) = mpsc::channel();
+
+    let pid1 = pid("crasher1", &node.id);
+    let pid2 = pid("crasher2", &node.id);
+    let pid3 = pid("crasher3", &node.id);
+    let spec = SupervisorSpec::new(RestartStrategy::OneForAll)
+        .child(crasher(pid1.clone(), &tx))
+        .child(failing_crasher(pid2.clone(), &tx, 2))
+        .child(crasher(pid3.clone(), &tx));
+    node.spawn_supervisor(&pid("supervisor", &node.id), spec).unwrap();
+    assert_eq!(recv_starts(&rx, 3), vec![pid1.clone(), pid2.clone(), pid3.clone()]);
+
+    // The restart stops at the child that fails to start, and the failed start restarts all
+    // processes again. Every process is started exactly once by the second restart.
+    crash(&node, &pid1);
+    assert_eq!(recv_starts(&rx, 4), vec![pid1.clone(), pid1.clone(), pid2.clone(), pid3.clone()]);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn escalation_to_parent_supervisor() {
+    let (node, handles) = start_node::<()>(5);
+    let (tx, rx) = mpsc::channel();
+
+    let top = pid("top", &node.id);
+    let nested = pid("nested", &node.id);
+    let pid1 = pid("crasher1", &node.id);
+    let pid2 = pid("crasher2", &node.id);
+    let nested_spec = SupervisorSpec::new(RestartStrategy::OneForOne)
+        .max_restarts(0, 60)
+        .child(crasher(pid2.clone(), &tx));
+    let spec = SupervisorSpec::new(RestartStrategy::OneForOne)
+        .max_restarts(1, 60)
+        .child(crasher(pid1.clone(), &tx))
+        .child(ChildSpec::supervisor(nested.clone(), nested_spec));
+    node.spawn_supervisor(&top, spec).unwrap();
+    assert_eq!(recv_starts(&rx, 2), vec![pid1.clone(), pid2.clone()]);
+
+    // The nested supervisor can't restart its child, so it fails and is restarted by its parent
+    crash(&node, &pid2);
+    assert_eq!(recv_starts(&rx, 1), vec![pid2.clone()]);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    // The second failure of the nested supervisor exceeds the restart intensity of its parent, so
+    // the whole tree is stopped
+    crash(&node, &pid2);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    crash(&node, &pid1);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    node.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! Test connecting nodes over mutually authenticated TLS
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::thread;
+use amy::Poller;
+
+use rabble::errors::ErrorKind;
+use rabble::{
+    Node,
+    Envelope,
+    Msg,
+    RabbleConfigBuilder,
+    TlsConfig,
+    TcpTransport
+};
+
+use utils::{
+    wait_for,
+    start_node_with_config,
+    test_pid,
+    recv,
+    established
+};
+
+fn tls_config(name: &str) -> TlsConfig {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+    TlsConfig {
+        ca_path: format!("{}/ca.pem", dir),
+        cert_path: format!("{}/{}.pem", dir, name),
+        key_path: format!("{}/{}.key", dir, name)
+    }
+}
+
+fn start_node(n: usize) -> (Node<()>, Vec<thread::JoinHandle<()>>) {
+    let config = RabbleConfigBuilder::new().tls(tls_config(&format!("node{}", n))).build().unwrap();
+    start_node_with_config(n, config)
+}
+
+#[test]
+fn tls_cluster() {
+    let (node1, mut handles) = start_node(1);
+    let (node2, handles2) = start_node(2);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+
+    // The nodes complete the TLS handshake and the cluster handshake
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        established(&node1, &mut poller, &test_rx) == 1 &&
+            established(&node2, &mut poller, &test_rx) == 1
+    }));
+
+    // Envelopes flow over the encrypted connection
+    let (to, from) = (test_pid(node2.id.clone()), test_pid(node1.id.clone()));
+    node1.send(Envelope::new(to, from, Msg::User(()), None)).unwrap();
+    let envelope = recv(&mut poller, &test_rx);
+    assert_eq!(envelope.from, test_pid(node1.id.clone()));
+    assert_eq!(envelope.to, test_pid(node2.id.clone()));
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn invalid_tls_config() {
+    let mut tls = tls_config("node1");
+    tls.cert_path = "/nonexistent/node3.pem".to_string();
+    let config = RabbleConfigBuilder::new().tls(tls).build().unwrap();
+    match TcpTransport::new(&config) {
+        Err(e) => assert_matches!(*e.kind(), ErrorKind::ConfigError(_)),
+        Ok(_) => panic!("Created a transport with a missing certificate")
+    }
+}
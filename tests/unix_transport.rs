@@ -0,0 +1,65 @@
+//! Test forming a cluster over Unix domain sockets
+
+extern crate amy;
+extern crate rabble;
+
+#[macro_use]
+extern crate assert_matches;
+extern crate serde;
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate time;
+
+mod utils;
+
+use std::env;
+use std::thread;
+use amy::Poller;
+
+use rabble::{
+    NodeId,
+    Node,
+    RabbleConfig,
+    UnixTransport,
+    MsgpackCodec
+};
+
+use utils::{
+    wait_for,
+    test_pid,
+    established
+};
+
+fn start_node(n: usize) -> (Node<()>, Vec<thread::JoinHandle<()>>) {
+    let path = env::temp_dir().join(format!("rabble-unix-transport-node{}.sock", n));
+    let node_id = NodeId {name: format!("node{}", n), addr: path.to_str().unwrap().to_string()};
+    let config = RabbleConfig::default();
+    let transport = Box::new(UnixTransport::new(&config));
+    rabble::rouse_with(node_id, config, transport, Box::new(MsgpackCodec), None).unwrap()
+}
+
+#[test]
+fn join_over_unix_sockets() {
+    let (node1, mut handles) = start_node(1);
+    let (node2, handles2) = start_node(2);
+    handles.extend(handles2);
+
+    let mut poller = Poller::new().unwrap();
+    let (test_tx, test_rx) = poller.get_registrar().unwrap().channel().unwrap();
+    node1.register_service(&test_pid(node1.id.clone()), &test_tx).unwrap();
+    node2.register_service(&test_pid(node2.id.clone()), &test_tx).unwrap();
+
+    node1.join(&node2.id).unwrap();
+    assert!(wait_for(time::Duration::seconds(5), || {
+        established(&node1, &mut poller, &test_rx) == 1 &&
+            established(&node2, &mut poller, &test_rx) == 1
+    }));
+
+    node1.shutdown();
+    node2.shutdown();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
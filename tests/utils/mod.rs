@@ -8,6 +8,7 @@ pub mod replica;
 pub mod api_server;
 pub mod messages;
 
+use std::fmt::Debug;
 use std::thread::{self, JoinHandle};
 use std::net::TcpStream;
 use amy::{Poller, Receiver, Sender};
@@ -21,7 +22,12 @@ use rabble::{
     Envelope,
     Pid,
     CorrelationId,
-    Msg
+    Msg,
+    Metric,
+    ClusterStatus,
+    RabbleConfig,
+    TcpTransport,
+    MsgpackCodec
 };
 use rabble::serialize::{Serialize, MsgpackSerializer};
 
@@ -78,14 +84,36 @@ pub fn send(sock: &mut TcpStream,
 }
 
 
+#[allow(dead_code)] // Not used in all tests
+pub fn node_id(n: usize) -> NodeId {
+    NodeId {
+        name: format!("node{}", n),
+        addr: format!("127.0.0.1:{}", 11000 + n)
+    }
+}
+
 #[allow(dead_code)] // Not used in all tests
 pub fn create_node_ids(n: usize) -> Vec<NodeId> {
-    (1..n + 1).map(|n| {
-        NodeId {
-            name: format!("node{}", n),
-            addr: format!("127.0.0.1:1100{}", n)
-        }
-    }).collect()
+    (1..n + 1).map(node_id).collect()
+}
+
+/// Start the node returned by `node_id(n)` with the default config
+#[allow(dead_code)] // Not used in all tests
+pub fn start_node<'de, T>(n: usize) -> (Node<T>, Vec<JoinHandle<()>>)
+    where T: ::serde::Serialize + ::serde::Deserialize<'de> + Send + Clone + Debug + 'static
+{
+    rabble::rouse(node_id(n), None)
+}
+
+/// Start the node returned by `node_id(n)` with the given config, connecting to other nodes over
+/// TCP
+#[allow(dead_code)] // Not used in all tests
+pub fn start_node_with_config<'de, T>(n: usize,
+                                      config: RabbleConfig) -> (Node<T>, Vec<JoinHandle<()>>)
+    where T: ::serde::Serialize + ::serde::Deserialize<'de> + Send + Clone + Debug + 'static
+{
+    let transport = Box::new(TcpTransport::new(&config).unwrap());
+    rabble::rouse_with(node_id(n), config, transport, Box::new(MsgpackCodec), None).unwrap()
 }
 
 #[allow(dead_code)] // Not used in all tests
@@ -113,6 +141,108 @@ pub fn test_pid(node_id: NodeId) -> Pid {
     }
 }
 
+#[allow(dead_code)] // Not used in all tests
+pub fn pid(name: &str, node_id: &NodeId) -> Pid {
+    Pid {
+        name: name.to_string(),
+        group: None,
+        node: node_id.clone()
+    }
+}
+
+#[allow(dead_code)] // Not used in all tests
+pub fn cluster_server(node_id: &NodeId) -> Pid {
+    Pid {
+        name: "cluster_server".to_string(),
+        group: Some("rabble".to_string()),
+        node: node_id.clone()
+    }
+}
+
+/// Return the next envelope sent to a service registered with the poller
+///
+/// Fails the test if no envelope arrives within 5 seconds
+#[allow(dead_code)] // Not used in all tests
+pub fn recv<T>(poller: &mut Poller, rx: &Receiver<Envelope<T>>) -> Envelope<T> {
+    loop {
+        if let Ok(envelope) = rx.try_recv() {
+            return envelope;
+        }
+        assert!(poller.wait(5000).unwrap().len() > 0, "Timed out waiting for a message");
+    }
+}
+
+/// Get the status of the cluster server of a node. The test must be registered as a service on
+/// the node with its `test_pid`.
+#[allow(dead_code)] // Not used in all tests
+pub fn cluster_status<'de, T>(node: &Node<T>,
+                              poller: &mut Poller,
+                              rx: &Receiver<Envelope<T>>) -> ClusterStatus
+    where T: ::serde::Serialize + ::serde::Deserialize<'de> + Debug + Clone
+{
+    node.cluster_status(CorrelationId::pid(test_pid(node.id.clone()))).unwrap();
+    match recv(poller, rx).msg {
+        Msg::ClusterStatus(status) => status,
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+}
+
+/// Return the number of peers a node has established connections with
+#[allow(dead_code)] // Not used in all tests
+pub fn established<'de, T>(node: &Node<T>,
+                           poller: &mut Poller,
+                           rx: &Receiver<Envelope<T>>) -> usize
+    where T: ::serde::Serialize + ::serde::Deserialize<'de> + Debug + Clone
+{
+    cluster_status(node, poller, rx).established.len()
+}
+
+/// Get a counter from the metrics of a process or service on a node. The test must be registered
+/// as a service on the node with its `test_pid`.
+#[allow(dead_code)] // Not used in all tests
+pub fn counter<'de, T>(node: &Node<T>,
+                       poller: &mut Poller,
+                       rx: &Receiver<Envelope<T>>,
+                       pid: Pid,
+                       name: &str) -> u64
+    where T: ::serde::Serialize + ::serde::Deserialize<'de> + Debug + Clone
+{
+    let from = test_pid(node.id.clone());
+    node.send(Envelope::new(pid, from, Msg::GetMetrics, None)).unwrap();
+    match recv(poller, rx).msg {
+        Msg::Metrics(metrics) => {
+            match metrics.into_iter().find(|&(ref metric, _)| metric == name) {
+                Some((_, Metric::Counter(count))) => count,
+                metric => panic!("Unexpected metric {:?}", metric)
+            }
+        },
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+}
+
+/// Get a gauge from the metrics of a process or service on a node. The test must be registered as
+/// a service on the node with its `test_pid`.
+#[allow(dead_code)] // Not used in all tests
+pub fn gauge<'de, T>(node: &Node<T>,
+                     poller: &mut Poller,
+                     rx: &Receiver<Envelope<T>>,
+                     pid: Pid,
+                     name: &str) -> i64
+    where T: ::serde::Serialize + ::serde::Deserialize<'de> + Debug + Clone
+{
+    let from = test_pid(node.id.clone());
+    node.send(Envelope::new(pid, from, Msg::GetMetrics, None)).unwrap();
+    match recv(poller, rx).msg {
+        Msg::Metrics(metrics) => {
+            match metrics.into_iter().find(|&(ref metric, _)| metric == name) {
+                Some((_, Metric::Gauge(value))) => value,
+                metric => panic!("Unexpected metric {:?}", metric)
+            }
+        },
+        msg => panic!("Unexpected msg {:?}", msg)
+    }
+}
+
 #[allow(dead_code)] // Not used in all tests
 pub fn register_test_as_service(poller: &mut Poller,
                                 nodes: &Vec<CrNode>,